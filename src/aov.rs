@@ -0,0 +1,334 @@
+//! Arbitrary output variables (AOVs): per-pixel data about the first
+//! surface seen through each pixel, gathered alongside the beauty image
+//! for compositing and denoising.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+use crate::{
+    color::Color,
+    framebuffer::{Framebuffer, Tile},
+    util::PFM,
+    vec3::Vec3,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    Normal,     // Shading normal at the first hit
+    Position,   // World position of the first hit
+    Depth,      // Distance of the first hit along the camera's view direction
+    Albedo,     // Surface color of the first hit
+    MaterialId, // Index of the first hit material, in order of appearance
+    ObjectId,   // Id of the first hit object
+    Motion,     // Screen-space motion of the first hit over the shutter interval
+    Lights,     // Contribution of each light, one layer per light
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Normal,
+        Aov::Position,
+        Aov::Depth,
+        Aov::Albedo,
+        Aov::MaterialId,
+        Aov::ObjectId,
+        Aov::Motion,
+        Aov::Lights,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Motion => "motion",
+            Aov::Lights => "lights",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    /// Ids are not meaningful when averaged, so id layers keep the value
+    /// of the first sample of each pixel.
+    fn is_id(&self) -> bool {
+        matches!(self, Aov::MaterialId | Aov::ObjectId)
+    }
+}
+
+/// Data gathered by one camera sample for the AOVs.
+///
+/// Camera rays that escape the scene leave everything zero, except for
+/// the albedo, which takes the background color.
+#[derive(Clone, Debug)]
+pub struct AovSample {
+    pub normal: Vec3,
+    pub position: Vec3,
+    pub depth: f64,
+    pub albedo: Color,
+    pub material: Option<usize>, // Address of the material, see `AovBuffers::add`
+    pub object_id: usize,
+    pub motion: Vec3, // Screen-space motion in pixels; z is unused
+    pub lights: Vec<Color>,
+}
+
+impl AovSample {
+    pub fn new(light_count: usize) -> Self {
+        Self {
+            normal: Vec3(0., 0., 0.),
+            position: Vec3(0., 0., 0.),
+            depth: 0.,
+            albedo: Color::new(0., 0., 0.),
+            material: None,
+            object_id: 0,
+            motion: Vec3(0., 0., 0.),
+            lights: vec![Color::new(0., 0., 0.); light_count],
+        }
+    }
+}
+
+/// Accumulation buffers for a set of AOVs.
+pub struct AovBuffers {
+    width: u32,
+    height: u32,
+    layers: Vec<(Aov, Framebuffer)>,
+    ids: Vec<(Aov, Vec<Option<u32>>)>,
+    lights: Vec<(String, Framebuffer)>,
+    material_ids: HashMap<usize, u32>,
+}
+
+impl AovBuffers {
+    /// Creates buffers for the requested AOVs; `light_names` names the
+    /// layers of `Aov::Lights`, in the order of `AovSample::lights`.
+    pub fn new(width: u32, height: u32, aovs: &[Aov], light_names: &[String]) -> Self {
+        let mut buffers = Self {
+            width,
+            height,
+            layers: Vec::new(),
+            ids: Vec::new(),
+            lights: Vec::new(),
+            material_ids: HashMap::new(),
+        };
+        for &aov in aovs {
+            if aov == Aov::Lights {
+                buffers.lights = light_names
+                    .iter()
+                    .map(|name| (name.clone(), Framebuffer::new(width, height)))
+                    .collect();
+            } else if aov.is_id() {
+                buffers
+                    .ids
+                    .push((aov, vec![None; (width * height) as usize]));
+            } else {
+                buffers.layers.push((aov, Framebuffer::new(width, height)));
+            }
+        }
+        buffers
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+
+    /// Adds one sample to pixel i, j of every buffer.
+    ///
+    /// Materials are identified by address and numbered from 1 in the
+    /// order they are first seen.
+    pub fn add(&mut self, i: u32, j: u32, sample: &AovSample) {
+        for (aov, fb) in &mut self.layers {
+            let value = match aov {
+                Aov::Normal => sample.normal,
+                Aov::Position => sample.position,
+                Aov::Depth => Vec3(sample.depth, sample.depth, sample.depth),
+                Aov::Albedo => sample.albedo,
+                Aov::Motion => sample.motion,
+                _ => unreachable!(),
+            };
+            fb.add(i, j, value);
+        }
+
+        for (aov, ids) in &mut self.ids {
+            let id = match aov {
+                Aov::MaterialId => match sample.material {
+                    Some(address) => {
+                        let next = self.material_ids.len() as u32 + 1;
+                        *self.material_ids.entry(address).or_insert(next)
+                    }
+                    None => 0,
+                },
+                Aov::ObjectId => sample.object_id as u32,
+                _ => unreachable!(),
+            };
+            ids[(j * self.width + i) as usize].get_or_insert(id);
+        }
+
+        for ((_, fb), &light) in self.lights.iter_mut().zip(&sample.lights) {
+            fb.add(i, j, light);
+        }
+    }
+
+    /// Marks the end of n passes over every pixel.
+    pub fn end_passes(&mut self, n: u32) {
+        for (_, fb) in &mut self.layers {
+            fb.end_passes(n);
+        }
+        for (_, fb) in &mut self.lights {
+            fb.end_passes(n);
+        }
+    }
+
+    /// Returns the averaged value of a non-id AOV at pixel i, j.
+    pub fn pixel(&self, aov: Aov, i: u32, j: u32) -> Option<Color> {
+        self.layers
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, fb)| fb.pixel(i, j))
+    }
+
+    /// Returns the id stored for pixel i, j by an id AOV.
+    pub fn id(&self, aov: Aov, i: u32, j: u32) -> Option<u32> {
+        self.ids
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, ids)| ids[(j * self.width + i) as usize].unwrap_or(0))
+    }
+
+    /// Returns the buffers of the pixels covered by tile.
+    pub fn crop(&self, tile: &Tile) -> Self {
+        let crop_ids = |ids: &Vec<Option<u32>>| {
+            (tile.y..tile.y + tile.height)
+                .flat_map(|j| (tile.x..tile.x + tile.width).map(move |i| (i, j)))
+                .map(|(i, j)| ids[(j * self.width + i) as usize])
+                .collect()
+        };
+        Self {
+            width: tile.width,
+            height: tile.height,
+            layers: self
+                .layers
+                .iter()
+                .map(|(aov, fb)| (*aov, fb.crop(tile)))
+                .collect(),
+            ids: self
+                .ids
+                .iter()
+                .map(|(aov, ids)| (*aov, crop_ids(ids)))
+                .collect(),
+            lights: self
+                .lights
+                .iter()
+                .map(|(name, fb)| (name.clone(), fb.crop(tile)))
+                .collect(),
+            material_ids: self.material_ids.clone(),
+        }
+    }
+
+    /// Writes each of aovs to its own PFM file named `<prefix>.<aov>.pfm`,
+    /// or `<prefix>.light_<name>.pfm` for the light layers. Buffers kept
+    /// for other uses, such as guiding the denoiser, are left out.
+    pub fn write_files(&self, prefix: &str, aovs: &[Aov]) -> io::Result<()> {
+        for (aov, fb) in self.layers.iter().filter(|(aov, _)| aovs.contains(aov)) {
+            write_pfm(&format!("{}.{}.pfm", prefix, aov.name()), fb.to_pfm())?;
+        }
+        for (aov, ids) in self.ids.iter().filter(|(aov, _)| aovs.contains(aov)) {
+            let mut pfm = PFM::new(self.width, self.height);
+            for id in ids {
+                let id = id.unwrap_or(0) as f64;
+                pfm.push(Color::new(id, id, id));
+            }
+            write_pfm(&format!("{}.{}.pfm", prefix, aov.name()), pfm)?;
+        }
+        for (name, fb) in self.lights.iter().filter(|_| aovs.contains(&Aov::Lights)) {
+            write_pfm(&format!("{}.light_{}.pfm", prefix, name), fb.to_pfm())?;
+        }
+        Ok(())
+    }
+}
+
+fn write_pfm(path: &str, pfm: PFM) -> io::Result<()> {
+    pfm.write_to_buffer(&mut BufWriter::new(File::create(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for aov in Aov::ALL {
+            assert_eq!(Aov::from_name(aov.name()), Some(aov));
+        }
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+
+    #[test]
+    fn material_ids_are_numbered_in_order_of_appearance() {
+        let mut buffers = AovBuffers::new(3, 1, &[Aov::MaterialId], &[]);
+        let mut sample = AovSample::new(0);
+        for (i, address) in [(0, 0xbeef), (1, 0xcafe), (2, 0xbeef)] {
+            sample.material = Some(address);
+            buffers.add(i, 0, &sample);
+        }
+        assert_eq!(buffers.id(Aov::MaterialId, 0, 0), Some(1));
+        assert_eq!(buffers.id(Aov::MaterialId, 1, 0), Some(2));
+        assert_eq!(buffers.id(Aov::MaterialId, 2, 0), Some(1));
+        assert_eq!(buffers.id(Aov::ObjectId, 0, 0), None);
+    }
+
+    #[test]
+    fn ids_keep_first_sample() {
+        let mut buffers = AovBuffers::new(1, 1, &[Aov::ObjectId, Aov::Depth], &[]);
+        let mut sample = AovSample::new(0);
+        for (id, depth) in [(4, 1.), (5, 3.)] {
+            sample.object_id = id;
+            sample.depth = depth;
+            buffers.add(0, 0, &sample);
+            buffers.end_passes(1);
+        }
+        assert_eq!(buffers.id(Aov::ObjectId, 0, 0), Some(4));
+        assert_eq!(buffers.pixel(Aov::Depth, 0, 0), Some(Vec3(2., 2., 2.)));
+    }
+
+    #[test]
+    fn lights_get_a_layer_each() {
+        let names = vec![String::from("sky"), String::from("sun")];
+        let mut buffers = AovBuffers::new(1, 1, &[Aov::Lights], &names);
+        let mut sample = AovSample::new(buffers.light_count());
+        sample.lights[1] = Color::new(1., 2., 3.);
+        buffers.add(0, 0, &sample);
+        buffers.end_passes(1);
+        assert_eq!(buffers.lights[0].1.pixel(0, 0), Color::new(0., 0., 0.));
+        assert_eq!(buffers.lights[1].1.pixel(0, 0), Color::new(1., 2., 3.));
+    }
+
+    #[test]
+    fn write_files_writes_only_requested_aovs() {
+        let dir = std::env::temp_dir().join(format!("tracerust-aov-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("out").to_str().unwrap().to_string();
+        let names = vec![String::from("sky")];
+        let buffers = AovBuffers::new(1, 1, &[Aov::Depth, Aov::Normal, Aov::Lights], &names);
+        buffers.write_files(&prefix, &[Aov::Depth]).unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, ["out.depth.pfm"]);
+    }
+}
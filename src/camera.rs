@@ -1,56 +1,168 @@
 use crate::{
+    aov::{Aov, AovBuffers, AovSample},
     color::Color,
-    hittable::HittableList,
-    ray::Ray,
-    util::{Interval, PPM, degrees_to_radians},
+    framebuffer::{Framebuffer, Tile},
+    hittable::{HitRecord, HittableList},
+    light::Light,
+    lightsampler::LightSelection,
+    ray::{Ray, RayDifferential},
+    sampling::power_heuristic,
+    spectrum::{PathThroughput, SampledWavelengths},
+    util::{Interval, degrees_to_radians},
     vec3::Vec3,
 };
 use rand;
 use std::io::BufWriter;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub struct Camera {
-    pub aspect_ratio: f64,      // Ratio of image width over height
-    pub image_width: u32,       // Rendered image width in pixel count
-    pub samples_per_pixel: u32, // Count of random samples for each pixel
-    pub max_depth: u32,         // Maximum number of ray bounces into scene
-    pub vfov: f64,              // vertical field of view in degrees
-    pub lookfrom: Vec3,         // Point camera is looking from
-    pub lookat: Vec3,           // Point camera is looking at
-    pub vup: Vec3,              // Camera-relative "up" direction
-
-    image_height: u32,        // Rendered image height
-    pixel_samples_scale: f64, // Color scale factor for a sum of pixel samples
-    center: Vec3,             // Camera center
-    pixel00_loc: Vec3,        // Location of pixel 0, 0
-    pixel_delta_u: Vec3,      // Offset to pixel to the right
-    pixel_delta_v: Vec3,      // Offset to pixel below
-    defocus_angle: f64,       // Variation angle of rays through each pixel
-    defocus_disk_u: Vec3,     // Defocus disk horizontal radius
-    defocus_disk_v: Vec3,     // Defocus disk vertical radius
+    pub aspect_ratio: f64,             // Ratio of image width over height
+    pub image_width: u32,              // Rendered image width in pixel count
+    pub samples_per_pixel: u32,        // Count of random samples for each pixel
+    pub max_depth: u32,                // Maximum number of ray bounces into scene
+    pub vfov: f64,                     // vertical field of view in degrees
+    pub lookfrom: Vec3,                // Point camera is looking from
+    pub lookat: Vec3,                  // Point camera is looking at
+    pub vup: Vec3,                     // Camera-relative "up" direction
+    pub time_budget: Option<Duration>, // Render passes until this wall-clock time is used up
+    pub crop: Option<Tile>,            // Only render this region of the image
+    pub crop_full_size: bool,          // Output a crop at full size with the rest black
+    pub aovs: Vec<Aov>,                // Output variables to gather alongside the color
+    pub spectral: bool,                // Trace hero wavelengths instead of RGB
+
+    image_height: u32,    // Rendered image height
+    center: Vec3,         // Camera center
+    w: Vec3,              // Camera frame basis vector pointing opposite the view direction
+    focus_distance: f64,  // Distance from camera center to plane of perfect focus
+    pixel00_loc: Vec3,    // Location of pixel 0, 0
+    pixel_delta_u: Vec3,  // Offset to pixel to the right
+    pixel_delta_v: Vec3,  // Offset to pixel below
+    defocus_angle: f64,   // Variation angle of rays through each pixel
+    defocus_disk_u: Vec3, // Defocus disk horizontal radius
+    defocus_disk_v: Vec3, // Defocus disk vertical radius
 }
 
 impl Camera {
     pub fn render(&self, world: HittableList) {
-        let width = self.image_width;
-        let height = self.image_height;
-        let mut ppm = PPM::new(width, height);
-        for j in 0..height {
-            eprint!("\rScanlines remaining: {} ", (height - j));
-            for i in 0..width {
-                let mut pixel_color = Color::new(0., 0., 0.);
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pixel_color += Self::color_ray(&r, self.max_depth, &world)
+        let fb = self.render_progressive(&world);
+        fb.to_ppm()
+            .write_to_buffer(&mut BufWriter::new(std::io::stdout()));
+    }
+
+    /// Rendered image height
+    pub fn image_height(&self) -> u32 {
+        self.image_height
+    }
+
+    /// Region of the image rendered each pass: the crop window if set,
+    /// otherwise the whole image.
+    pub fn region(&self) -> Tile {
+        match self.crop {
+            Some(crop) => {
+                assert!(crop.area() > 0, "crop window {:?} is empty", crop);
+                assert!(
+                    crop.fits(self.image_width, self.image_height),
+                    "crop window {:?} lies outside the image",
+                    crop
+                );
+                crop
+            }
+            None => Tile::new(0, 0, self.image_width, self.image_height),
+        }
+    }
+
+    /// Accumulates progressive passes of one sample per pixel into a new
+    /// framebuffer.
+    ///
+    /// Without a time budget exactly `samples_per_pixel` passes are
+    /// rendered. With a budget, passes are added for as long as the next
+    /// one is expected to finish within it, ignoring `samples_per_pixel`.
+    /// At least one pass is always rendered.
+    pub fn render_progressive(&self, world: &HittableList) -> Framebuffer {
+        let (width, height) = match self.crop_full_size {
+            true => (self.image_width, self.image_height),
+            false => (self.region().width, self.region().height),
+        };
+        let mut fb = Framebuffer::new(width, height);
+        if !self.aovs.is_empty() {
+            let aovs = AovBuffers::new(width, height, &self.aovs, &self.light_names(world));
+            fb = fb.with_aovs(aovs);
+        }
+        let start = Instant::now();
+
+        match self.time_budget {
+            None => {
+                for pass in 0..self.samples_per_pixel {
+                    eprint!("\rPass {}/{} ", pass + 1, self.samples_per_pixel);
+                    self.render_pass(world, &mut fb);
+                }
+            }
+            Some(budget) => loop {
+                eprint!("\rPass {} ", fb.passes() + 1);
+                self.render_pass(world, &mut fb);
+
+                let elapsed = start.elapsed();
+                let per_pass = elapsed / fb.passes();
+                if elapsed + per_pass > budget {
+                    break;
                 }
+            },
+        }
+        eprint!(
+            "\rDone. {} samples per pixel in {:.2}s.\n",
+            fb.passes(),
+            start.elapsed().as_secs_f64()
+        );
+
+        fb
+    }
+
+    /// Adds one sample for every pixel of the rendered region to the
+    /// framebuffer, which must be sized for the output image, and to its
+    /// AOV buffers if it has any.
+    pub fn render_pass(&self, world: &HittableList, fb: &mut Framebuffer) {
+        let region = self.region();
+        let (dx, dy) = match self.crop_full_size {
+            true => (0, 0),
+            false => (region.x, region.y),
+        };
 
-                ppm.push(self.pixel_samples_scale * pixel_color);
+        let light_count = fb.aovs().map_or(0, |aovs| aovs.light_count());
+        for j in region.y..region.y + region.height {
+            for i in region.x..region.x + region.width {
+                let r = self.get_ray(i, j);
+                if fb.aovs().is_some() {
+                    let mut sample = AovSample::new(light_count);
+                    fb.add(i - dx, j - dy, self.trace(&r, world, Some(&mut sample)));
+                    fb.add_aovs(i - dx, j - dy, &sample);
+                } else {
+                    fb.add(i - dx, j - dy, self.color_ray(&r, world));
+                }
             }
         }
-        eprint!("\rDone.                   \n");
+        fb.end_pass();
+    }
 
-        ppm.write_to_buffer(&mut BufWriter::new(std::io::stdout()));
+    /// Adds `samples` samples per pixel of an arbitrary tile of the image
+    /// to the running sums in buf, which holds one color per tile pixel
+    /// laid out row by row. Rays are generated for the pixels' positions
+    /// in the full image, so tiles rendered separately fit together.
+    pub fn render_tile(&self, world: &HittableList, tile: &Tile, samples: u32, buf: &mut [Color]) {
+        assert!(tile.fits(self.image_width, self.image_height));
+        assert_eq!(buf.len(), tile.area());
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                let pixel = &mut buf[(j * tile.width + i) as usize];
+                for _ in 0..samples {
+                    let r = self.get_ray(tile.x + i, tile.y + j);
+                    *pixel += self.color_ray(&r, world);
+                }
+            }
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         aspect_ratio: f64,
         image_width: u32,
@@ -91,7 +203,6 @@ impl Camera {
         // Calculate the location of the upper left pixel.
         let viewport_upper_left = center - focus_distance * w - viewport_u / 2.0 - viewport_v / 2.0;
         let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
-        let pixel_samples_scale = 1. / samples_per_pixel as f64;
 
         // Calculate the camera defocus disk basis vectors.
         let defocus_radius = focus_distance * degrees_to_radians(defocus_angle / 2.).tan();
@@ -109,38 +220,293 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            pixel_samples_scale,
             lookfrom,
             lookat,
             vup,
+            time_budget: None,
+            crop: None,
+            crop_full_size: false,
+            aovs: Vec::new(),
+            spectral: false,
+            w,
+            focus_distance,
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
         }
     }
 
-    pub fn color_ray(ray: &Ray, depth: u32, world: &HittableList) -> Color {
-        if depth <= 0 {
-            return Color::new(0., 0., 0.);
-        }
-        match world.hit(ray, &Interval::new(0.001, f64::INFINITY)) {
-            Some(rec) => match rec.mat.scatter(ray, &rec) {
+    /// Radiance arriving along a ray that escapes the scene.
+    fn background(ray: &Ray) -> Color {
+        let u = ray.dir().unit();
+        let a = 0.5 * (u.y() + 1.0);
+        (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+    }
+
+    /// Radiance arriving along a ray that escapes world: its environment
+    /// if it has one, else the default sky. A ray scattered with density
+    /// scatter_pdf shares the environment's light with its samples.
+    fn sky(ray: &Ray, world: &HittableList, scatter_pdf: Option<f64>) -> Color {
+        let Some(environment) = &world.environment else {
+            return Self::background(ray);
+        };
+        let weight = match scatter_pdf {
+            Some(pdf) => power_heuristic(pdf, environment.pdf(ray.origin(), ray.dir().unit())),
+            None => 1.,
+        };
+        weight * environment.radiance(ray.dir())
+    }
+
+    /// Names of the lights whose contributions `trace` reports separately:
+    /// the sky, then the world's light groups numbered from 1.
+    pub fn light_names(&self, world: &HittableList) -> Vec<String> {
+        let lights = (1..=world.light_group_count()).map(|k| k.to_string());
+        std::iter::once(String::from("sky")).chain(lights).collect()
+    }
+
+    pub fn color_ray(&self, ray: &Ray, world: &HittableList) -> Color {
+        self.trace(ray, world, None)
+    }
+
+    /// Follows a path from ray through the scene for at most `max_depth`
+    /// bounces and returns the radiance carried back along it. If sample is
+    /// given, it receives the first hit's AOV data and the contribution of
+    /// each light.
+    fn trace(&self, ray: &Ray, world: &HittableList, mut sample: Option<&mut AovSample>) -> Color {
+        let mut ray = ray.clone();
+        let mut throughput = PathThroughput::new(ray.wavelength());
+        let mut radiance = Color::new(0., 0., 0.);
+        let mut scatter_pdf = None; // Density of the last scattered direction
+        let mut last_hit = None; // Point and normal the ray last scattered from
+
+        for bounce in 0..self.max_depth {
+            let Some(mut rec) = Self::hit_through_media(&mut ray, world, &mut throughput) else {
+                let sky = throughput.radiance(Self::sky(&ray, world, scatter_pdf));
+                if let Some(sample) = sample {
+                    if bounce == 0 {
+                        sample.albedo = sky;
+                    }
+                    if let Some(light) = sample.lights.get_mut(0) {
+                        *light += sky;
+                    }
+                }
+                return radiance + sky;
+            };
+            rec.set_footprint(&ray);
+
+            if bounce == 0
+                && let Some(sample) = sample.as_deref_mut()
+            {
+                self.record_first_hit(&ray, &rec, sample);
+            }
+
+            // Light the surface emits, shared with the samples of it as a
+            // light if it is one.
+            let emitted = rec.mat.emitted(&ray, &rec);
+            if emitted != Color::new(0., 0., 0.) {
+                let light = world.emitter(rec.object_id);
+                let weight = match (scatter_pdf, light, last_hit) {
+                    (Some(pdf), Some(k), Some((point, normal))) => {
+                        power_heuristic(pdf, Self::light_pdf(world, k, point, normal, ray.dir()))
+                    }
+                    _ => 1.,
+                };
+                let emitted = throughput.radiance(weight * emitted);
+                radiance += emitted;
+                if let Some(k) = light
+                    && let Some(light) = sample
+                        .as_deref_mut()
+                        .and_then(|s| s.lights.get_mut(world.light_group(k) + 1))
+                {
+                    *light += emitted;
+                }
+            }
+
+            // Every light, or one picked by the world's light selection and
+            // weighted by the probability of picking it.
+            let (every, picked) = match world.light_selection() {
+                LightSelection::All => (0..world.lights().len(), None),
+                _ => (0..0, world.light_sampler().sample(rec.point, rec.normal)),
+            };
+            for (k, prob) in every.map(|k| (k, 1.)).chain(picked) {
+                let light = world.lights()[k].as_ref();
+                let direct =
+                    throughput.radiance(Self::direct_light(&ray, &rec, world, light, prob));
+                radiance += direct;
+                if let Some(light) = sample
+                    .as_deref_mut()
+                    .and_then(|s| s.lights.get_mut(world.light_group(k) + 1))
+                {
+                    *light += direct;
+                }
+            }
+            if let Some(environment) = &world.environment {
+                let direct = throughput.radiance(Self::direct_light(
+                    &ray,
+                    &rec,
+                    world,
+                    environment.as_ref(),
+                    1.,
+                ));
+                radiance += direct;
+                if let Some(light) = sample.as_deref_mut().and_then(|s| s.lights.get_mut(0)) {
+                    *light += direct;
+                }
+            }
+
+            match rec.mat.scatter(&ray, &rec) {
                 Some(scatres) => {
-                    return scatres.attenuation
-                        * Camera::color_ray(&scatres.scattered, depth - 1, world);
+                    throughput.attenuate(scatres.attenuation);
+                    scatter_pdf = scatres.pdf;
+                    last_hit = Some((rec.point, rec.normal));
+
+                    // Rays transmitted through a surface enter or leave its medium.
+                    let mut media = ray.media().clone();
+                    if let Some(medium) = rec.mat.medium() {
+                        // Only the hero wavelength follows the direction a
+                        // dispersive surface picked for it.
+                        if medium.refraction_index.is_dispersive() {
+                            throughput.terminate_secondary();
+                        }
+                        if scatres.scattered.dir().dot(&rec.normal) < 0. {
+                            media.cross(medium, rec.front_face);
+                        }
+                    }
+                    ray = scatres
+                        .scattered
+                        .with_media(media)
+                        .with_wavelength(ray.wavelength());
                 }
-                None => return Color::new(0., 0., 0.),
-            },
-            None => {
-                let u = ray.dir().unit();
-                let a = 0.5 * (u.y() + 1.0);
-                (1.0 - a) * Color::new(1.0, 1.0, 1.0) + a * Color::new(0.5, 0.7, 1.0)
+                None => break,
             }
         }
+
+        radiance
+    }
+
+    /// Light from light reflected at the hit back along ray: one sample of
+    /// it, if no shadow ray finds it blocked, over the probability prob of
+    /// having picked the light. Specular surfaces reflect none, as only
+    /// exactly one direction reaches them. Lights that scattered rays can
+    /// also reach share their samples' weight with them by the power
+    /// heuristic.
+    fn direct_light(
+        ray: &Ray,
+        rec: &HitRecord,
+        world: &HittableList,
+        light: &dyn Light,
+        prob: f64,
+    ) -> Color {
+        let black = Color::new(0., 0., 0.);
+        let Some(sample) = light.sample(rec.point) else {
+            return black;
+        };
+        let f = rec.mat.eval(ray, rec, sample.wi);
+        if f == black {
+            return black;
+        }
+        let shadow = Ray::new(rec.point, sample.wi, ray.time());
+        if world.is_occluded(
+            &shadow,
+            &Interval::new(0.001, sample.distance * (1. - 1e-9)),
+        ) {
+            return black;
+        }
+
+        // Unblocked light on the far side of a transmitting surface
+        // travels through the medium behind it.
+        let mut media = ray.media().clone();
+        if let Some(medium) = rec.mat.medium()
+            && sample.wi.dot(&rec.normal) < 0.
+        {
+            media.cross(medium, rec.front_face);
+        }
+        let transmittance = match media.current() {
+            Some(medium) if sample.distance.is_finite() => medium.transmittance(sample.distance),
+            _ => Color::new(1., 1., 1.),
+        };
+        let weight = match sample.pdf {
+            Some(pdf) => power_heuristic(prob * pdf, rec.mat.pdf(ray, rec, sample.wi)),
+            None => 1.,
+        };
+        weight * f * transmittance * sample.radiance / prob
+    }
+
+    /// Density with which direct lighting at point, on a surface with the
+    /// given normal, samples direction wi from the world's light k,
+    /// including the chance of picking that light.
+    fn light_pdf(world: &HittableList, k: usize, point: Vec3, normal: Vec3, wi: Vec3) -> f64 {
+        let pmf = match world.light_selection() {
+            LightSelection::All => 1.,
+            _ => world.light_sampler().pmf(point, normal, k),
+        };
+        pmf * world.lights()[k].pdf(point, wi)
+    }
+
+    /// Finds the next surface hit by ray, passing through the false
+    /// intersections of media that a medium of higher priority overrides,
+    /// and attenuates throughput by the absorption along the way.
+    fn hit_through_media(
+        ray: &mut Ray,
+        world: &HittableList,
+        throughput: &mut PathThroughput,
+    ) -> Option<HitRecord> {
+        loop {
+            let rec = world.hit(ray, &Interval::new(0.001, f64::INFINITY))?;
+            if let Some(medium) = ray.media().current() {
+                throughput.attenuate(medium.transmittance(rec.t * ray.dir().length()));
+            }
+
+            match rec.mat.medium() {
+                Some(medium) if ray.media().is_false_intersection(&medium) => {
+                    let mut media = ray.media().clone();
+                    media.cross(medium, rec.front_face);
+                    *ray = Ray::new(rec.point, ray.dir(), ray.time())
+                        .with_media(media)
+                        .with_wavelength(ray.wavelength())
+                        .with_differential(ray.differential());
+                }
+                _ => return Some(rec),
+            }
+        }
+    }
+
+    /// Fills in the AOV data of a camera ray's first hit.
+    fn record_first_hit(&self, ray: &Ray, rec: &HitRecord, sample: &mut AovSample) {
+        sample.normal = rec.normal;
+        sample.position = rec.point;
+        sample.depth = (rec.point - self.center).dot(&-self.w);
+        sample.albedo = rec.mat.albedo(rec);
+        sample.material = Some(Rc::as_ptr(&rec.mat) as *const () as usize);
+        sample.object_id = rec.object_id;
+
+        // Screen-space motion between the hit point's positions at the
+        // start and at the end of the shutter interval.
+        let start = rec.point - ray.time() * rec.motion;
+        let end = rec.point + (1. - ray.time()) * rec.motion;
+        if let (Some((i0, j0)), Some((i1, j1))) = (self.project(start), self.project(end)) {
+            sample.motion = Vec3(i1 - i0, j1 - j0, 0.);
+        }
+    }
+
+    /// Returns the continuous pixel coordinates at which a point in front
+    /// of the camera appears, with pixel centers at whole numbers.
+    pub fn project(&self, point: Vec3) -> Option<(f64, f64)> {
+        let d = point - self.center;
+        let dz = d.dot(&-self.w);
+        if dz <= 0. {
+            return None;
+        }
+        let on_viewport = self.center + d * (self.focus_distance / dz) - self.pixel00_loc;
+        Some((
+            on_viewport.dot(&self.pixel_delta_u) / self.pixel_delta_u.length_squared(),
+            on_viewport.dot(&self.pixel_delta_v) / self.pixel_delta_v.length_squared(),
+        ))
     }
 
     /// Construct a camera ray originating from the defocus disk and directed at a
-    /// sampled point around the pixel location i, j.
+    /// sampled point around the pixel location i, j. In spectral mode the ray
+    /// carries a random hero wavelength.
     fn get_ray(&self, i: u32, j: u32) -> Ray {
         let offset = Camera::sample_square();
         let pixel_sample = self.pixel00_loc
@@ -152,7 +518,20 @@ impl Camera {
         } else {
             self.defocus_disk_sample()
         };
-        Ray::new(origin, pixel_sample - origin, rand::random())
+        let wavelength = self.spectral.then(SampledWavelengths::sample_hero);
+        // Rays through the same point of the next pixels over. With several
+        // samples per pixel each covers only part of it.
+        let differential = RayDifferential {
+            rx_origin: origin,
+            rx_dir: pixel_sample + self.pixel_delta_u - origin,
+            ry_origin: origin,
+            ry_dir: pixel_sample + self.pixel_delta_v - origin,
+        };
+        let mut ray = Ray::new(origin, pixel_sample - origin, rand::random())
+            .with_wavelength(wavelength)
+            .with_differential(Some(differential));
+        ray.scale_differential((1. / (self.samples_per_pixel as f64).sqrt()).max(0.125));
+        ray
     }
 
     fn sample_square() -> Vec3 {
@@ -164,3 +543,495 @@ impl Camera {
         self.center + p.0 * self.defocus_disk_u + p.1 * self.defocus_disk_v
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::EnvironmentMap;
+    use crate::hittable::Sphere;
+    use crate::light::PointLight;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal, ScatterResult};
+    use crate::mesh::TriangleMesh;
+    use crate::spectrum::{LAMBDA_MAX, LAMBDA_MIN};
+    use std::cell::RefCell;
+    use std::f64::consts::PI;
+
+    fn test_camera() -> Camera {
+        Camera::new(
+            1.,
+            4,
+            3,
+            2,
+            90.,
+            Vec3(0., 0., 0.),
+            Vec3(0., 0., -1.),
+            Vec3(0., 1., 0.),
+            1.,
+            0.,
+        )
+    }
+
+    #[test]
+    fn render_progressive_renders_samples_per_pixel_passes() {
+        let cam = test_camera();
+        let fb = cam.render_progressive(&HittableList::new());
+        assert_eq!(fb.passes(), 3);
+    }
+
+    #[test]
+    fn time_budget_renders_at_least_one_pass() {
+        let mut cam = test_camera();
+        cam.time_budget = Some(Duration::ZERO);
+        let fb = cam.render_progressive(&HittableList::new());
+        assert_eq!(fb.passes(), 1);
+    }
+
+    #[test]
+    fn crop_renders_only_region() {
+        let mut cam = test_camera();
+        cam.crop = Some(Tile::new(1, 2, 3, 1));
+
+        let fb = cam.render_progressive(&HittableList::new());
+        assert_eq!((fb.width(), fb.height()), (3, 1));
+        assert!(fb.pixel(0, 0).length() > 0.);
+
+        cam.crop_full_size = true;
+        let fb = cam.render_progressive(&HittableList::new());
+        assert_eq!((fb.width(), fb.height()), (4, 4));
+        assert_eq!(fb.pixel(0, 2), Color::new(0., 0., 0.));
+        assert_eq!(fb.pixel(1, 1), Color::new(0., 0., 0.));
+        assert!(fb.pixel(1, 2).length() > 0.);
+        assert!(fb.pixel(3, 2).length() > 0.);
+    }
+
+    #[test]
+    fn render_tile_matches_image_position() {
+        // Looking down -z, the sky gradient gets bluer towards the top of
+        // the image, so the top row of a tile must match the full image's.
+        let cam = test_camera();
+        let world = HittableList::new();
+        let top = Tile::new(0, 0, 4, 1);
+        let bottom = Tile::new(0, 3, 4, 1);
+        let mut top_buf = vec![Color::new(0., 0., 0.); 4];
+        let mut bottom_buf = vec![Color::new(0., 0., 0.); 4];
+        cam.render_tile(&world, &top, 1, &mut top_buf);
+        cam.render_tile(&world, &bottom, 1, &mut bottom_buf);
+        assert!(top_buf[0].x() < bottom_buf[0].x());
+    }
+
+    #[test]
+    fn aovs_describe_first_hit() {
+        let mut world = HittableList::new();
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.2, 0.4, 0.6)));
+        world.add(Rc::new(Sphere::moving(
+            Vec3(-0.1, 0., -3.),
+            Vec3(0.1, 0., -3.),
+            1.5,
+            mat,
+        )));
+
+        let mut cam = test_camera();
+        cam.samples_per_pixel = 1;
+        cam.aovs = vec![
+            Aov::Normal,
+            Aov::Depth,
+            Aov::Albedo,
+            Aov::ObjectId,
+            Aov::Motion,
+        ];
+        // Rendered rays are jittered within their pixels, so trace the ray
+        // through the centre of pixel 2, 2, which hits the sphere.
+        let centre = cam.pixel00_loc + 2. * cam.pixel_delta_u + 2. * cam.pixel_delta_v;
+        let ray = Ray::new(cam.center, centre - cam.center, 0.5);
+        let mut sample = AovSample::new(0);
+        cam.trace(&ray, &world, Some(&mut sample));
+        let mut fb = Framebuffer::new(4, 4).with_aovs(AovBuffers::new(4, 4, &cam.aovs, &[]));
+        fb.add_aovs(2, 2, &sample);
+        fb.end_pass();
+        let aovs = fb.aovs().unwrap();
+
+        let normal = aovs.pixel(Aov::Normal, 2, 2).unwrap();
+        assert!(normal.z() > 0.5);
+        let depth = aovs.pixel(Aov::Depth, 2, 2).unwrap().x();
+        assert!((1.5..3.).contains(&depth));
+        assert_eq!(
+            aovs.pixel(Aov::Albedo, 2, 2),
+            Some(Color::new(0.2, 0.4, 0.6))
+        );
+        assert!(aovs.id(Aov::ObjectId, 2, 2).unwrap() > 0);
+        let motion = aovs.pixel(Aov::Motion, 2, 2).unwrap();
+        assert!(motion.x() > 0. && motion.y().abs() < 1e-9);
+
+        // Any ray through the corner pixel misses the sphere and sees the
+        // sky.
+        let fb = cam.render_progressive(&world);
+        let aovs = fb.aovs().unwrap();
+        assert_eq!(aovs.pixel(Aov::Depth, 0, 0), Some(Vec3(0., 0., 0.)));
+        assert_eq!(aovs.id(Aov::ObjectId, 0, 0), Some(0));
+        assert_eq!(aovs.pixel(Aov::Albedo, 0, 0), Some(fb.pixel(0, 0)));
+    }
+
+    #[test]
+    fn project_works() {
+        let cam = test_camera();
+        let (i, j) = cam.project(Vec3(0., 0., -5.)).unwrap();
+        assert!((i - 1.5).abs() < 1e-9 && (j - 1.5).abs() < 1e-9);
+        let (i, j) = cam.project(Vec3(-1., 1., -1.)).unwrap();
+        assert!((i + 0.5).abs() < 1e-9 && (j + 0.5).abs() < 1e-9);
+        assert_eq!(cam.project(Vec3(0., 0., 1.)), None);
+    }
+
+    #[test]
+    fn camera_rays_reach_neighbouring_pixels() {
+        let mut cam = test_camera();
+        cam.samples_per_pixel = 1;
+        let ray = cam.get_ray(1, 2);
+        let diff = ray.differential().unwrap();
+        let (i, j) = cam.project(ray.at(1.)).unwrap();
+        let (xi, xj) = cam.project(diff.rx_origin + diff.rx_dir).unwrap();
+        let (yi, yj) = cam.project(diff.ry_origin + diff.ry_dir).unwrap();
+        assert!((xi - i - 1.).abs() < 1e-9 && (xj - j).abs() < 1e-9);
+        assert!((yi - i).abs() < 1e-9 && (yj - j - 1.).abs() < 1e-9);
+
+        // With more samples each covers less of the pixel.
+        cam.samples_per_pixel = 16;
+        let ray = cam.get_ray(1, 2);
+        let diff = ray.differential().unwrap();
+        let (i, _) = cam.project(ray.at(1.)).unwrap();
+        let (xi, _) = cam.project(diff.rx_origin + diff.rx_dir).unwrap();
+        assert!((xi - i - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mirrors_keep_reflected_differentials() {
+        // Records the rays that reach it and absorbs them.
+        struct Probe(RefCell<Vec<Ray>>);
+        impl Material for Probe {
+            fn scatter(&self, r_in: &Ray, _rec: &HitRecord) -> Option<ScatterResult> {
+                self.0.borrow_mut().push(r_in.clone());
+                None
+            }
+
+            fn albedo(&self, _rec: &HitRecord) -> Color {
+                Color::new(0., 0., 0.)
+            }
+        }
+
+        // A nearly flat mirror in front of the camera reflects its rays
+        // back onto a probe behind it.
+        let mut world = HittableList::new();
+        let mirror: Rc<dyn Material> = Rc::new(Metal::new(Color::new(1., 1., 1.), 0.));
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 0., -101.),
+            100.,
+            &mirror,
+        )));
+        let probe = Rc::new(Probe(RefCell::new(vec![])));
+        let probe_mat: Rc<dyn Material> = probe.clone();
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 0., 4.),
+            2.,
+            &probe_mat,
+        )));
+
+        test_camera().render_progressive(&world);
+        let rays = probe.0.borrow();
+        assert!(!rays.is_empty());
+        for ray in rays.iter() {
+            // The offset rays leave the mirror heading back too, rather
+            // than keep those of the camera ray.
+            let diff = ray.differential().unwrap();
+            assert!(diff.rx_origin.z() < -0.9 && diff.ry_origin.z() < -0.9);
+            assert!(diff.rx_dir.z() > 0. && diff.ry_dir.z() > 0.);
+        }
+    }
+
+    #[test]
+    fn lights_are_sampled_through_shadow_rays() {
+        let mut cam = test_camera();
+        cam.max_depth = 1;
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(albedo));
+        let intensity = Color::new(2., 2., 2.);
+        let lit_by = |position: Vec3| {
+            let mut world = HittableList::new();
+            world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 1., &mat)));
+            world.add_light(Rc::new(PointLight::new(position, intensity)));
+            world
+        };
+
+        // The hit at (0, 0, -2) faces +z, and the light is √2 away at 45°.
+        let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.);
+        let expected = albedo / PI * (0.5f64.sqrt() * intensity / 2.);
+        let world = lit_by(Vec3(0., 1., -1.));
+        assert!((cam.color_ray(&ray, &world) - expected).length() < 1e-12);
+
+        // Lights below the horizon or behind something send nothing.
+        let world = lit_by(Vec3(0., 0., -5.));
+        assert_eq!(cam.color_ray(&ray, &world), Color::new(0., 0., 0.));
+        let mut world = lit_by(Vec3(0., 2., -2.));
+        world.add(Rc::new(Sphere::stationary(Vec3(0., 1., -2.), 0.2, &mat)));
+        assert_eq!(cam.color_ray(&ray, &world), Color::new(0., 0., 0.));
+        assert_eq!(cam.light_names(&world), ["sky", "1"]);
+    }
+
+    #[test]
+    fn light_selections_agree_on_average() {
+        let mut cam = test_camera();
+        cam.max_depth = 1;
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 1., &mat)));
+        for (k, position) in [Vec3(0., 1., -1.), Vec3(2., 0., -1.), Vec3(-1., -1., 0.)]
+            .into_iter()
+            .enumerate()
+        {
+            let intensity = Color::new(1., 2., 3.) * (k + 1) as f64;
+            world.add_light(Rc::new(PointLight::new(position, intensity)));
+        }
+
+        let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.);
+        let all = cam.color_ray(&ray, &world);
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Bvh,
+        ] {
+            world.set_light_selection(selection);
+            let n = 20_000;
+            let mut total = Color::new(0., 0., 0.);
+            for _ in 0..n {
+                total += cam.color_ray(&ray, &world);
+            }
+            assert!((total / n as f64 - all).length() < 0.01 * all.length());
+        }
+    }
+
+    #[test]
+    fn environment_light_is_counted_once() {
+        // A convex diffuse object inside an environment of constant
+        // radiance reflects its albedo times that radiance, which
+        // environment samples and scattered rays must share without
+        // counting it twice.
+        let cam = test_camera();
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(albedo));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 1., &mat)));
+        let sky = Color::new(1., 2., 3.);
+        world.set_environment(Rc::new(EnvironmentMap::new(8, 4, vec![sky; 32])));
+
+        let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.);
+        let n = 20_000;
+        let mut total = Color::new(0., 0., 0.);
+        for _ in 0..n {
+            total += cam.color_ray(&ray, &world);
+        }
+        assert!((total / n as f64 - albedo * sky).length() < 0.02);
+
+        // Rays missing everything see the environment instead of the
+        // default sky.
+        let up = Ray::new(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 0.);
+        assert_eq!(cam.color_ray(&up, &world), sky);
+    }
+
+    #[test]
+    fn emissive_meshes_are_counted_once() {
+        // A glowing quad above a diffuse sphere lights it the same whether
+        // its triangles are lights sharing their light with scattered
+        // rays, or plain surfaces that only scattered rays find.
+        let mut cam = test_camera();
+        cam.max_depth = 2;
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let glow: Rc<dyn Material> = Rc::new(DiffuseLight::new(Color::new(4., 4., 4.)));
+        let positions = vec![
+            Vec3(-1., 2., -4.),
+            Vec3(1., 2., -4.),
+            Vec3(1., 2., -2.),
+            Vec3(-1., 2., -2.),
+        ];
+        let quad = Rc::new(TriangleMesh::new(positions, vec![[0, 1, 2], [0, 2, 3]]));
+        let dark = Rc::new(EnvironmentMap::new(
+            1,
+            1,
+            vec![Color::new(0.01, 0.01, 0.01)],
+        ));
+        let scene = |lit: bool| {
+            let mut world = HittableList::new();
+            world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 1., &mat)));
+            world.set_environment(dark.clone());
+            match lit {
+                true => world.add_mesh(&quad, &glow),
+                false => quad.triangles(&glow).into_iter().for_each(|t| world.add(t)),
+            }
+            world
+        };
+        let average = |world: &HittableList| {
+            let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0.3, -1.), 0.);
+            let n = 40_000;
+            let mut total = Color::new(0., 0., 0.);
+            for _ in 0..n {
+                total += cam.color_ray(&ray, world);
+            }
+            total / n as f64
+        };
+
+        let unlit = average(&scene(false));
+        let mut world = scene(true);
+        assert_eq!(world.lights().len(), 2);
+        assert_eq!(cam.light_names(&world), ["sky", "1"]);
+        for selection in [LightSelection::All, LightSelection::Bvh] {
+            world.set_light_selection(selection);
+            let lit = average(&world);
+            assert!((lit - unlit).length() < 0.03 * unlit.length());
+        }
+
+        // The whole mesh shares one light layer.
+        world.add_light(Rc::new(PointLight::new(
+            Vec3(0., 2., 0.),
+            Color::new(1., 1., 1.),
+        )));
+        assert_eq!(cam.light_names(&world), ["sky", "1", "2"]);
+        cam.aovs = vec![Aov::Lights];
+        let fb = cam.render_progressive(&world);
+        assert_eq!(fb.aovs().unwrap().light_count(), 3);
+    }
+
+    #[test]
+    fn emissive_spheres_are_counted_once() {
+        // Small glowing spheres above a diffuse one light it the same
+        // whether they are lights or plain surfaces.
+        let mut cam = test_camera();
+        cam.max_depth = 2;
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let glow: Rc<dyn Material> = Rc::new(DiffuseLight::new(Color::new(4., 4., 4.)));
+        let lamps: Vec<_> = [Vec3(-0.8, 1.8, -3.), Vec3(0.8, 2., -2.5)]
+            .into_iter()
+            .map(|center| Rc::new(Sphere::stationary(center, 0.4, &glow)))
+            .collect();
+        let dark = Rc::new(EnvironmentMap::new(
+            1,
+            1,
+            vec![Color::new(0.01, 0.01, 0.01)],
+        ));
+        let scene = |lit: bool| {
+            let mut world = HittableList::new();
+            world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 1., &mat)));
+            world.set_environment(dark.clone());
+            for lamp in &lamps {
+                match lit {
+                    true => world.add_sphere(Rc::clone(lamp)),
+                    false => world.add(lamp.clone()),
+                }
+            }
+            world
+        };
+        let average = |world: &HittableList| {
+            let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0.3, -1.), 0.);
+            let n = 40_000;
+            let mut total = Color::new(0., 0., 0.);
+            for _ in 0..n {
+                total += cam.color_ray(&ray, world);
+            }
+            total / n as f64
+        };
+
+        let unlit = average(&scene(false));
+        let mut world = scene(true);
+        assert_eq!(world.lights().len(), 2);
+        assert_eq!(cam.light_names(&world), ["sky", "1", "2"]);
+        for selection in [
+            LightSelection::All,
+            LightSelection::Power,
+            LightSelection::Bvh,
+        ] {
+            world.set_light_selection(selection);
+            let lit = average(&world);
+            assert!((lit - unlit).length() < 0.03 * unlit.length());
+        }
+    }
+
+    #[test]
+    fn time_budget_keeps_adding_passes() {
+        let mut cam = test_camera();
+        cam.time_budget = Some(Duration::from_millis(20));
+        let start = Instant::now();
+        let fb = cam.render_progressive(&HittableList::new());
+        assert!(fb.passes() > cam.samples_per_pixel);
+        // Only a loose bound, so that a busy machine doesn't fail it.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Radiance along the axis of the test camera through spheres of
+    /// the given radii and materials around (0, 0, -3).
+    fn color_through_spheres(spheres: Vec<(f64, Dielectric)>) -> Color {
+        let mut world = HittableList::new();
+        for (radius, material) in spheres {
+            let material: Rc<dyn Material> = Rc::new(material);
+            world.add(Rc::new(Sphere::stationary(
+                Vec3(0., 0., -3.),
+                radius,
+                &material,
+            )));
+        }
+        let mut cam = test_camera();
+        cam.max_depth = 10;
+        cam.color_ray(&Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.), &world)
+    }
+
+    #[test]
+    fn thick_glass_absorbs_more() {
+        // With a refractive index of 1 the axial ray passes straight through.
+        let transmittance = Color::new(0.5, 0.8, 0.9);
+        let glass = || Dielectric::new(1.).with_transmittance(transmittance, 1.);
+        let sky = Camera::background(&Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.));
+
+        let thin = color_through_spheres(vec![(0.5, glass())]);
+        let thick = color_through_spheres(vec![(1., glass())]);
+        assert!((thin - sky * transmittance).length() < 1e-9);
+        assert!((thick - sky * transmittance * transmittance).length() < 1e-9);
+    }
+
+    #[test]
+    fn media_priorities_decide_absorption() {
+        let transmittance = Color::new(0.5, 0.8, 0.9);
+        let sky = Camera::background(&Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.));
+        let glass = || Dielectric::new(1.).with_priority(2);
+        let liquid = |priority| {
+            Dielectric::new(1.)
+                .with_transmittance(transmittance, 1.)
+                .with_priority(priority)
+        };
+
+        // Clear glass with higher priority overrides the liquid inside it.
+        let overridden = color_through_spheres(vec![(1., glass()), (0.5, liquid(1))]);
+        assert!((overridden - sky).length() < 1e-9);
+
+        // With a higher priority the liquid fills its sphere.
+        let filled = color_through_spheres(vec![(1., glass()), (0.5, liquid(3))]);
+        assert!((filled - sky * transmittance).length() < 1e-9);
+    }
+
+    #[test]
+    fn spectral_paths_average_to_rgb() {
+        let transmittance = Color::new(0.5, 0.8, 0.9);
+        let glass: Rc<dyn Material> =
+            Rc::new(Dielectric::new(1.).with_transmittance(transmittance, 1.));
+        let mut world = HittableList::new();
+        world.add(Rc::new(Sphere::stationary(Vec3(0., 0., -3.), 0.5, &glass)));
+        let mut cam = test_camera();
+        cam.max_depth = 10;
+        let ray = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.);
+        let rgb = cam.color_ray(&ray, &world);
+
+        let n = 1000;
+        let mut spectral = Color::new(0., 0., 0.);
+        for k in 0..n {
+            let hero = LAMBDA_MIN + (k as f64 + 0.5) / n as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            spectral += cam.color_ray(&ray.clone().with_wavelength(Some(hero)), &world) / n as f64;
+        }
+        // Products of upsampled spectra only approximate the upsampled
+        // product of the colors.
+        assert!((spectral - rgb).length() < 0.01, "{:?} {:?}", spectral, rgb);
+    }
+}
@@ -1,12 +1,252 @@
+use std::sync::OnceLock;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
 use crate::util::INTENSITY;
 use crate::vec3::Vec3;
 
+/// Encodes a linear component with the piecewise sRGB transfer function
+/// (the OETF of IEC 61966-2-1).
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0. {
+        0.
+    } else if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Decodes an sRGB encoded component to linear, the inverse of
+/// `linear_to_srgb` on [0, 1].
+pub fn srgb_to_linear(encoded_component: f64) -> f64 {
+    if encoded_component <= 0. {
+        0.
+    } else if encoded_component <= 0.04045 {
+        encoded_component / 12.92
+    } else {
+        ((encoded_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Operator compressing scene-referred linear values into the displayable
+/// range [0, 1]. Applied to each channel separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,                 // No compression, values above 1 are clipped
+    Reinhard,              // x / (1 + x)
+    ReinhardExtended(f64), // Reinhard with the given white point mapped to 1
+    Aces,                  // Narkowicz's fit of the ACES filmic curve
+    Hable,                 // Hable's filmic curve from Uncharted 2
+}
+
+impl ToneMap {
+    pub fn apply(&self, x: f64) -> f64 {
+        let x = x.max(0.);
+        match *self {
+            ToneMap::Clamp => x.min(1.),
+            ToneMap::Reinhard => x / (1. + x),
+            ToneMap::ReinhardExtended(white) => {
+                debug_assert!(white.is_finite() && white > 0.);
+                (x * (1. + x / (white * white)) / (1. + x)).min(1.)
+            }
+            ToneMap::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0., 1.)
+            }
+            ToneMap::Hable => {
+                const WHITE: f64 = 11.2;
+                (Self::hable_partial(x) / Self::hable_partial(WHITE)).min(1.)
+            }
+        }
+    }
+
+    fn hable_partial(x: f64) -> f64 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    }
+}
+
+/// Transform from the linear values of the float framebuffer to encoded
+/// display values in [0, 1]: exposure, then tone mapping, then the sRGB
+/// transfer function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTransform {
+    pub exposure: f64, // Exposure adjustment in stops (EV)
+    pub tone_map: ToneMap,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+        }
+    }
+}
+
+impl DisplayTransform {
+    pub fn apply(&self, linear: Color) -> Color {
+        let scale = 2f64.powf(self.exposure);
+        let encode = |x: f64| linear_to_srgb(self.tone_map.apply(scale * x));
+        Color::new(encode(linear.0), encode(linear.1), encode(linear.2))
+    }
+}
+
+/// Noise added before quantizing, to trade banding for fine grain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dither {
+    None,
+    Triangular, // Random noise with a triangular PDF spanning ±1 step
+    Bayer,      // 8x8 ordered dither
+    BlueNoise,  // 64x64 blue noise threshold mask
+}
+
+/// Conversion of encoded [0, 1] values to 8-bit integers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantizer {
+    pub round_to_nearest: bool, // Round instead of truncating; implied by dithering
+    pub dither: Dither,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Self {
+            round_to_nearest: false,
+            dither: Dither::None,
+        }
+    }
+}
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    match linear_component > 0. {
-        true => linear_component.sqrt(),
-        false => 0.,
+impl Quantizer {
+    /// Quantizes the value of channel c of pixel i, j.
+    pub fn quantize(&self, x: f64, i: u32, j: u32, c: usize) -> u8 {
+        if !self.round_to_nearest && self.dither == Dither::None {
+            return (256. * INTENSITY.clamp(x)) as u8;
+        }
+
+        let offset = match self.dither {
+            Dither::None => 0.,
+            Dither::Triangular => rand::random::<f64>() - rand::random::<f64>(),
+            Dither::Bayer => bayer_threshold(i, j),
+            // Shift the mask per channel so channels don't dither in lockstep.
+            Dither::BlueNoise => blue_noise_threshold(i + 17 * c as u32, j + 31 * c as u32),
+        };
+        (255. * x + 0.5 + offset).floor().clamp(0., 255.) as u8
+    }
+}
+
+/// Threshold in [-0.5, 0.5) of an 8x8 Bayer matrix at pixel i, j.
+fn bayer_threshold(i: u32, j: u32) -> f64 {
+    // Interleaves the bits of i ^ j and j in reverse order, so the lowest
+    // coordinate bits decide the coarsest threshold steps.
+    let (x, y) = (i % 8, j % 8);
+    let xy = x ^ y;
+    let mut rank = 0;
+    for bit in 0..3 {
+        rank = (rank << 2) | (((xy >> bit) & 1) << 1) | ((y >> bit) & 1);
     }
+    (rank as f64 + 0.5) / 64. - 0.5
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Threshold in [-0.5, 0.5) of a tiled blue noise mask at pixel i, j.
+fn blue_noise_threshold(i: u32, j: u32) -> f64 {
+    static MASK: OnceLock<Vec<u16>> = OnceLock::new();
+    let mask = MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5));
+    let n = BLUE_NOISE_SIZE;
+    let rank = mask[(j as usize % n) * n + i as usize % n];
+    (rank as f64 + 0.5) / (n * n) as f64 - 0.5
+}
+
+/// Generates a size x size blue noise threshold mask with Ulichney's
+/// void-and-cluster method, returning the rank of every pixel. The
+/// initial pattern comes from a fixed seed, so that every run and every
+/// process dithers alike.
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u16> {
+    let n = size * size;
+
+    // Gaussian energy contributed by a set pixel at each toroidal offset.
+    let mut kernel = vec![0.; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let x = dx.min(size - dx) as f64;
+            let y = dy.min(size - dy) as f64;
+            kernel[dy * size + dx] = (-(x * x + y * y) / (2. * sigma * sigma)).exp();
+        }
+    }
+    let update = |energy: &mut [f64], p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for qy in 0..size {
+            for qx in 0..size {
+                let d = ((qy + size - py) % size) * size + (qx + size - px) % size;
+                energy[qy * size + qx] += sign * kernel[d];
+            }
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| pattern[p])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&p| !pattern[p])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Initial pattern: random pixels, relaxed by moving points from the
+    // tightest cluster to the largest void until that changes nothing.
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.; n];
+    let ones = n / 10;
+    let mut count = 0;
+    while count < ones {
+        let p = rng.random_range(0..n);
+        if !pattern[p] {
+            pattern[p] = true;
+            update(&mut energy, p, 1.);
+            count += 1;
+        }
+    }
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1.);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1.);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0u16; n];
+
+    // Rank the initial points by removing tightest clusters.
+    let mut removing = pattern.clone();
+    let mut removing_energy = energy.clone();
+    for r in (0..ones).rev() {
+        let cluster = tightest_cluster(&removing, &removing_energy);
+        removing[cluster] = false;
+        update(&mut removing_energy, cluster, -1.);
+        rank[cluster] = r as u16;
+    }
+
+    // Rank the remaining pixels by filling the largest voids.
+    for r in ones..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1.);
+        rank[void] = r as u16;
+    }
+
+    rank
 }
 
 pub type Color = Vec3;
@@ -16,14 +256,24 @@ impl Color {
         Vec3(r, g, b)
     }
 
-    pub fn write_io<W: std::io::Write>(&self, w: &mut W) {
-        let r = linear_to_gamma(self.0);
-        let g = linear_to_gamma(self.1);
-        let b = linear_to_gamma(self.2);
+    /// Luminance of a linear color with Rec. 709 primaries.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x() + 0.7152 * self.y() + 0.0722 * self.z()
+    }
+
+    /// Writes the color of pixel i, j as three decimal 8-bit values.
+    pub fn write_io<W: std::io::Write>(
+        &self,
+        w: &mut W,
+        display: &DisplayTransform,
+        quantizer: &Quantizer,
+        (i, j): (u32, u32),
+    ) {
+        let Vec3(r, g, b) = display.apply(*self);
 
-        let rbyte = (256. * INTENSITY.clamp(r)) as u8;
-        let gbyte = (256. * INTENSITY.clamp(g)) as u8;
-        let bbyte = (256. * INTENSITY.clamp(b)) as u8;
+        let rbyte = quantizer.quantize(r, i, j, 0);
+        let gbyte = quantizer.quantize(g, i, j, 1);
+        let bbyte = quantizer.quantize(b, i, j, 2);
         writeln!(w, "{} {} {}", rbyte, gbyte, bbyte).unwrap();
     }
 }
@@ -36,8 +286,200 @@ mod tests {
     fn write_to_stdout_works() {
         let c = Vec3(0.5, 1.0, 0.0);
         let mut s = Vec::new();
-        c.write_io(&mut s);
+        c.write_io(
+            &mut s,
+            &DisplayTransform::default(),
+            &Quantizer::default(),
+            (0, 0),
+        );
         let s = String::from_utf8(s).unwrap();
-        assert_eq!(s, "181 255 0\n");
+        assert_eq!(s, "188 255 0\n");
+    }
+
+    #[test]
+    fn linear_to_srgb_works() {
+        assert_eq!(linear_to_srgb(-1.), 0.);
+        assert!((linear_to_srgb(0.001) - 0.01292).abs() < 1e-12);
+        assert!((linear_to_srgb(0.0031308) - 0.04045).abs() < 1e-5);
+        assert!((linear_to_srgb(0.18) - 0.46135).abs() < 1e-5);
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn srgb_to_linear_works() {
+        assert_eq!(srgb_to_linear(-1.), 0.);
+        for k in 0..=100 {
+            let x = k as f64 / 100.;
+            assert!((linear_to_srgb(srgb_to_linear(x)) - x).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn tone_maps_are_monotonic_and_bounded() {
+        let maps = [
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ReinhardExtended(4.),
+            ToneMap::Aces,
+            ToneMap::Hable,
+        ];
+        for map in maps {
+            assert!(map.apply(0.).abs() < 1e-12, "{:?}", map);
+            let mut last = 0.;
+            for k in 1..200 {
+                let y = map.apply(k as f64 * 0.1);
+                assert!(y >= last && y <= 1., "{:?}", map);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn tone_map_reference_values() {
+        assert_eq!(ToneMap::Clamp.apply(3.), 1.);
+        assert_eq!(ToneMap::Reinhard.apply(1.), 0.5);
+        assert!((ToneMap::ReinhardExtended(4.).apply(4.) - 1.).abs() < 1e-12);
+        assert!((ToneMap::ReinhardExtended(4.).apply(1.) - 0.53125).abs() < 1e-12);
+        assert!((ToneMap::Aces.apply(1.) - 2.54 / 3.16).abs() < 1e-12);
+        assert!((ToneMap::Hable.apply(11.2) - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exposure_scales_in_stops() {
+        let display = DisplayTransform {
+            exposure: 1.,
+            tone_map: ToneMap::Clamp,
+        };
+        let expected = DisplayTransform::default().apply(Color::new(0.5, 0.2, 0.));
+        assert_eq!(display.apply(Color::new(0.25, 0.1, 0.)), expected);
+    }
+
+    /// Quantizes a slow horizontal gradient spanning a few 8-bit steps.
+    fn quantize_gradient(quantizer: &Quantizer) -> (Vec<f64>, Vec<Vec<u8>>) {
+        let (width, height) = (512, 64);
+        let values: Vec<f64> = (0..width)
+            .map(|i| (100. + 4. * i as f64 / width as f64) / 255.)
+            .collect();
+        let rows = (0..height)
+            .map(|j| {
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &x)| quantizer.quantize(x, i as u32, j, 0))
+                    .collect()
+            })
+            .collect();
+        (values, rows)
+    }
+
+    /// Largest error, in 8-bit steps, between the mean of the quantized
+    /// values in each 8-column band and the mean of the exact values.
+    fn max_band_error(values: &[f64], rows: &[Vec<u8>]) -> f64 {
+        let mut max_error: f64 = 0.;
+        for band in (0..values.len()).step_by(8) {
+            let exact = values[band..band + 8].iter().sum::<f64>() * 255. / 8.;
+            let quantized = rows
+                .iter()
+                .flat_map(|row| &row[band..band + 8])
+                .map(|&b| b as f64)
+                .sum::<f64>()
+                / (8 * rows.len()) as f64;
+            max_error = max_error.max((quantized - exact).abs());
+        }
+        max_error
+    }
+
+    /// Fraction of the 8x8 blocks in which every pixel has the same value,
+    /// i.e. that lie inside a visible band.
+    fn flat_block_fraction(rows: &[Vec<u8>]) -> f64 {
+        let mut blocks = 0;
+        let mut flat = 0;
+        for by in (0..rows.len()).step_by(8) {
+            for bx in (0..rows[0].len()).step_by(8) {
+                let first = rows[by][bx];
+                blocks += 1;
+                if rows[by..by + 8]
+                    .iter()
+                    .all(|row| row[bx..bx + 8].iter().all(|&b| b == first))
+                {
+                    flat += 1;
+                }
+            }
+        }
+        flat as f64 / blocks as f64
+    }
+
+    #[test]
+    fn round_to_nearest_works() {
+        let truncate = Quantizer::default();
+        let nearest = Quantizer {
+            round_to_nearest: true,
+            dither: Dither::None,
+        };
+        assert_eq!(truncate.quantize(0.7 / 255., 0, 0, 0), 0);
+        assert_eq!(nearest.quantize(0.7 / 255., 0, 0, 0), 1);
+        assert_eq!(nearest.quantize(0.3 / 255., 0, 0, 0), 0);
+        assert_eq!(nearest.quantize(1.2, 0, 0, 0), 255);
+        assert_eq!(nearest.quantize(-0.1, 0, 0, 0), 0);
+    }
+
+    #[test]
+    fn undithered_gradient_bands() {
+        let quantizer = Quantizer {
+            round_to_nearest: true,
+            dither: Dither::None,
+        };
+        let (values, rows) = quantize_gradient(&quantizer);
+        assert!(flat_block_fraction(&rows) > 0.9);
+        assert!(max_band_error(&values, &rows) > 0.4);
+    }
+
+    #[test]
+    fn dithered_gradients_do_not_band() {
+        for dither in [Dither::Triangular, Dither::Bayer, Dither::BlueNoise] {
+            let quantizer = Quantizer {
+                round_to_nearest: true,
+                dither,
+            };
+            let (values, rows) = quantize_gradient(&quantizer);
+            assert!(flat_block_fraction(&rows) < 0.05, "{:?}", dither);
+            assert!(max_band_error(&values, &rows) < 0.1, "{:?}", dither);
+        }
+    }
+
+    #[test]
+    fn thresholds_are_uniform() {
+        let mut bayer: Vec<f64> = (0..8)
+            .flat_map(|j| (0..8).map(move |i| bayer_threshold(i, j)))
+            .collect();
+        bayer.sort_by(f64::total_cmp);
+        for (k, t) in bayer.iter().enumerate() {
+            assert!((t - ((k as f64 + 0.5) / 64. - 0.5)).abs() < 1e-12);
+        }
+
+        let n = BLUE_NOISE_SIZE as u32;
+        let mut blue: Vec<f64> = (0..n)
+            .flat_map(|j| (0..n).map(move |i| blue_noise_threshold(i, j)))
+            .collect();
+        blue.sort_by(f64::total_cmp);
+        for (k, t) in blue.iter().enumerate() {
+            assert!((t - ((k as f64 + 0.5) / (n * n) as f64 - 0.5)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn bayer_neighbors_differ() {
+        // Horizontally adjacent thresholds of a Bayer matrix are always at
+        // least half the range apart.
+        for j in 0..8 {
+            for i in 0..8 {
+                assert!((bayer_threshold(i, j) - bayer_threshold(i + 1, j)).abs() >= 0.25);
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_is_reproducible() {
+        assert_eq!(void_and_cluster(16, 1.5), void_and_cluster(16, 1.5));
     }
 }
@@ -0,0 +1,241 @@
+//! Constructive solid geometry: unions, intersections and differences of
+//! closed objects. A ray's hits on each operand split it into intervals
+//! inside and outside that operand; the combined object keeps the hits
+//! where the ray crosses between inside and outside of the combination.
+
+use std::rc::Rc;
+
+use crate::{
+    bvh::AABB,
+    hittable::{HitRecord, Hittable},
+    ray::Ray,
+    util::Interval,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl Operation {
+    fn inside(self, left: bool, right: bool) -> bool {
+        match self {
+            Self::Union => left || right,
+            Self::Intersection => left && right,
+            Self::Difference => left && !right,
+        }
+    }
+}
+
+/// Combination of two objects as solids. Both must be closed, so that
+/// every ray alternates between entering and leaving them, and may
+/// themselves be combinations. Surfaces keep the materials of the
+/// operands they come from, so the walls of a hole cut by a difference
+/// take the material of the object cut away.
+pub struct Csg {
+    operation: Operation,
+    left: Rc<dyn Hittable>,
+    right: Rc<dyn Hittable>,
+    bbox: AABB,
+}
+
+impl Csg {
+    fn new(operation: Operation, left: Rc<dyn Hittable>, right: Rc<dyn Hittable>) -> Self {
+        let bbox = match operation {
+            Operation::Union => AABB::from_boxes(left.bounding_box(), right.bounding_box()),
+            _ => left.bounding_box().clone(),
+        };
+        Self {
+            operation,
+            left,
+            right,
+            bbox,
+        }
+    }
+
+    /// Points inside either object.
+    pub fn union(left: Rc<dyn Hittable>, right: Rc<dyn Hittable>) -> Self {
+        Self::new(Operation::Union, left, right)
+    }
+
+    /// Points inside both objects.
+    pub fn intersection(left: Rc<dyn Hittable>, right: Rc<dyn Hittable>) -> Self {
+        Self::new(Operation::Intersection, left, right)
+    }
+
+    /// Points inside left but not right.
+    pub fn difference(left: Rc<dyn Hittable>, right: Rc<dyn Hittable>) -> Self {
+        Self::new(Operation::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        self.hits(ray, ray_t).into_iter().next()
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn hits(&self, ray: &Ray, ray_t: &Interval) -> Vec<HitRecord> {
+        // Whether the ray is inside an operand depends on all its hits
+        // beyond the start, so only the combined hits are cut to ray_t.
+        let beyond = Interval::new(ray_t.min(), f64::INFINITY);
+        if !self.bbox.hit(ray, &beyond) {
+            return vec![];
+        }
+        let left = self.left.hits(ray, &beyond);
+        let right = self.right.hits(ray, &beyond);
+
+        // The ray starts inside an operand when its first hit there is an
+        // exit, and each hit after toggles that.
+        let starts_inside = |hits: &[HitRecord]| hits.first().is_some_and(|rec| !rec.front_face);
+        let mut inside = [starts_inside(&left), starts_inside(&right)];
+        let mut combined = self.operation.inside(inside[0], inside[1]);
+
+        let mut events: Vec<(usize, HitRecord)> = left
+            .into_iter()
+            .map(|rec| (0, rec))
+            .chain(right.into_iter().map(|rec| (1, rec)))
+            .collect();
+        events.sort_by(|a, b| a.1.t.total_cmp(&b.1.t));
+
+        let mut hits = vec![];
+        for (operand, mut rec) in events {
+            inside[operand] = rec.front_face;
+            let now_inside = self.operation.inside(inside[0], inside[1]);
+            if now_inside != combined {
+                if !ray_t.surrounds(rec.t) {
+                    break;
+                }
+                // The normal already faces the ray; only the side of the
+                // combined surface the ray arrives from can change.
+                combined = now_inside;
+                rec.front_face = now_inside;
+                hits.push(rec);
+            }
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::material::{Lambertian, Material};
+    use crate::mesh::TriangleMesh;
+    use crate::vec3::Vec3;
+
+    fn sphere(center: Vec3, radius: f64) -> Rc<dyn Hittable> {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        Rc::new(Sphere::stationary(center, radius, &mat))
+    }
+
+    fn ts(object: &dyn Hittable, ray: &Ray) -> Vec<(f64, bool)> {
+        object
+            .hits(ray, &Interval::new(0.001, f64::INFINITY))
+            .iter()
+            .map(|rec| (rec.t, rec.front_face))
+            .collect()
+    }
+
+    fn assert_hits(actual: Vec<(f64, bool)>, expected: &[(f64, bool)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a.0 - e.0).abs() < 1e-9 && a.1 == e.1, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn default_hits_work() {
+        let ray = Ray::new(Vec3(-3., 0., 0.), Vec3(1., 0., 0.), 0.);
+        assert_hits(
+            ts(sphere(Vec3(0., 0., 0.), 1.).as_ref(), &ray),
+            &[(2., true), (4., false)],
+        );
+    }
+
+    #[test]
+    fn csg_operations_work() {
+        let big = sphere(Vec3(0., 0., 0.), 1.);
+        let small = sphere(Vec3(1., 0., 0.), 0.5);
+        let ray = Ray::new(Vec3(-3., 0., 0.), Vec3(1., 0., 0.), 0.);
+
+        let union = Csg::union(big.clone(), small.clone());
+        assert_hits(ts(&union, &ray), &[(2., true), (4.5, false)]);
+        let intersection = Csg::intersection(big.clone(), small.clone());
+        assert_hits(ts(&intersection, &ray), &[(3.5, true), (4., false)]);
+        let difference = Csg::difference(big.clone(), small.clone());
+        assert_hits(ts(&difference, &ray), &[(2., true), (3.5, false)]);
+
+        // The wall of the bite faces into the bigger sphere.
+        let rec = difference
+            .hit(
+                &Ray::new(Vec3(0.2, 0., 0.), Vec3(1., 0., 0.), 0.),
+                &Interval::new(0.001, f64::INFINITY),
+            )
+            .unwrap();
+        assert!(!rec.front_face && (rec.normal - Vec3(-1., 0., 0.)).length() < 1e-9);
+
+        // Rays starting inside find only the way out.
+        let ray = Ray::new(Vec3(0., 0., 0.), Vec3(1., 0., 0.), 0.);
+        assert_hits(ts(&union, &ray), &[(1.5, false)]);
+        assert_hits(ts(&difference, &ray), &[(0.5, false)]);
+        // Rays missing one operand see only the other.
+        let ray = Ray::new(Vec3(-0.5, -3., 0.), Vec3(0., 1., 0.), 0.);
+        let root3 = 3f64.sqrt() / 2.;
+        assert_hits(
+            ts(&difference, &ray),
+            &[(3. - root3, true), (3. + root3, false)],
+        );
+        assert!(ts(&intersection, &ray).is_empty());
+
+        // A short interval still sees the operands beyond its end.
+        let ray = Ray::new(Vec3(0.8, 0., 0.), Vec3(-1., 0., 0.), 0.);
+        let rec = difference.hit(&ray, &Interval::new(0.001, 0.5)).unwrap();
+        assert!((rec.t - 0.3).abs() < 1e-9 && rec.front_face);
+        assert!(difference.hit(&ray, &Interval::new(0.001, 0.2)).is_none());
+    }
+
+    #[test]
+    fn nested_csg_works() {
+        // A bead: a sphere with a hole through it, unioned with a smaller
+        // sphere in the hole.
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let mut hole = HittableList::new();
+        let mesh = Rc::new(TriangleMesh::cuboid(
+            Vec3(-0.5, -0.5, -2.),
+            Vec3(0.5, 0.5, 2.),
+        ));
+        for triangle in mesh.triangles(&mat) {
+            hole.add(triangle);
+        }
+        let hole: Rc<dyn Hittable> = Rc::new(hole);
+        let bead: Rc<dyn Hittable> = Rc::new(Csg::difference(sphere(Vec3(0., 0., 0.), 1.), hole));
+        let along = Ray::new(Vec3(0., 0., -3.), Vec3(0., 0., 1.), 0.);
+        assert!(ts(bead.as_ref(), &along).is_empty());
+        let across = Ray::new(Vec3(-3., 0., 0.), Vec3(1., 0., 0.), 0.);
+        assert_hits(
+            ts(bead.as_ref(), &across),
+            &[(2., true), (2.5, false), (3.5, true), (4., false)],
+        );
+
+        let filled = Csg::union(bead, sphere(Vec3(0., 0., 0.), 0.25));
+        assert_hits(ts(&filled, &along), &[(2.75, true), (3.25, false)]);
+        assert_hits(
+            ts(&filled, &across),
+            &[
+                (2., true),
+                (2.5, false),
+                (2.75, true),
+                (3.25, false),
+                (3.5, true),
+                (4., false),
+            ],
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Edge-avoiding à-trous wavelet denoiser.
+//!
+//! Filters the averaged color of a framebuffer with a sequence of
+//! increasingly sparse 5x5 B3-spline kernels, as in Dammertz et al.,
+//! "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination
+//! Filtering" (2010). Edge-stopping weights on color and on the normal and
+//! depth AOVs keep the filter from blurring across geometric edges, and the
+//! color is divided by the albedo AOV before filtering, so that texture
+//! detail is not smoothed away with the noise.
+
+use crate::{aov::Aov, color::Color, framebuffer::Framebuffer, vec3::Vec3};
+
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Albedo channels below this are not divided out, to avoid amplifying
+/// noise on nearly black surfaces.
+const MIN_ALBEDO: f64 = 1e-3;
+
+pub struct Denoiser {
+    pub strength: f64,     // Width of the color edge-stopping function; 0 disables
+    pub iterations: u32,   // Filter passes; the last spans 2^(n+1) + 1 pixels
+    pub sigma_normal: f64, // Width of the normal edge-stopping function
+    pub sigma_depth: f64,  // Width of the relative depth edge-stopping function
+}
+
+impl Denoiser {
+    pub fn new(strength: f64) -> Self {
+        Self {
+            strength,
+            iterations: 5,
+            sigma_normal: 0.3,
+            sigma_depth: 0.1,
+        }
+    }
+
+    /// Returns a denoised copy of the framebuffer's averaged color, guided
+    /// by its normal, depth and albedo AOVs as far as they are available.
+    pub fn denoise(&self, fb: &Framebuffer) -> Framebuffer {
+        let width = fb.width();
+        let height = fb.height();
+        if width == 0 || height == 0 {
+            return Framebuffer::from_pixels(width, height, &[]);
+        }
+        let guide = |aov: Aov| {
+            fb.aovs()
+                .filter(|aovs| aovs.pixel(aov, 0, 0).is_some())
+                .map(|aovs| {
+                    (0..height)
+                        .flat_map(|j| (0..width).map(move |i| (i, j)))
+                        .map(|(i, j)| aovs.pixel(aov, i, j).unwrap())
+                        .collect::<Vec<_>>()
+                })
+        };
+        let normals = guide(Aov::Normal);
+        let depths = guide(Aov::Depth);
+        let albedos = guide(Aov::Albedo);
+
+        let mut color: Vec<Color> = (0..height)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| fb.pixel(i, j))
+            .collect();
+        if let Some(albedos) = &albedos {
+            for (c, a) in color.iter_mut().zip(albedos) {
+                *c = demodulate(*c, *a);
+            }
+        }
+
+        if self.strength > 0. {
+            let mut sigma_color = self.strength;
+            for iteration in 0..self.iterations {
+                let step = 1 << iteration;
+                color = self.filter(
+                    &color,
+                    width,
+                    height,
+                    step,
+                    sigma_color,
+                    normals.as_deref(),
+                    depths.as_deref(),
+                );
+                sigma_color /= 2.;
+            }
+        }
+
+        if let Some(albedos) = &albedos {
+            for (c, a) in color.iter_mut().zip(albedos) {
+                *c = remodulate(*c, *a);
+            }
+        }
+        Framebuffer::from_pixels(width, height, &color)
+    }
+
+    /// Applies one à-trous pass with the kernel taps step pixels apart.
+    #[allow(clippy::too_many_arguments)]
+    fn filter(
+        &self,
+        color: &[Color],
+        width: u32,
+        height: u32,
+        step: i64,
+        sigma_color: f64,
+        normals: Option<&[Vec3]>,
+        depths: Option<&[Vec3]>,
+    ) -> Vec<Color> {
+        let index = |i: i64, j: i64| (j * width as i64 + i) as usize;
+        let mut out = Vec::with_capacity(color.len());
+
+        for j in 0..height as i64 {
+            for i in 0..width as i64 {
+                let p = index(i, j);
+                let mut sum = Color::new(0., 0., 0.);
+                let mut weight_sum = 0.;
+
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let qi = i + (dx as i64 - 2) * step;
+                        let qj = j + (dy as i64 - 2) * step;
+                        if qi < 0 || qj < 0 || qi >= width as i64 || qj >= height as i64 {
+                            continue;
+                        }
+                        let q = index(qi, qj);
+
+                        let mut w = kx * ky;
+                        w *= (-(color[p] - color[q]).length_squared()
+                            / (sigma_color * sigma_color))
+                            .exp();
+                        if let Some(n) = normals {
+                            let dn = (n[p] - n[q]).length_squared();
+                            w *= (-dn / (self.sigma_normal * self.sigma_normal)).exp();
+                        }
+                        if let Some(z) = depths {
+                            let (zp, zq) = (z[p].x(), z[q].x());
+                            let dz = (zp - zq).abs() / (zp.abs().max(zq.abs()) + 1e-6);
+                            w *= (-dz / (self.sigma_depth * step as f64)).exp();
+                        }
+
+                        sum += w * color[q];
+                        weight_sum += w;
+                    }
+                }
+                out.push(sum / weight_sum);
+            }
+        }
+        out
+    }
+}
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    let div = |c: f64, a: f64| if a > MIN_ALBEDO { c / a } else { c };
+    Color::new(
+        div(color.x(), albedo.x()),
+        div(color.y(), albedo.y()),
+        div(color.z(), albedo.z()),
+    )
+}
+
+fn remodulate(color: Color, albedo: Color) -> Color {
+    let mul = |c: f64, a: f64| if a > MIN_ALBEDO { c * a } else { c };
+    Color::new(
+        mul(color.x(), albedo.x()),
+        mul(color.y(), albedo.y()),
+        mul(color.z(), albedo.z()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::{AovBuffers, AovSample};
+
+    /// Noisy gray image whose left and right halves face different ways
+    /// and differ in brightness.
+    fn noisy_split_image() -> Framebuffer {
+        let aovs = AovBuffers::new(16, 16, &[Aov::Normal, Aov::Depth, Aov::Albedo], &[]);
+        let mut fb = Framebuffer::new(16, 16).with_aovs(aovs);
+        let mut sample = AovSample::new(0);
+        sample.depth = 5.;
+        sample.albedo = Color::new(1., 1., 1.);
+        for j in 0..16 {
+            for i in 0..16 {
+                let left = i < 8;
+                sample.normal = if left {
+                    Vec3(1., 0., 0.)
+                } else {
+                    Vec3(0., 0., 1.)
+                };
+                let base = if left { 0.2 } else { 0.8 };
+                let noise = 0.2 * (rand::random::<f64>() - 0.5);
+                fb.add(i, j, Color::new(base + noise, base + noise, base + noise));
+                fb.add_aovs(i, j, &sample);
+            }
+        }
+        fb.end_pass();
+        fb
+    }
+
+    fn variance(fb: &Framebuffer, columns: std::ops::Range<u32>) -> f64 {
+        let values: Vec<f64> = (0..16)
+            .flat_map(|j| columns.clone().map(move |i| (i, j)))
+            .map(|(i, j)| fb.pixel(i, j).x())
+            .collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn denoise_reduces_noise() {
+        let fb = noisy_split_image();
+        let denoised = Denoiser::new(0.5).denoise(&fb);
+        assert!(variance(&denoised, 0..8) < 0.25 * variance(&fb, 0..8));
+        assert!(variance(&denoised, 8..16) < 0.25 * variance(&fb, 8..16));
+    }
+
+    #[test]
+    fn denoise_keeps_edges() {
+        let fb = noisy_split_image();
+        let denoised = Denoiser::new(0.5).denoise(&fb);
+        for j in 0..16 {
+            assert!((denoised.pixel(7, j).x() - 0.2).abs() < 0.1);
+            assert!((denoised.pixel(8, j).x() - 0.8).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn denoise_keeps_empty_image() {
+        let fb = Framebuffer::new(0, 0).with_aovs(AovBuffers::new(0, 0, &Aov::ALL, &[]));
+        let denoised = Denoiser::new(0.5).denoise(&fb);
+        assert_eq!((denoised.width(), denoised.height()), (0, 0));
+    }
+
+    #[test]
+    fn zero_strength_keeps_image() {
+        let fb = noisy_split_image();
+        let denoised = Denoiser::new(0.).denoise(&fb);
+        for j in 0..16 {
+            for i in 0..16 {
+                assert!((denoised.pixel(i, j) - fb.pixel(i, j)).length() < 1e-12);
+            }
+        }
+    }
+}
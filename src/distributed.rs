@@ -0,0 +1,515 @@
+//! Distributed rendering over TCP.
+//!
+//! A coordinator splits the image into work items, each a tile together
+//! with a number of samples per pixel, and hands them out to any number of
+//! workers. Workers rebuild the scene from a description string sent by
+//! the coordinator, render the work items they receive with
+//! `Camera::render_tile` and stream back the per-pixel sample sums, which
+//! the coordinator merges into a `Framebuffer`. Work items held by a worker
+//! that disconnects, or that doesn't answer within the coordinator's
+//! timeout, are handed out again.
+//!
+//! The protocol consists of newline terminated text messages, except for
+//! the tile buffers which follow a `RESULT` line as little-endian `f32`
+//! r, g, b triples laid out row by row:
+//!
+//! ```text
+//! coordinator -> worker   SCENE <description>
+//! coordinator -> worker   TILE <x> <y> <width> <height> <samples>
+//! worker -> coordinator   RESULT <x> <y> <width> <height> <samples>
+//! coordinator -> worker   DONE
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    framebuffer::{Framebuffer, Tile},
+    hittable::HittableList,
+};
+
+/// How long idle threads wait before polling for new connections or work.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a worker may take to answer a work item by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A tile of the image and the number of samples per pixel to render for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorkItem {
+    pub tile: Tile,
+    pub samples: u32,
+}
+
+impl WorkItem {
+    /// Splits region into tiles of at most tile_size x tile_size pixels and
+    /// the samples into ranges of at most sample_chunk samples, so that
+    /// every pixel of region receives exactly `samples` samples.
+    pub fn split(region: &Tile, tile_size: u32, samples: u32, sample_chunk: u32) -> Vec<Self> {
+        assert!(tile_size > 0 && sample_chunk > 0);
+        let mut items = Vec::new();
+        for y in (region.y..region.y + region.height).step_by(tile_size as usize) {
+            for x in (region.x..region.x + region.width).step_by(tile_size as usize) {
+                let width = tile_size.min(region.x + region.width - x);
+                let height = tile_size.min(region.y + region.height - y);
+                let mut remaining = samples;
+                while remaining > 0 {
+                    let chunk = sample_chunk.min(remaining);
+                    items.push(Self {
+                        tile: Tile::new(x, y, width, height),
+                        samples: chunk,
+                    });
+                    remaining -= chunk;
+                }
+            }
+        }
+        items
+    }
+
+    fn to_message(self, keyword: &str) -> String {
+        let t = self.tile;
+        format!(
+            "{} {} {} {} {} {}\n",
+            keyword, t.x, t.y, t.width, t.height, self.samples
+        )
+    }
+
+    fn from_message(line: &str, keyword: &str) -> io::Result<Self> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some(keyword) {
+            return Err(protocol_error(line));
+        }
+        let numbers: Vec<u32> = words
+            .map(|w| w.parse().map_err(|_| protocol_error(line)))
+            .collect::<io::Result<_>>()?;
+        match numbers[..] {
+            [x, y, width, height, samples] => Ok(Self {
+                tile: Tile::new(x, y, width, height),
+                samples,
+            }),
+            _ => Err(protocol_error(line)),
+        }
+    }
+}
+
+fn protocol_error(line: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected message: {:?}", line.trim_end()),
+    )
+}
+
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed",
+        ));
+    }
+    Ok(line)
+}
+
+fn write_buffer<W: Write>(writer: &mut W, buf: &[Color]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(buf.len() * 12);
+    for c in buf {
+        for channel in [c.x(), c.y(), c.z()] {
+            bytes.extend_from_slice(&(channel as f32).to_le_bytes());
+        }
+    }
+    writer.write_all(&bytes)
+}
+
+fn read_buffer<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<Color>> {
+    let mut bytes = vec![0u8; len * 12];
+    reader.read_exact(&mut bytes)?;
+    let channel = |k: usize| f32::from_le_bytes(bytes[4 * k..4 * k + 4].try_into().unwrap()) as f64;
+    Ok((0..len)
+        .map(|p| Color::new(channel(3 * p), channel(3 * p + 1), channel(3 * p + 2)))
+        .collect())
+}
+
+/// Hands out work items to workers connecting over TCP and merges their
+/// results.
+pub struct Coordinator {
+    listener: TcpListener,
+    timeout: Duration, // Time a worker may take to answer, and for workers to return
+}
+
+impl Coordinator {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets how long a worker may take to return a work item before the
+    /// item is handed to another worker. Once workers have connected, the
+    /// render also fails if none is left for this long.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves the scene description to every worker that connects and
+    /// distributes the work items among them until all results have been
+    /// merged into a width x height framebuffer.
+    ///
+    /// Every pixel covered by the work items must receive the same number
+    /// of samples in total, which becomes the framebuffer's pass count.
+    pub fn run(
+        &self,
+        scene: &str,
+        width: u32,
+        height: u32,
+        items: Vec<WorkItem>,
+    ) -> io::Result<Framebuffer> {
+        let total = items.len();
+        let mut fb = Framebuffer::new(width, height);
+        let mut samples = vec![0u32; (width * height) as usize];
+
+        let queue = Arc::new(Mutex::new(VecDeque::from(items)));
+        let finished = Arc::new(AtomicBool::new(false));
+        let connected = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        let mut done = 0;
+        let mut any_connected = false;
+        let mut alone_since = None;
+        eprint!("\rWork items remaining: {} ", total);
+        while done < total {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let scene = scene.to_string();
+                    let queue = Arc::clone(&queue);
+                    let finished = Arc::clone(&finished);
+                    let connected = Arc::clone(&connected);
+                    let tx = tx.clone();
+                    let timeout = self.timeout;
+                    connected.fetch_add(1, Ordering::Relaxed);
+                    any_connected = true;
+                    thread::spawn(move || {
+                        let result = serve_worker(stream, &scene, &queue, &finished, &tx, timeout);
+                        connected.fetch_sub(1, Ordering::Relaxed);
+                        result
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            // Without workers the render can't finish; give new ones a
+            // chance to connect before giving up.
+            if any_connected && connected.load(Ordering::Relaxed) == 0 {
+                let since = *alone_since.get_or_insert_with(Instant::now);
+                if since.elapsed() > self.timeout {
+                    eprintln!();
+                    return Err(io::Error::new(
+                        ErrorKind::NotConnected,
+                        format!("all workers left with {} work items to go", total - done),
+                    ));
+                }
+            } else {
+                alone_since = None;
+            }
+
+            if let Ok((item, buf)) = rx.recv_timeout(POLL_INTERVAL) {
+                fb.add_tile(&item.tile, &buf);
+                let t = item.tile;
+                for j in t.y..t.y + t.height {
+                    for i in t.x..t.x + t.width {
+                        samples[(j * width + i) as usize] += item.samples;
+                    }
+                }
+                done += 1;
+                eprint!("\rWork items remaining: {} ", total - done);
+            }
+        }
+        finished.store(true, Ordering::Relaxed);
+        eprint!("\rDone.                        \n");
+
+        let passes = samples
+            .iter()
+            .copied()
+            .filter(|&s| s > 0)
+            .max()
+            .unwrap_or(0);
+        fb.end_passes(passes);
+        Ok(fb)
+    }
+}
+
+/// Feeds work items to one connected worker until the render is finished.
+/// If the worker fails, disconnects or takes longer than timeout to
+/// answer, the item it was working on is put back into the queue for
+/// another worker to pick up.
+fn serve_worker(
+    stream: TcpStream,
+    scene: &str,
+    queue: &Mutex<VecDeque<WorkItem>>,
+    finished: &AtomicBool,
+    results: &mpsc::Sender<(WorkItem, Vec<Color>)>,
+    timeout: Duration,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    writer.write_all(format!("SCENE {}\n", scene).as_bytes())?;
+
+    loop {
+        let item = loop {
+            if finished.load(Ordering::Relaxed) {
+                return writer.write_all(b"DONE\n");
+            }
+            if let Some(item) = queue.lock().unwrap().pop_front() {
+                break item;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        match exchange(&mut writer, &mut reader, item) {
+            Ok(buf) => {
+                if results.send((item, buf)).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                queue.lock().unwrap().push_back(item);
+                eprintln!("\nWorker disconnected: {}", e);
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Sends one work item to a worker and reads back its tile buffer.
+fn exchange<W: Write, R: BufRead>(
+    writer: &mut W,
+    reader: &mut R,
+    item: WorkItem,
+) -> io::Result<Vec<Color>> {
+    writer.write_all(item.to_message("TILE").as_bytes())?;
+    let line = read_message(reader)?;
+    if WorkItem::from_message(&line, "RESULT")? != item {
+        return Err(protocol_error(&line));
+    }
+    read_buffer(reader, item.tile.area())
+}
+
+/// Connects to a coordinator, builds the scene it serves with build_scene
+/// and renders work items until the coordinator is done.
+pub fn run_worker<A, F>(addr: A, build_scene: F) -> io::Result<()>
+where
+    A: ToSocketAddrs,
+    F: FnOnce(&str) -> (HittableList, Camera),
+{
+    let stream = TcpStream::connect(addr)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let line = read_message(&mut reader)?;
+    let scene = line
+        .strip_prefix("SCENE ")
+        .ok_or_else(|| protocol_error(&line))?;
+    let (world, cam) = build_scene(scene.trim_end());
+
+    loop {
+        let line = read_message(&mut reader)?;
+        if line.trim_end() == "DONE" {
+            return Ok(());
+        }
+        let item = WorkItem::from_message(&line, "TILE")?;
+        if !item.tile.fits(cam.image_width, cam.image_height()) {
+            return Err(protocol_error(&line));
+        }
+
+        let mut buf = vec![Color::new(0., 0., 0.); item.tile.area()];
+        cam.render_tile(&world, &item.tile, item.samples, &mut buf);
+
+        writer.write_all(item.to_message("RESULT").as_bytes())?;
+        write_buffer(&mut writer, &buf)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Vec3;
+
+    fn test_scene(description: &str) -> (HittableList, Camera) {
+        assert_eq!(description, "test");
+        let cam = Camera::new(
+            1.,
+            8,
+            1,
+            2,
+            90.,
+            Vec3(0., 0., 0.),
+            Vec3(0., 0., -1.),
+            Vec3(0., 1., 0.),
+            1.,
+            0.,
+        );
+        (HittableList::new(), cam)
+    }
+
+    #[test]
+    fn split_covers_region() {
+        let region = Tile::new(1, 2, 5, 3);
+        let items = WorkItem::split(&region, 2, 5, 2);
+        assert_eq!(items.len(), 3 * 2 * 3);
+
+        let mut samples = vec![0; 8 * 8];
+        for item in items {
+            let t = item.tile;
+            assert!(t.x >= 1 && t.x + t.width <= 6 && t.y >= 2 && t.y + t.height <= 5);
+            for j in t.y..t.y + t.height {
+                for i in t.x..t.x + t.width {
+                    samples[j as usize * 8 + i as usize] += item.samples;
+                }
+            }
+        }
+        assert_eq!(samples.iter().filter(|&&s| s == 5).count(), 15);
+        assert_eq!(samples.iter().filter(|&&s| s == 0).count(), 64 - 15);
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let item = WorkItem {
+            tile: Tile::new(1, 2, 3, 4),
+            samples: 5,
+        };
+        let msg = item.to_message("TILE");
+        assert_eq!(msg, "TILE 1 2 3 4 5\n");
+        assert_eq!(WorkItem::from_message(&msg, "TILE").unwrap(), item);
+        assert!(WorkItem::from_message(&msg, "RESULT").is_err());
+        assert!(WorkItem::from_message("TILE 1 2\n", "TILE").is_err());
+    }
+
+    #[test]
+    fn workers_render_on_localhost() {
+        let coordinator = Coordinator::bind("127.0.0.1:0").unwrap();
+        let addr = coordinator.local_addr().unwrap();
+
+        let workers: Vec<_> = (0..2)
+            .map(|_| thread::spawn(move || run_worker(addr, test_scene)))
+            .collect();
+
+        let items = WorkItem::split(&Tile::new(0, 0, 8, 8), 3, 4, 2);
+        let fb = coordinator.run("test", 8, 8, items).unwrap();
+        for w in workers {
+            w.join().unwrap().unwrap();
+        }
+
+        assert_eq!(fb.passes(), 4);
+        for j in 0..8 {
+            for i in 0..8 {
+                assert!(fb.pixel(i, j).length() > 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn work_of_disconnected_worker_is_reassigned() {
+        let coordinator = Coordinator::bind("127.0.0.1:0").unwrap();
+        let addr = coordinator.local_addr().unwrap();
+
+        // Takes the first work item and disconnects without answering,
+        // before any well-behaved worker connects.
+        let quitter = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream);
+            read_message(&mut reader).unwrap();
+            read_message(&mut reader).unwrap();
+        });
+        let worker = thread::spawn(move || {
+            quitter.join().unwrap();
+            run_worker(addr, test_scene)
+        });
+
+        let items = WorkItem::split(&Tile::new(0, 0, 8, 8), 4, 2, 2);
+        let fb = coordinator.run("test", 8, 8, items).unwrap();
+        worker.join().unwrap().unwrap();
+
+        assert_eq!(fb.passes(), 2);
+        for j in 0..8 {
+            for i in 0..8 {
+                assert!(fb.pixel(i, j).length() > 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn work_of_unresponsive_worker_is_reassigned() {
+        let coordinator = Coordinator::bind("127.0.0.1:0")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100));
+        let addr = coordinator.local_addr().unwrap();
+
+        // Takes the first work item and never answers, staying connected
+        // until the coordinator gives up on it.
+        let (took_item, item_taken) = mpsc::channel();
+        let sleeper = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream);
+            read_message(&mut reader).unwrap();
+            read_message(&mut reader).unwrap();
+            took_item.send(()).unwrap();
+            assert!(read_message(&mut reader).is_err());
+        });
+        let worker = thread::spawn(move || {
+            item_taken.recv().unwrap();
+            run_worker(addr, test_scene)
+        });
+
+        let items = WorkItem::split(&Tile::new(0, 0, 8, 8), 4, 2, 2);
+        let fb = coordinator.run("test", 8, 8, items).unwrap();
+        worker.join().unwrap().unwrap();
+        sleeper.join().unwrap();
+        assert_eq!(fb.passes(), 2);
+    }
+
+    #[test]
+    fn render_fails_without_workers() {
+        let coordinator = Coordinator::bind("127.0.0.1:0")
+            .unwrap()
+            .with_timeout(Duration::from_millis(100));
+        let addr = coordinator.local_addr().unwrap();
+        let quitter = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            read_message(&mut BufReader::new(stream)).unwrap();
+        });
+
+        let items = WorkItem::split(&Tile::new(0, 0, 8, 8), 4, 2, 2);
+        let result = coordinator.run("test", 8, 8, items);
+        assert!(result.is_err_and(|e| e.kind() == ErrorKind::NotConnected));
+        quitter.join().unwrap();
+    }
+
+    #[test]
+    fn worker_rejects_tiles_outside_image() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let worker = thread::spawn(move || run_worker(addr, test_scene));
+
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"SCENE test\nTILE 6 0 4 1 1\n").unwrap();
+        let err = worker.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
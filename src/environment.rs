@@ -0,0 +1,213 @@
+//! Light arriving from infinitely far away in every direction, given by an
+//! image in latitude-longitude layout, such as an HDR photograph of a sky.
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::{
+    color::Color,
+    light::{Light, LightSample},
+    sampling::Distribution2D,
+    util::PFM,
+    vec3::Vec3,
+};
+
+/// Light arriving along rays that escape the scene, which can also be
+/// sampled from surfaces like any other light.
+pub trait Environment: Light {
+    /// Radiance arriving from direction dir.
+    fn radiance(&self, dir: Vec3) -> Color;
+}
+
+/// Radiance from each direction, looked up in an equirectangular image
+/// whose top row is straight up (+y) and whose columns go around y like the
+/// u coordinate of a sphere. Samples pick directions in proportion to the
+/// radiance so that small bright regions like the sun are found directly.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,           // Radiance, top row first
+    distribution: Distribution2D, // Over the image, by luminance times sin θ
+}
+
+impl EnvironmentMap {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        assert!(width > 0 && height > 0);
+        let (width, height) = (width as usize, height as usize);
+
+        // Rows near the poles cover less of the sphere, in proportion to
+        // the sine of their angle from the pole.
+        let weights: Vec<f64> = pixels
+            .iter()
+            .enumerate()
+            .map(|(k, c)| {
+                let theta = PI * ((k / width) as f64 + 0.5) / height as f64;
+                c.luminance().max(0.) * theta.sin()
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            distribution: Distribution2D::new(&weights, width, height),
+            pixels,
+        }
+    }
+
+    /// Loads the radiance from a PFM file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let pfm = PFM::read(BufReader::new(File::open(path)?))?;
+        Ok(Self::new(pfm.width(), pfm.height(), pfm.pixels().to_vec()))
+    }
+
+    /// Image coordinates (s, t) in [0, 1]² of a unit direction.
+    fn coords(dir: Vec3) -> (f64, f64) {
+        // Unlike acos(y), this keeps its precision near the poles.
+        let theta = dir.x().hypot(dir.z()).atan2(dir.y());
+        let phi = (-dir.z()).atan2(dir.x()) + PI;
+        (phi / (2. * PI), theta / PI)
+    }
+
+    /// Unit direction at image coordinates (s, t).
+    pub fn direction(s: f64, t: f64) -> Vec3 {
+        let (theta, phi) = (PI * t, 2. * PI * s - PI);
+        Vec3(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            -theta.sin() * phi.sin(),
+        )
+    }
+}
+
+impl Environment for EnvironmentMap {
+    /// Radiance of the pixel dir falls in.
+    fn radiance(&self, dir: Vec3) -> Color {
+        let (s, t) = Self::coords(dir.unit());
+        let i = ((s * self.width as f64) as usize).min(self.width - 1);
+        let j = ((t * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _: Vec3) -> Option<LightSample> {
+        let ((s, t), pdf_st) = self
+            .distribution
+            .sample(rand::random::<f64>(), rand::random::<f64>());
+        let wi = Self::direction(s, t);
+
+        // The image maps onto the sphere stretched by 2π² sin θ.
+        let sin_theta = (PI * t).sin();
+        if pdf_st == 0. || sin_theta <= 0. {
+            return None;
+        }
+        let pdf = pdf_st / (2. * PI * PI * sin_theta);
+        Some(LightSample {
+            wi,
+            distance: f64::INFINITY,
+            radiance: self.radiance(wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn pdf(&self, _: Vec3, wi: Vec3) -> f64 {
+        let (s, t) = Self::coords(wi.unit());
+        let sin_theta = (PI * t).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        self.distribution.pdf(s, t) / (2. * PI * PI * sin_theta)
+    }
+
+    /// Light from every direction falling on a disk of the scene's size.
+    fn power(&self, scene_radius: f64) -> Color {
+        let mut total = Color::new(0., 0., 0.);
+        for (k, c) in self.pixels.iter().enumerate() {
+            let theta = PI * ((k / self.width) as f64 + 0.5) / self.height as f64;
+            total += theta.sin() * *c;
+        }
+        let solid_angle = 2. * PI * PI / (self.width * self.height) as f64;
+        PI * scene_radius * scene_radius * solid_angle * total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dim sky with a small bright sun near the horizon.
+    fn sky_with_sun() -> EnvironmentMap {
+        let (width, height) = (64, 32);
+        let pixels = (0..width * height)
+            .map(|k| match (k % width, k / width) {
+                (40, 12) => Color::new(1000., 900., 800.),
+                (_, j) if j < height / 2 => Color::new(0.3, 0.5, 1.),
+                _ => Color::new(0.1, 0.1, 0.1),
+            })
+            .collect();
+        EnvironmentMap::new(width, height, pixels)
+    }
+
+    #[test]
+    fn directions_round_trip() {
+        for (s, t) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.25, 0.99)] {
+            let (s1, t1) = EnvironmentMap::coords(EnvironmentMap::direction(s, t));
+            assert!((s1 - s).abs() < 1e-12 && (t1 - t).abs() < 1e-12);
+        }
+        assert!((EnvironmentMap::direction(0.3, 0.) - Vec3(0., 1., 0.)).length() < 1e-12);
+    }
+
+    #[test]
+    fn environment_pdf_integrates_to_one() {
+        // Midpoint rule over the sphere in (θ, φ).
+        let env = sky_with_sun();
+        let n = 400;
+        let mut total = 0.;
+        for j in 0..n {
+            let theta = PI * (j as f64 + 0.5) / n as f64;
+            for i in 0..2 * n {
+                let phi = PI * (i as f64 + 0.5) / n as f64;
+                let wi = Vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += env.pdf(Vec3(0., 0., 0.), wi) * theta.sin();
+            }
+        }
+        total *= (PI / n as f64) * (PI / n as f64);
+        assert!((total - 1.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn environment_sampling_works() {
+        let env = sky_with_sun();
+        let point = Vec3(0., 0., 0.);
+        let n = 100_000;
+        let mut total = Color::new(0., 0., 0.);
+        let mut sun = 0;
+        for _ in 0..n {
+            let sample = env.sample(point).unwrap();
+            let pdf = sample.pdf.unwrap();
+            assert!((pdf - env.pdf(point, sample.wi)).abs() <= 1e-9 * pdf);
+            assert!((sample.radiance * pdf - env.radiance(sample.wi)).length() < 1e-9);
+            total += sample.radiance;
+            if env.radiance(sample.wi).x() > 100. {
+                sun += 1;
+            }
+        }
+
+        // Most samples find the sun, and their estimate of the light
+        // arriving from all directions matches the image's.
+        assert!(sun > n / 2);
+        let mut expected = Color::new(0., 0., 0.);
+        for (k, c) in env.pixels.iter().enumerate() {
+            let theta = PI * ((k / env.width) as f64 + 0.5) / env.height as f64;
+            let solid_angle = 2. * PI * PI * theta.sin() / (env.width * env.height) as f64;
+            expected += solid_angle * *c;
+        }
+        assert!((total / n as f64 - expected).length() < 1e-2 * expected.length());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    aov::{AovBuffers, AovSample},
+    color::Color,
+    util::{PFM, PPM},
+};
+
+/// Rectangular region of an image in pixel coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Converts a window given in normalized [0, 1] image coordinates,
+    /// with x0, y0 the upper left and x1, y1 the lower right corner, to
+    /// the smallest tile of an image_width x image_height image covering it.
+    pub fn from_normalized(
+        x0: f64,
+        y0: f64,
+        x1: f64,
+        y1: f64,
+        image_width: u32,
+        image_height: u32,
+    ) -> Self {
+        let to_pixel = |t: f64, size: u32| t.clamp(0., 1.) * size as f64;
+        let i0 = to_pixel(x0.min(x1), image_width).floor() as u32;
+        let j0 = to_pixel(y0.min(y1), image_height).floor() as u32;
+        let i1 = to_pixel(x0.max(x1), image_width).ceil() as u32;
+        let j1 = to_pixel(y0.max(y1), image_height).ceil() as u32;
+        Self::new(i0, j0, i1 - i0, j1 - j0)
+    }
+
+    /// Number of pixels in the tile.
+    pub fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Returns true if the tile lies within a width x height image.
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        // Written so as not to overflow on tiles read from the network.
+        self.x <= width
+            && self.width <= width - self.x
+            && self.y <= height
+            && self.height <= height - self.y
+    }
+}
+
+/// Floating point accumulation buffer for progressive rendering.
+///
+/// Holds the running sum of radiance samples for every pixel together
+/// with the number of completed passes, so that passes can be added one
+/// at a time and the average resolved at any point. Optionally carries
+/// AOV buffers that accumulate alongside the color.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    sum: Vec<Color>,
+    passes: u32,
+    aovs: Option<AovBuffers>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            sum: vec![Color::new(0., 0., 0.); (width * height) as usize],
+            passes: 0,
+            aovs: None,
+        }
+    }
+
+    /// Creates a framebuffer holding one pass of the given pixels, laid out
+    /// row by row.
+    pub fn from_pixels(width: u32, height: u32, pixels: &[Color]) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        let mut fb = Self::new(width, height);
+        fb.sum.copy_from_slice(pixels);
+        fb.passes = 1;
+        fb
+    }
+
+    /// Attaches AOV buffers of the same size to the framebuffer.
+    pub fn with_aovs(mut self, aovs: AovBuffers) -> Self {
+        assert_eq!((aovs.width(), aovs.height()), (self.width, self.height));
+        self.aovs = Some(aovs);
+        self
+    }
+
+    pub fn aovs(&self) -> Option<&AovBuffers> {
+        self.aovs.as_ref()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of completed passes, i.e. the samples per pixel achieved so far.
+    pub fn passes(&self) -> u32 {
+        self.passes
+    }
+
+    /// Adds one sample to the running sum of pixel i, j.
+    pub fn add(&mut self, i: u32, j: u32, color: Color) {
+        self.sum[(j * self.width + i) as usize] += color;
+    }
+
+    /// Adds one sample to the AOV buffers at pixel i, j, if there are any.
+    pub fn add_aovs(&mut self, i: u32, j: u32, sample: &AovSample) {
+        if let Some(aovs) = &mut self.aovs {
+            aovs.add(i, j, sample);
+        }
+    }
+
+    /// Adds a buffer of per-pixel sample sums, laid out row by row, to
+    /// the pixels covered by tile.
+    pub fn add_tile(&mut self, tile: &Tile, buf: &[Color]) {
+        assert!(tile.fits(self.width, self.height));
+        assert_eq!(buf.len(), tile.area());
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                self.add(tile.x + i, tile.y + j, buf[(j * tile.width + i) as usize]);
+            }
+        }
+    }
+
+    /// Marks the end of a pass over every pixel.
+    pub fn end_pass(&mut self) {
+        self.end_passes(1);
+    }
+
+    /// Marks the end of n passes, for sums of several samples per pixel
+    /// added at once.
+    pub fn end_passes(&mut self, n: u32) {
+        self.passes += n;
+        if let Some(aovs) = &mut self.aovs {
+            aovs.end_passes(n);
+        }
+    }
+
+    /// Returns the average of all samples accumulated for pixel i, j.
+    pub fn pixel(&self, i: u32, j: u32) -> Color {
+        if self.passes == 0 {
+            return Color::new(0., 0., 0.);
+        }
+        self.sum[(j * self.width + i) as usize] / self.passes as f64
+    }
+
+    /// Returns a new framebuffer holding only the pixels covered by tile.
+    pub fn crop(&self, tile: &Tile) -> Self {
+        assert!(tile.fits(self.width, self.height));
+        let mut cropped = Self::new(tile.width, tile.height);
+        for j in 0..tile.height {
+            for i in 0..tile.width {
+                cropped.add(
+                    i,
+                    j,
+                    self.sum[((tile.y + j) * self.width + tile.x + i) as usize],
+                );
+            }
+        }
+        cropped.passes = self.passes;
+        cropped.aovs = self.aovs.as_ref().map(|aovs| aovs.crop(tile));
+        cropped
+    }
+
+    pub fn to_ppm(&self) -> PPM {
+        let mut ppm = PPM::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                ppm.push(self.pixel(i, j));
+            }
+        }
+        ppm
+    }
+
+    pub fn to_pfm(&self) -> PFM {
+        let mut pfm = PFM::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                pfm.push(self.pixel(i, j));
+            }
+        }
+        pfm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_averages_passes() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.add(1, 0, Color::new(1., 0., 0.));
+        fb.end_pass();
+        fb.add(1, 0, Color::new(0., 0., 1.));
+        fb.end_pass();
+
+        assert_eq!(fb.passes(), 2);
+        assert_eq!(fb.pixel(0, 0), Color::new(0., 0., 0.));
+        assert_eq!(fb.pixel(1, 0), Color::new(0.5, 0., 0.5));
+    }
+
+    #[test]
+    fn add_tile_works() {
+        let mut fb = Framebuffer::new(3, 3);
+        let tile = Tile::new(1, 1, 2, 1);
+        fb.add_tile(&tile, &[Color::new(1., 1., 1.), Color::new(2., 2., 2.)]);
+        fb.end_pass();
+
+        assert_eq!(fb.pixel(0, 1), Color::new(0., 0., 0.));
+        assert_eq!(fb.pixel(1, 1), Color::new(1., 1., 1.));
+        assert_eq!(fb.pixel(2, 1), Color::new(2., 2., 2.));
+        assert_eq!(fb.pixel(2, 2), Color::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn crop_works() {
+        let mut fb = Framebuffer::new(3, 2);
+        fb.add(2, 1, Color::new(1., 2., 3.));
+        fb.end_passes(2);
+
+        let cropped = fb.crop(&Tile::new(1, 1, 2, 1));
+        assert_eq!((cropped.width(), cropped.height()), (2, 1));
+        assert_eq!(cropped.passes(), 2);
+        assert_eq!(cropped.pixel(1, 0), Color::new(0.5, 1., 1.5));
+    }
+
+    #[test]
+    fn from_normalized_works() {
+        let tile = Tile::from_normalized(0.25, 0.5, 0.75, 1., 400, 200);
+        assert_eq!(tile, Tile::new(100, 100, 200, 100));
+
+        let tile = Tile::from_normalized(0.9, 0.9, 0.1, 0.1, 10, 10);
+        assert_eq!(tile, Tile::new(1, 1, 8, 8));
+
+        // A window of zero size covers no pixels.
+        let tile = Tile::from_normalized(1., 1., 1., 1., 10, 10);
+        assert_eq!(tile.area(), 0);
+    }
+
+    #[test]
+    fn fits_works() {
+        assert!(Tile::new(1, 2, 3, 4).fits(4, 6));
+        assert!(!Tile::new(1, 2, 4, 4).fits(4, 6));
+        assert!(!Tile::new(5, 0, 0, 0).fits(4, 6));
+        assert!(!Tile::new(1, 0, u32::MAX, 1).fits(4, 6));
+        assert!(!Tile::new(0, u32::MAX, 1, 2).fits(4, 6));
+    }
+
+    #[test]
+    fn empty_buffer_is_black() {
+        let fb = Framebuffer::new(1, 1);
+        assert_eq!(fb.pixel(0, 0), Color::new(0., 0., 0.));
+    }
+}
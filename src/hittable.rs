@@ -1,7 +1,20 @@
-use crate::{bvh::AABB, material::Material, ray::Ray, util::Interval, vec3::Vec3};
+use crate::{bvh::{AABB, BVHNode}, environment::Environment, light::{Light, SphereLight, TriangleLight}, lightsampler::{LightSampler, LightSelection}, material::Material, mesh::TriangleMesh, ray::{Ray, RayDifferential}, texture::{Footprint, TexCoords, Texture}, util::Interval, vec3::{Onb, Vec3}};
+use std::f64::consts::PI;
+use std::cell::OnceCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::cmp::{ Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
+static NEXT_OBJECT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns a new id for an object, unique within the process. Ids are
+/// handed out in construction order, starting from 1.
+pub fn next_object_id() -> usize {
+    NEXT_OBJECT_ID.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+#[derive(Clone)]
 pub struct HitRecord {
     pub point: Vec3,
     pub normal: Vec3,
@@ -10,6 +23,14 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    pub object_id: usize, // Id of the object hit, 0 if unknown
+    pub motion: Vec3,     // Displacement of the hit point over the shutter interval
+    pub dpdu: Vec3,       // Derivative of the surface point with respect to u
+    pub dpdv: Vec3,       // Derivative of the surface point with respect to v
+    pub object_point: Vec3, // Hit point relative to the object, for textures that move with it
+    pub dndu: Vec3,       // Derivative of the normal with respect to u
+    pub dndv: Vec3,       // Derivative of the normal with respect to v
+    pub footprint: Footprint, // Change of the point and u, v to the neighbouring pixels
 }
 
 impl HitRecord {
@@ -35,14 +56,154 @@ impl HitRecord {
             u: 0.,
             v: 0.,
             front_face,
+            object_id: 0,
+            motion: Vec3(0., 0., 0.),
+            dpdu: Vec3(0., 0., 0.),
+            dpdv: Vec3(0., 0., 0.),
+            object_point: point,
+            dndu: Vec3(0., 0., 0.),
+            dndv: Vec3(0., 0., 0.),
+            footprint: Footprint::ZERO,
         }
     }
+
+    /// Sets the footprint from the differential of the ray that hit: the
+    /// offsets to where the offset rays meet the tangent plane, and the
+    /// changes in u and v that best match them.
+    pub fn set_footprint(&mut self, ray: &Ray) {
+        self.footprint = Footprint::ZERO;
+        let Some(diff) = ray.differential() else {
+            return;
+        };
+        let n = self.normal;
+        let to_plane = |origin: Vec3, dir: Vec3| {
+            let t = (self.point - origin).dot(&n) / dir.dot(&n);
+            (t.is_finite()).then(|| origin + t * dir - self.point)
+        };
+        let (Some(dpdx), Some(dpdy)) = (
+            to_plane(diff.rx_origin, diff.rx_dir),
+            to_plane(diff.ry_origin, diff.ry_dir),
+        ) else {
+            return;
+        };
+
+        // Least squares solution of dpdu du + dpdv dv = dp.
+        let (a00, a01, a11) = (self.dpdu.length_squared(), self.dpdu.dot(&self.dpdv), self.dpdv.length_squared());
+        let det = a00 * a11 - a01 * a01;
+        let to_uv = |dp: Vec3| {
+            if det <= 1e-12 * a00 * a11 {
+                return (0., 0.);
+            }
+            let (b0, b1) = (self.dpdu.dot(&dp), self.dpdv.dot(&dp));
+            ((a11 * b0 - a01 * b1) / det, (a00 * b1 - a01 * b0) / det)
+        };
+        let (dudx, dvdx) = to_uv(dpdx);
+        let (dudy, dvdy) = to_uv(dpdy);
+        self.footprint = Footprint { dpdx, dpdy, dudx, dvdx, dudy, dvdy };
+    }
+
+    /// Differential of the ray reflected from the hit in direction wi,
+    /// following r_in's through a mirror reflection.
+    pub fn reflected_differential(&self, r_in: &Ray, wi: Vec3) -> Option<RayDifferential> {
+        let n = self.normal;
+        let cos = -r_in.dir().unit().dot(&n);
+        self.specular_differential(r_in, wi.unit(), |dwo, dn, dcos| {
+            2. * (dcos * n + cos * dn) - dwo
+        })
+    }
+
+    /// Differential of the ray refracted into direction wi, where eta is
+    /// the ratio of the refractive index on the incident side to that on
+    /// the other.
+    pub fn refracted_differential(&self, r_in: &Ray, wi: Vec3, eta: f64) -> Option<RayDifferential> {
+        let n = self.normal;
+        let wi = wi.unit();
+        let cos_i = -r_in.dir().unit().dot(&n);
+        let cos_t = -wi.dot(&n);
+        let mu = eta * cos_i - cos_t;
+        self.specular_differential(r_in, wi, |dwo, dn, dcos| {
+            mu * dn + (eta - eta * eta * cos_i / cos_t) * dcos * n - eta * dwo
+        })
+    }
+
+    /// Offset rays leaving the hit in direction wi, where dir_change gives
+    /// the change in wi from the changes in the outgoing direction and the
+    /// normal, and in the cosine between them.
+    fn specular_differential(
+        &self,
+        r_in: &Ray,
+        wi: Vec3,
+        dir_change: impl Fn(Vec3, Vec3, f64) -> Vec3,
+    ) -> Option<RayDifferential> {
+        let diff = r_in.differential()?;
+        let n = self.normal;
+        let wo = -r_in.dir().unit();
+        let offset = |dir: Vec3, du: f64, dv: f64| {
+            let dn = du * self.dndu + dv * self.dndv;
+            let dwo = -dir.unit() - wo;
+            wi + dir_change(dwo, dn, dwo.dot(&n) + wo.dot(&dn))
+        };
+        let f = &self.footprint;
+        Some(RayDifferential {
+            rx_origin: self.point + f.dpdx,
+            rx_dir: offset(diff.rx_dir, f.dudx, f.dvdx),
+            ry_origin: self.point + f.dpdy,
+            ry_dir: offset(diff.ry_dir, f.dudy, f.dvdy),
+        })
+    }
+
+    /// Coordinates for looking up textures at the hit.
+    pub fn tex_coords(&self) -> TexCoords {
+        TexCoords {
+            u: self.u,
+            v: self.v,
+            point: self.point,
+            object_point: self.object_point,
+            normal: self.normal,
+            footprint: self.footprint,
+        }
+    }
+
+    /// Surface derivatives at the hit, or an arbitrary pair of unit
+    /// tangents if the primitive has no parametrization there.
+    pub fn tangents(&self) -> (Vec3, Vec3) {
+        if self.dpdu.cross(&self.dpdv).near_zero() {
+            let onb = Onb::new(self.normal);
+            (onb.u, onb.v)
+        } else {
+            (self.dpdu, self.dpdv)
+        }
+    }
+
+    /// Orthonormal shading frame around the normal with u along dpdu, for
+    /// materials that respond differently across and along the surface.
+    pub fn shading_frame(&self) -> Onb {
+        Onb::with_tangent(self.normal, self.tangents().0)
+    }
 }
 
 pub trait Hittable {
     fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord>;
 
     fn bounding_box(&self) -> &AABB;
+
+    /// Every hit along ray within ray_t, nearest first, such as the
+    /// entries and exits of a closed object that solid geometry combines.
+    /// By default found with `hit`, each search starting past the last.
+    fn hits(&self, ray: &Ray, ray_t: &Interval) -> Vec<HitRecord> {
+        let mut hits: Vec<HitRecord> = vec![];
+        let mut min = ray_t.min();
+        while let Some(rec) = self.hit(ray, &Interval::new(min, ray_t.max())) {
+            min = rec.t;
+            hits.push(rec);
+        }
+        hits
+    }
+}
+
+/// Whether a box is bounded along every axis, and not empty.
+fn is_finite(b: &AABB) -> bool {
+    [&b.x, &b.y, &b.z].iter().all(|i| i.size().is_finite() && i.size() >= 0.)
 }
 
 pub fn box_compare(a: &Rc::<dyn Hittable>, b: &Rc::<dyn Hittable>, axis_index: usize) -> Ordering {
@@ -50,24 +211,41 @@ pub fn box_compare(a: &Rc::<dyn Hittable>, b: &Rc::<dyn Hittable>, axis_index: u
     let b_axis_interval = b.bounding_box().axis_interval(axis_index);
 
     if a_axis_interval.min() < b_axis_interval.min() {
-        return Ordering::Less
+        Ordering::Less
     } else {
-        return Ordering::Greater
+        Ordering::Greater
     }
 }
 
 pub struct HittableList {
     pub objects: Vec<Rc<dyn Hittable>>,
-    bbox: AABB
+    lights: Vec<Rc<dyn Light>>, // Lights reached only by sampling them
+    light_groups: Vec<usize>, // Group of each light, which shares one AOV layer
+    pub environment: Option<Rc<dyn Environment>>, // Light from rays escaping the scene, instead of the default sky
+    light_selection: LightSelection, // How direct lighting picks among the lights
+    light_sampler: OnceCell<LightSampler>, // Built for the lights when first used
+    emitters: HashMap<usize, usize>, // Index in lights of each emissive object, by object id
+    bbox: AABB,
+    finite_bbox: AABB, // Bounds of the objects that have them, such as all but planes
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HittableList {
     pub fn new() -> Self {
-        Self { objects: vec![], bbox: AABB::empty()}
+        Self { objects: vec![], lights: vec![], light_groups: vec![], environment: None, light_selection: LightSelection::default(), light_sampler: OnceCell::new(), emitters: HashMap::new(), bbox: AABB::empty(), finite_bbox: AABB::empty()}
     }
 
     pub fn from_hittable(bvh: Rc<dyn Hittable>) -> Self {
-        Self { objects: vec![Rc::clone(&bvh)], bbox: bvh.bounding_box().clone()}
+        let finite_bbox = match is_finite(bvh.bounding_box()) {
+            true => bvh.bounding_box().clone(),
+            false => AABB::empty(),
+        };
+        Self { objects: vec![Rc::clone(&bvh)], lights: vec![], light_groups: vec![], environment: None, light_selection: LightSelection::default(), light_sampler: OnceCell::new(), emitters: HashMap::new(), bbox: bvh.bounding_box().clone(), finite_bbox}
     }
 
     pub fn count(&self) -> usize {
@@ -76,11 +254,128 @@ impl HittableList {
 
     pub fn add(&mut self, object: Rc<dyn Hittable>) {
         self.bbox = AABB::from_boxes(&self.bbox, object.bounding_box());
+        if is_finite(object.bounding_box()) {
+            self.finite_bbox = AABB::from_boxes(&self.finite_bbox, object.bounding_box());
+        }
         self.objects.push(object);
     }
 
+    pub fn lights(&self) -> &[Rc<dyn Light>] {
+        &self.lights
+    }
+
+    pub fn add_light(&mut self, light: Rc<dyn Light>) {
+        let group = self.light_group_count();
+        self.push_light(light, group);
+    }
+
+    fn push_light(&mut self, light: Rc<dyn Light>, group: usize) {
+        self.lights.push(light);
+        self.light_groups.push(group);
+        self.light_sampler.take();
+    }
+
+    /// Group of light k, numbered from 0 in the order added. Each light is
+    /// a group of its own, except that the faces of an emissive mesh form
+    /// one, so that light AOVs get a layer per emitter.
+    pub fn light_group(&self, k: usize) -> usize {
+        self.light_groups[k]
+    }
+
+    pub fn light_group_count(&self) -> usize {
+        self.light_groups.last().map_or(0, |&group| group + 1)
+    }
+
+    /// Adds the faces of mesh, made of material. If the material emits
+    /// light, each face that does becomes a light too, sampled like the
+    /// others and weighted against the rays that hit it.
+    pub fn add_mesh(&mut self, mesh: &Rc<TriangleMesh>, material: &Rc<dyn Material>) {
+        let group = self.light_group_count();
+        for triangle in mesh.triangles(material) {
+            if let Some(emit) = material.emission() {
+                let light = TriangleLight::new(Rc::clone(&triangle), emit);
+                if light.power(0.) != Vec3(0., 0., 0.) {
+                    self.emitters.insert(triangle.id(), self.lights.len());
+                    self.push_light(Rc::new(light), group);
+                }
+            }
+            self.add(triangle);
+        }
+    }
+
+    /// Adds sphere. If its material emits light and the sphere stays in
+    /// place, it becomes a light too, like the faces of an emissive mesh.
+    pub fn add_sphere(&mut self, sphere: Rc<Sphere>) {
+        if let Some(emit) = sphere.material.emission()
+            && sphere.center.dir() == Vec3(0., 0., 0.)
+            && sphere.radius > 0.
+        {
+            let light = SphereLight::new(Rc::clone(&sphere), emit);
+            if light.power(0.) != Vec3(0., 0., 0.) {
+                self.emitters.insert(sphere.id, self.lights.len());
+                let group = self.light_group_count();
+                self.push_light(Rc::new(light), group);
+            }
+        }
+        self.add(sphere);
+    }
+
+    /// Index in lights of the object with the given id, if it is one.
+    pub fn emitter(&self, object_id: usize) -> Option<usize> {
+        self.emitters.get(&object_id).copied()
+    }
+
+    pub fn light_selection(&self) -> LightSelection {
+        self.light_selection
+    }
+
+    pub fn set_light_selection(&mut self, selection: LightSelection) {
+        self.light_selection = selection;
+        self.light_sampler.take();
+    }
+
+    /// Sampler picking among the lights with the light selection. It is
+    /// built on first use and rebuilt after the lights or the selection
+    /// change. The power of lights at infinity is taken over the bounds
+    /// of the finite objects, which planes would otherwise make unbounded.
+    pub fn light_sampler(&self) -> &LightSampler {
+        self.light_sampler.get_or_init(|| {
+            let b = &self.finite_bbox;
+            let radius = 0.5 * Vec3(b.x.size(), b.y.size(), b.z.size()).length();
+            let radius = if radius.is_finite() { radius } else { 0. };
+            LightSampler::new(&self.lights, self.light_selection, radius)
+        })
+    }
+
+    pub fn set_environment(&mut self, environment: Rc<dyn Environment>) {
+        self.environment = Some(environment);
+    }
+
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.lights.clear();
+        self.light_groups.clear();
+        self.environment = None;
+        self.light_sampler.take();
+        self.emitters.clear();
+    }
+
+    /// The same scene with its objects in a bounding volume hierarchy.
+    pub fn into_bvh(mut self) -> Self {
+        let count = self.count();
+        let bvh: Rc<dyn Hittable> = BVHNode::new(&mut self.objects, 0, count);
+        Self {
+            bbox: bvh.bounding_box().clone(),
+            objects: vec![bvh],
+            light_sampler: OnceCell::new(),
+            ..self
+        }
+    }
+
+    /// Whether anything blocks ray within ray_t, as a shadow ray from a
+    /// surface to a light asks. Cut out parts of objects let it through.
+    pub fn is_occluded(&self, ray: &Ray, ray_t: &Interval) -> bool {
+        self.objects.iter().any(|o| o.hit(ray, ray_t).is_some())
     }
 
     pub fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
@@ -88,12 +383,12 @@ impl HittableList {
         let mut closest_so_far = ray_t.max();
 
         for o in &self.objects {
-            if let Some(rec) = o.hit(ray, ray_t) {
-                if rec.t < closest_so_far {
-                    closest_so_far = rec.t;
-                    rec_out = Some(rec);
-                }
-            };
+            if let Some(rec) = o.hit(ray, ray_t)
+                && rec.t < closest_so_far
+            {
+                closest_so_far = rec.t;
+                rec_out = Some(rec);
+            }
         }
 
         rec_out
@@ -115,7 +410,8 @@ pub struct Sphere {
     center: Ray,
     radius: f64,
     material: Rc<dyn Material>,
-    bbox: AABB
+    bbox: AABB,
+    id: usize,
 }
 
 impl Sphere {
@@ -125,8 +421,9 @@ impl Sphere {
         Self {
             center: center_ray,
             radius,
-            material: Rc::clone(&mat),
-            bbox: AABB::from_points(center - rvec, center + rvec)
+            material: Rc::clone(mat),
+            bbox: AABB::from_points(center - rvec, center + rvec),
+            id: next_object_id(),
         }
     }
 
@@ -139,7 +436,8 @@ impl Sphere {
             center,
             radius,
             material: Rc::clone(&mat),
-            bbox: AABB::from_boxes(&box1, &box2)
+            bbox: AABB::from_boxes(&box1, &box2),
+            id: next_object_id(),
         }
     }
 
@@ -150,6 +448,21 @@ impl Sphere {
     pub fn radius(&self) -> f64 {
         self.radius
     }
+
+    /// Texture coordinates of a point p on the unit sphere, with u going
+    /// around the y axis from -x and v from the bottom pole to the top,
+    /// and the derivatives of the point on a sphere of the given radius.
+    fn uv(p: Vec3, radius: f64) -> (f64, f64, Vec3, Vec3) {
+        let theta = (-p.y()).clamp(-1., 1.).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+
+        let dpdu = 2. * PI * radius * Vec3(p.z(), 0., -p.x());
+        // At the poles dp/dv depends on the direction of approach; any
+        // tangent will do.
+        let s = (p.x() * p.x() + p.z() * p.z()).sqrt().max(1e-12);
+        let dpdv = PI * radius * Vec3(-p.x() * p.y() / s, s, -p.y() * p.z() / s);
+        (phi / (2. * PI), theta / PI, dpdu, dpdv)
+    }
 }
 
 impl Hittable for Sphere {
@@ -175,16 +488,261 @@ impl Hittable for Sphere {
             }
         }
 
-        return Some(HitRecord::new(
+        let mut rec = HitRecord::new(
             ray.at(root),
             root,
             ray,
             (ray.at(root) - current_center) / self.radius,
             Rc::clone(&self.material),
-        ));
+        );
+        let radius = self.radius.abs();
+        (rec.u, rec.v, rec.dpdu, rec.dpdv) = Self::uv((rec.point - current_center) / radius, radius);
+        // The normal is the offset from the center over the signed radius,
+        // turned toward the ray.
+        let side = if rec.front_face { 1. } else { -1. };
+        rec.dndu = side * rec.dpdu / self.radius;
+        rec.dndv = side * rec.dpdv / self.radius;
+        rec.object_point = rec.point - current_center;
+        rec.object_id = self.id;
+        rec.motion = self.center.dir();
+        Some(rec)
     }
 
     fn bounding_box(&self) -> &AABB {
         &self.bbox
     }
 }
+
+/// Object with parts cut away by an alpha texture, such as a leaf or a
+/// fence on a simple shape. Alpha is read from the first channel at the
+/// texture coordinates of each hit. Hits are skipped where alpha falls
+/// below the threshold, or, without one, with probability 1 - alpha, so
+/// that fractional alpha renders as partial coverage on average. A
+/// skipped hit continues the search behind it, so the mask applies to
+/// every query through `hit`, shadow rays included.
+pub struct AlphaMasked {
+    object: Rc<dyn Hittable>,
+    alpha: Rc<dyn Texture>,
+    threshold: Option<f64>, // Alpha below which hits are skipped
+}
+
+impl AlphaMasked {
+    pub fn new(object: Rc<dyn Hittable>, alpha: Rc<dyn Texture>) -> Self {
+        Self { object, alpha, threshold: None }
+    }
+
+    /// Cuts the object out sharply where alpha is below threshold instead
+    /// of skipping hits at random.
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    fn is_opaque(&self, rec: &HitRecord) -> bool {
+        let alpha = self.alpha.value_at(&rec.tex_coords()).x();
+        match self.threshold {
+            Some(threshold) => alpha >= threshold,
+            None => rand::random::<f64>() < alpha,
+        }
+    }
+}
+
+impl Hittable for AlphaMasked {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let mut min = ray_t.min();
+        loop {
+            let rec = self.object.hit(ray, &Interval::new(min, ray_t.max()))?;
+            if self.is_opaque(&rec) {
+                return Some(rec);
+            }
+            min = rec.t;
+        }
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.object.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::texture::{CheckerTexture, SolidColor};
+
+    #[test]
+    fn sphere_derivatives_work() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let center = Vec3(1., 2., 3.);
+        let sphere = Sphere::stationary(center, 2., &mat);
+        let hit = |dir: Vec3| {
+            let ray = Ray::new(center + 5. * dir, -dir, 0.);
+            sphere.hit(&ray, &Interval::new(0.001, f64::INFINITY)).unwrap()
+        };
+
+        let rec = hit(Vec3(1., 0., 0.));
+        assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+        assert!(hit(Vec3(0., 1., 0.)).v > 0.999);
+
+        // The derivatives match finite differences of hits at nearby
+        // coordinates, and their cross product points outwards.
+        let rec = hit(Vec3(0.3, 0.4, -0.5).unit());
+        let point_at = |u: f64, v: f64| {
+            let (theta, phi) = (v * PI, u * 2. * PI);
+            let p = Vec3(-theta.sin() * phi.cos(), -theta.cos(), theta.sin() * phi.sin());
+            center + 2. * p
+        };
+        assert!((point_at(rec.u, rec.v) - rec.point).length() < 1e-9);
+        let d = 1e-6;
+        let dpdu = (point_at(rec.u + d, rec.v) - point_at(rec.u - d, rec.v)) / (2. * d);
+        let dpdv = (point_at(rec.u, rec.v + d) - point_at(rec.u, rec.v - d)) / (2. * d);
+        assert!((dpdu - rec.dpdu).length() < 1e-6);
+        assert!((dpdv - rec.dpdv).length() < 1e-6);
+        assert!(rec.dpdu.cross(&rec.dpdv).dot(&rec.normal) > 0.);
+    }
+
+    #[test]
+    fn differentials_match_offset_rays() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let sphere = Sphere::stationary(Vec3(0., 0., -3.), 1.5, &mat);
+        let hit = |ray: &Ray| sphere.hit(ray, &Interval::new(0.001, f64::INFINITY)).unwrap();
+        let eps = 1e-5;
+        let close = |a: Vec3, b: Vec3, scale: f64| (a - b).length() < 1e-3 * scale;
+
+        // From outside, and from inside where the normal faces inwards.
+        for origin in [Vec3(0.3, 0.2, 1.), Vec3(0.2, -0.3, -3.5)] {
+            let dir = Vec3(0.1, 0.3, -1.);
+            let diff = RayDifferential {
+                rx_origin: origin,
+                rx_dir: dir + Vec3(eps, 0., 0.),
+                ry_origin: origin + Vec3(0., eps, 0.),
+                ry_dir: dir,
+            };
+            let ray = Ray::new(origin, dir, 0.).with_differential(Some(diff));
+            let mut rec = hit(&ray);
+            rec.set_footprint(&ray);
+            let f = rec.footprint;
+            let rx = hit(&Ray::new(diff.rx_origin, diff.rx_dir, 0.));
+            let ry = hit(&Ray::new(diff.ry_origin, diff.ry_dir, 0.));
+
+            // The footprint reaches the offset rays' hits, to first order.
+            assert!(close(rec.point + f.dpdx, rx.point, f.dpdx.length()));
+            assert!(close(rec.point + f.dpdy, ry.point, f.dpdy.length()));
+            let uv = |u: f64, v: f64| Vec3(u, v, 0.);
+            let change = uv(f.dudx, f.dvdx);
+            assert!(close(uv(rec.u, rec.v) + change, uv(rx.u, rx.v), change.length()));
+
+            // Specular rays leave in the directions the offset rays take.
+            let unit = ray.dir().unit();
+            let specular = [
+                (unit.reflect(&rec.normal), None),
+                (unit.refract(&rec.normal, 0.8), Some(0.8)),
+            ];
+            for (wi, eta) in specular {
+                let (d, offset_dir) = match eta {
+                    None => (
+                        rec.reflected_differential(&ray, wi).unwrap(),
+                        diff.rx_dir.unit().reflect(&rx.normal),
+                    ),
+                    Some(eta) => (
+                        rec.refracted_differential(&ray, wi, eta).unwrap(),
+                        diff.rx_dir.unit().refract(&rx.normal, eta),
+                    ),
+                };
+                let wi = wi.unit();
+                let turn = (offset_dir.unit() - wi).length();
+                assert!(turn > 0.);
+                assert!(close(d.rx_dir.unit(), offset_dir.unit(), turn));
+                assert_eq!(d.rx_origin, rec.point + f.dpdx);
+            }
+        }
+
+        // Rays without a differential have no footprint.
+        let mut rec = hit(&Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.));
+        rec.set_footprint(&Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., -1.), 0.));
+        assert_eq!(rec.footprint, Footprint::ZERO);
+    }
+
+    #[test]
+    fn alpha_masks_cut_out_hits() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let sphere: Rc<dyn Hittable> = Rc::new(Sphere::stationary(Vec3(0., 0., 0.), 1., &mat));
+        let ray = Ray::new(Vec3(0., 0., 5.), Vec3(0., 0., -1.), 0.);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let gray = |alpha| Rc::new(SolidColor::gray(alpha)) as Rc<dyn Texture>;
+
+        // Only the far side is left where the near side is cut away.
+        let front_cut = Rc::new(CheckerTexture::from_colors(
+            10.,
+            Vec3(0., 0., 0.),
+            Vec3(1., 1., 1.),
+        ));
+        let masked = AlphaMasked::new(sphere.clone(), front_cut).with_threshold(0.5);
+        assert_eq!(masked.hit(&ray, &ray_t).unwrap().t, 6.);
+        let masked = AlphaMasked::new(sphere.clone(), gray(0.2)).with_threshold(0.5);
+        assert!(masked.hit(&ray, &ray_t).is_none());
+
+        // Fractional alpha blocks that fraction of shadow rays.
+        let mut world = HittableList::new();
+        world.add(Rc::new(AlphaMasked::new(sphere.clone(), gray(0.3))));
+        let n = 20_000;
+        let blocked = (0..n).filter(|_| world.is_occluded(&ray, &ray_t)).count();
+        // Each ray passes both sides of the sphere.
+        let expected = 1. - 0.7 * 0.7;
+        assert!((blocked as f64 / n as f64 - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn object_points_move_with_the_object() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let sphere = Sphere::moving(Vec3(0., 0., 0.), Vec3(2., 0., 0.), 1., mat);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let start = Ray::new(Vec3(0.5, 0., 5.), Vec3(0., 0., -1.), 0.);
+        let end = Ray::new(Vec3(2.5, 0., 5.), Vec3(0., 0., -1.), 1.);
+        let (start, end) = (sphere.hit(&start, &ray_t).unwrap(), sphere.hit(&end, &ray_t).unwrap());
+
+        assert_eq!(end.point - start.point, Vec3(2., 0., 0.));
+        assert_eq!(start.object_point, end.object_point);
+        assert_eq!(start.tex_coords().object_point, start.point);
+    }
+
+    #[test]
+    fn planes_leave_lights_at_infinity_their_power() {
+        use crate::color::Color;
+        use crate::light::{DirectionalLight, PointLight};
+        use crate::shapes::Plane;
+
+        // The sun is weighed over the sphere whether or not the infinite
+        // floor is there.
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let scene = |floor: bool| {
+            let mut world = HittableList::new();
+            world.add(Rc::new(Sphere::stationary(Vec3(0., 1., 0.), 1., &mat)));
+            if floor {
+                world.add(Rc::new(Plane::new(
+                    Vec3(0., 0., 0.),
+                    Vec3(0., 1., 0.),
+                    &mat,
+                )));
+            }
+            world.add_light(Rc::new(PointLight::new(
+                Vec3(0., 3., 0.),
+                Color::new(1., 1., 1.),
+            )));
+            world.add_light(Rc::new(DirectionalLight::new(
+                Vec3(1., -1., 0.),
+                Color::new(1., 1., 1.),
+            )));
+            world.set_light_selection(LightSelection::Power);
+            world
+        };
+        let (point, normal) = (Vec3(2., 0., 0.), Vec3(0., 1., 0.));
+        let sun = scene(false).light_sampler().pmf(point, normal, 1);
+        assert!(sun > 0.);
+        assert_eq!(scene(true).light_sampler().pmf(point, normal, 1), sun);
+        assert_eq!(
+            scene(true).into_bvh().light_sampler().pmf(point, normal, 1),
+            sun
+        );
+    }
+}
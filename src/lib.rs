@@ -1,9 +1,27 @@
+pub mod aov;
 pub mod bvh;
 pub mod camera;
 pub mod color;
+pub mod csg;
+pub mod denoise;
+pub mod distributed;
+pub mod environment;
+pub mod framebuffer;
 pub mod hittable;
+pub mod light;
+pub mod lightsampler;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
+pub mod mipmap;
+pub mod noise;
 pub mod ray;
+pub mod roots;
+pub mod sampling;
+pub mod shapes;
+pub mod sky;
+pub mod spectrum;
 pub mod texture;
 pub mod util;
 pub mod vec3;
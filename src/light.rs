@@ -0,0 +1,928 @@
+//! Lights sampled explicitly from surfaces and reached by shadow rays.
+//! Most are never hit by rays; emissive triangles and spheres are also
+//! surfaces, and share their light with the rays that find them.
+//!
+//! Intensities are radiometric, per color channel: point and spot lights
+//! give their radiant intensity in W/sr, distant lights their irradiance
+//! in W/m² on a surface facing them, and surfaces their radiance in
+//! W/(m² sr). Lights can also be given the power of a real fixture, in
+//! watts or lumens, keeping the color they were made with.
+
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use crate::{
+    bvh::AABB,
+    color::Color,
+    hittable::{Hittable, Sphere},
+    mesh::Triangle,
+    ray::Ray,
+    sampling::{sample_spherical_triangle, sample_uniform_triangle, spherical_triangle_area},
+    spectrum::LUMINOUS_EFFICACY,
+    texture::{TexCoords, Texture},
+    util::{Interval, degrees_to_radians},
+    vec3::{Onb, Vec3},
+};
+
+/// Direction from a point toward a light and the light arriving along it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+    pub wi: Vec3,         // Unit direction from the point toward the light
+    pub distance: f64,    // Distance to the light, infinite for distant lights
+    pub radiance: Color,  // Radiance over the density of wi, or the irradiance of a delta light
+    pub pdf: Option<f64>, // Solid angle density of wi; None if no scattered ray can reach the light
+}
+
+pub trait Light {
+    /// Samples the light arriving at point, ignoring occlusion. None if
+    /// the point gets no light from it.
+    fn sample(&self, point: Vec3) -> Option<LightSample>;
+
+    /// Solid angle density with which `sample` picks wi from point, for
+    /// weighting the same light found by a scattered ray.
+    fn pdf(&self, _point: Vec3, _wi: Vec3) -> f64 {
+        0.
+    }
+
+    /// Total power emitted, in W per channel, for choosing brighter lights
+    /// more often. Lights at infinity count the power falling on a disk
+    /// the size of the scene, whose bounding sphere has scene_radius.
+    fn power(&self, scene_radius: f64) -> Color;
+
+    /// Where the light is and which way it shines, for estimating its
+    /// contribution at a point. None for lights at infinity.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+/// Total power emitted by a light, as fixtures are rated. Radiant power
+/// comes with the luminous efficacy of the light it radiates, such as
+/// `blackbody_efficacy` of the temperature of a filament, since only
+/// that part of it is visible.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Power {
+    Watts { watts: f64, efficacy: f64 }, // Radiant power, and lm/W of it
+    Lumens(f64),                         // Luminous power
+}
+
+impl Power {
+    /// Power in the units of colors: the luminance of the power of a
+    /// light, or its luminous power over `LUMINOUS_EFFICACY`.
+    pub fn watts(self) -> f64 {
+        match self {
+            Power::Watts { watts, efficacy } => watts * efficacy / LUMINOUS_EFFICACY,
+            Power::Lumens(lumens) => lumens / LUMINOUS_EFFICACY,
+        }
+    }
+}
+
+/// Bounds on the position and the emission directions of one or more
+/// lights, after "Importance Sampling of Many Lights with Adaptive
+/// Tree Splitting" by Conty Estevez and Kulla. Light leaves points in the
+/// box in directions within theta_o of axis, plus up to theta_e more for
+/// surfaces that emit sideways.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightBounds {
+    pub bbox: AABB,
+    pub power: f64,       // Luminance of the total power
+    pub axis: Vec3,       // Unit direction at the center of the emission cone
+    pub cos_theta_o: f64, // Cosine of the spread of emission axes around axis
+    pub cos_theta_e: f64, // Cosine of the spread of emission around each axis
+}
+
+impl LightBounds {
+    /// Bounds of both self and other.
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0. {
+            return other.clone();
+        }
+        if other.power == 0. {
+            return self.clone();
+        }
+        let (axis, cos_theta_o) = union_cones(
+            (self.axis, self.cos_theta_o),
+            (other.axis, other.cos_theta_o),
+        );
+        LightBounds {
+            bbox: AABB::from_boxes(&self.bbox, &other.bbox),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        let b = &self.bbox;
+        0.5 * Vec3(
+            b.x.min() + b.x.max(),
+            b.y.min() + b.y.max(),
+            b.z.min() + b.z.max(),
+        )
+    }
+
+    /// Conservative estimate of the light reaching point, on a surface
+    /// with the given normal, or anywhere around it if the normal is zero:
+    /// the power over the squared distance, times the largest cosines the
+    /// bounds allow at the light and at the surface. Zero if no light in
+    /// the bounds can shine on the point.
+    pub fn importance(&self, point: Vec3, normal: Vec3) -> f64 {
+        let center = self.centroid();
+        let b = &self.bbox;
+        let radius = 0.5 * Vec3(b.x.size(), b.y.size(), b.z.size()).length();
+        let to_point = point - center;
+        // Keep points inside the bounds from being arbitrarily close.
+        let distance2 = to_point.length_squared().max(radius);
+
+        // cos(max(0, a - b)) and sin(max(0, a - b)) from cosines and sines.
+        let cos_sub = |sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64| match cos_a > cos_b {
+            true => 1.,
+            false => cos_a * cos_b + sin_a * sin_b,
+        };
+        let sin_sub = |sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64| match cos_a > cos_b {
+            true => 0.,
+            false => sin_a * cos_b - cos_a * sin_b,
+        };
+        let sin_of = |cos: f64| (1. - cos * cos).max(0.).sqrt();
+
+        // Angle the bounds subtend seen from the point.
+        let cos_theta_b = match to_point.length_squared() > radius * radius {
+            true => sin_of(radius / to_point.length()),
+            false => -1.,
+        };
+        let sin_theta_b = sin_of(cos_theta_b);
+
+        // Smallest angle between an emission axis and the point.
+        let wi = match to_point.length_squared() > 0. {
+            true => to_point.unit(),
+            false => self.axis,
+        };
+        let cos_theta_w = self.axis.dot(&wi);
+        let sin_theta_w = sin_of(cos_theta_w);
+        let sin_theta_o = sin_of(self.cos_theta_o);
+        let cos_theta_x = cos_sub(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta = cos_sub(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta <= self.cos_theta_e {
+            return 0.;
+        }
+
+        let mut importance = self.power * cos_theta / distance2;
+        if normal != Vec3(0., 0., 0.) {
+            let cos_theta_i = normal.dot(&wi).abs();
+            let sin_theta_i = sin_of(cos_theta_i);
+            importance *= cos_sub(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.)
+    }
+}
+
+/// Smallest cone, as an axis and the cosine of its half angle, holding
+/// the cones a and b.
+fn union_cones(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    let (theta_a, theta_b) = (a.1.clamp(-1., 1.).acos(), b.1.clamp(-1., 1.).acos());
+    let theta_d = a.0.dot(&b.0).clamp(-1., 1.).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    // Rotate a's axis toward b's until the cone spans both.
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let turn = a.0.cross(&b.0);
+    if theta_o >= PI || turn.length_squared() == 0. {
+        return (a.0, -1.);
+    }
+    let (k, theta_r) = (turn.unit(), theta_o - theta_a);
+    let axis = theta_r.cos() * a.0 + theta_r.sin() * k.cross(&a.0);
+    (axis.unit(), theta_o.cos())
+}
+
+/// Light radiating equally in all directions from a point.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Color, // Radiant intensity in W/sr
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+
+    /// Scales the intensity to emit power.
+    pub fn with_power(mut self, power: Power) -> Self {
+        let luminance = self.power(0.).luminance();
+        assert!(
+            luminance > 0.,
+            "a light without luminance has no power to scale"
+        );
+        self.intensity *= power.watts() / luminance;
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.length();
+        (distance > 0.).then(|| LightSample {
+            wi: to_light / distance,
+            distance,
+            radiance: self.intensity / (distance * distance),
+            pdf: None,
+        })
+    }
+
+    fn power(&self, _: f64) -> Color {
+        4. * PI * self.intensity
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bbox: AABB::from_points(self.position, self.position),
+            power: self.power(0.).luminance(),
+            axis: Vec3(0., 0., 1.),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+        })
+    }
+}
+
+/// Point light shining into a cone: at full intensity up to the falloff
+/// angle from its axis, fading smoothly to nothing at the cone angle.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,  // Unit axis of the cone
+    intensity: Color, // Radiant intensity in W/sr along the axis
+    cos_cone: f64,    // Cosine of the angle where the light ends
+    cos_falloff: f64, // Cosine of the angle where it starts to fade
+}
+
+impl SpotLight {
+    /// Light at position shining toward target, in a cone whose edge lies
+    /// cone_angle degrees from its axis. The edge is hard until a falloff
+    /// is set.
+    pub fn new(position: Vec3, target: Vec3, intensity: Color, cone_angle: f64) -> Self {
+        let cos_cone = degrees_to_radians(cone_angle).cos();
+        Self {
+            position,
+            direction: (target - position).unit(),
+            intensity,
+            cos_cone,
+            cos_falloff: cos_cone,
+        }
+    }
+
+    /// Starts fading the light at falloff_angle degrees from the axis.
+    pub fn with_falloff(mut self, falloff_angle: f64) -> Self {
+        self.cos_falloff = degrees_to_radians(falloff_angle).cos().max(self.cos_cone);
+        self
+    }
+
+    /// Scales the intensity to emit power into the cone as it is set.
+    pub fn with_power(mut self, power: Power) -> Self {
+        let luminance = self.power(0.).luminance();
+        assert!(
+            luminance > 0.,
+            "a light without luminance has no power to scale"
+        );
+        self.intensity *= power.watts() / luminance;
+        self
+    }
+
+    /// Fraction of the axial intensity sent at cos_theta to the axis.
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff {
+            return 1.;
+        }
+        if cos_theta <= self.cos_cone {
+            return 0.;
+        }
+        let t = (cos_theta - self.cos_cone) / (self.cos_falloff - self.cos_cone);
+        t * t * (3. - 2. * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.length();
+        if distance == 0. {
+            return None;
+        }
+        let wi = to_light / distance;
+        let falloff = self.falloff(-wi.dot(&self.direction));
+        (falloff > 0.).then(|| LightSample {
+            wi,
+            distance,
+            radiance: falloff * self.intensity / (distance * distance),
+            pdf: None,
+        })
+    }
+
+    /// The smooth falloff sends about half as much as the full intensity
+    /// would over its band.
+    fn power(&self, _: f64) -> Color {
+        let full = 1. - self.cos_falloff;
+        let band = 0.5 * (self.cos_falloff - self.cos_cone);
+        2. * PI * (full + band) * self.intensity
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let theta_e = self.cos_cone.acos() - self.cos_falloff.acos();
+        Some(LightBounds {
+            bbox: AABB::from_points(self.position, self.position),
+            power: self.power(0.).luminance(),
+            axis: self.direction,
+            cos_theta_o: self.cos_falloff,
+            cos_theta_e: theta_e.cos(),
+        })
+    }
+}
+
+/// Light from infinitely far away, arriving everywhere from the same
+/// direction, like the sun. With an angular radius it is a disk in the sky
+/// of that size, casting soft shadows.
+pub struct DirectionalLight {
+    direction: Vec3,     // Unit direction the light travels in
+    irradiance: Color,   // W/m² on a surface facing the light
+    angular_radius: f64, // Radius of the disk in radians, 0 for a point
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: direction.unit(),
+            irradiance,
+            angular_radius: 0.,
+        }
+    }
+
+    /// Sets the angular radius of the light's disk in degrees, such as
+    /// 0.27 for the sun.
+    pub fn with_angular_radius(mut self, angular_radius: f64) -> Self {
+        assert!((0. ..90.).contains(&angular_radius));
+        self.angular_radius = degrees_to_radians(angular_radius);
+        self
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _: Vec3) -> Option<LightSample> {
+        let toward = -self.direction;
+        if self.angular_radius == 0. {
+            return Some(LightSample {
+                wi: toward,
+                distance: f64::INFINITY,
+                radiance: self.irradiance,
+                pdf: None,
+            });
+        }
+
+        // Uniform direction in the cone the disk subtends. Its radiance
+        // L gives the irradiance L π sin² r facing it, and dividing by
+        // the density 1 / (2π (1 - cos r)) leaves the factor below. Rays
+        // escaping the scene see the sky behind it, not the disk.
+        let cos_max = self.angular_radius.cos();
+        let cos_theta = 1. - rand::random::<f64>() * (1. - cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rand::random::<f64>();
+        let local = Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let sin2_max = 1. - cos_max * cos_max;
+        Some(LightSample {
+            wi: Onb::new(toward).to_world(local),
+            distance: f64::INFINITY,
+            radiance: 2. * (1. - cos_max) / sin2_max * self.irradiance,
+            pdf: None,
+        })
+    }
+
+    fn power(&self, scene_radius: f64) -> Color {
+        PI * scene_radius * scene_radius * self.irradiance
+    }
+}
+
+/// Triangle glowing from its front face with the radiance of a texture,
+/// looked up at the triangle's texture coordinates. Points that see it
+/// under a moderate solid angle sample directions uniformly within it;
+/// for tiny triangles, whose spherical triangles are numerically fragile,
+/// and huge ones, points are picked uniformly by area instead.
+pub struct TriangleLight {
+    triangle: Rc<Triangle>,
+    emit: Rc<dyn Texture>,
+    power: Color, // Power emitted, from the radiance averaged over the face
+}
+
+impl TriangleLight {
+    /// Solid angles in which directions are sampled within the triangle.
+    const MIN_SOLID_ANGLE: f64 = 3e-4;
+    const MAX_SOLID_ANGLE: f64 = 6.22;
+
+    pub fn new(triangle: Rc<Triangle>, emit: Rc<dyn Texture>) -> Self {
+        let mut light = Self {
+            triangle,
+            emit,
+            power: Color::new(0., 0., 0.),
+        };
+        // Average the radiance over stratified points, uniform by area,
+        // so that a texture that is mostly dark gives little power.
+        let n = 8;
+        let mut total = Color::new(0., 0., 0.);
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                total += light.radiance_at(sample_uniform_triangle(u1, u2));
+            }
+        }
+        light.power = PI * light.triangle.area() * total / (n * n) as f64;
+        light
+    }
+
+    /// Radiance emitted at barycentric coordinates b and the point there.
+    fn radiance_at(&self, b: (f64, f64, f64)) -> Color {
+        let (point, u, v) = self.triangle.point_at(b);
+        let mut coords = TexCoords::new(u, v, point);
+        coords.normal = self.triangle.normal();
+        self.emit.value_at(&coords)
+    }
+
+    /// Solid angle the triangle covers seen from point, or None if the
+    /// point is behind its emitting face.
+    fn solid_angle(&self, point: Vec3) -> Option<f64> {
+        let [p0, p1, p2] = self.triangle.vertices();
+        if (point - p0).dot(&self.triangle.normal()) <= 0. {
+            return None;
+        }
+        Some(spherical_triangle_area(p0 - point, p1 - point, p2 - point))
+    }
+
+    fn samples_solid_angle(solid_angle: f64) -> bool {
+        (Self::MIN_SOLID_ANGLE..=Self::MAX_SOLID_ANGLE).contains(&solid_angle)
+    }
+}
+
+impl Light for TriangleLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let solid_angle = self.solid_angle(point)?;
+        let (u1, u2) = (rand::random::<f64>(), rand::random::<f64>());
+        if Self::samples_solid_angle(solid_angle) {
+            let [p0, p1, p2] = self.triangle.vertices();
+            let wi = sample_spherical_triangle(p0 - point, p1 - point, p2 - point, u1, u2)?;
+            let rec = self
+                .triangle
+                .hit(&Ray::new(point, wi, 0.), &Interval::new(0., f64::INFINITY))?;
+            return Some(LightSample {
+                wi,
+                distance: rec.t,
+                radiance: solid_angle * self.emit.value_at(&rec.tex_coords()),
+                pdf: Some(1. / solid_angle),
+            });
+        }
+
+        // Uniform by area, converted to a density over directions.
+        let b = sample_uniform_triangle(u1, u2);
+        let to_light = self.triangle.point_at(b).0 - point;
+        let distance = to_light.length();
+        let wi = to_light / distance;
+        let cos = -wi.dot(&self.triangle.normal());
+        if cos <= 0. {
+            return None;
+        }
+        let pdf = distance * distance / (cos * self.triangle.area());
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.radiance_at(b) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn pdf(&self, point: Vec3, wi: Vec3) -> f64 {
+        let Some(solid_angle) = self.solid_angle(point) else {
+            return 0.;
+        };
+        let ray = Ray::new(point, wi, 0.);
+        let Some(rec) = self.triangle.hit(&ray, &Interval::new(0., f64::INFINITY)) else {
+            return 0.;
+        };
+        if Self::samples_solid_angle(solid_angle) {
+            return 1. / solid_angle;
+        }
+        let distance = rec.t * wi.length();
+        let cos = wi.unit().dot(&self.triangle.normal()).abs();
+        distance * distance / (cos * self.triangle.area())
+    }
+
+    /// Radiance L from the front face sends π L per unit area.
+    fn power(&self, _: f64) -> Color {
+        self.power
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bbox: self.triangle.bounding_box().clone(),
+            power: self.power.luminance(),
+            axis: self.triangle.normal(),
+            cos_theta_o: 1.,
+            cos_theta_e: 0.,
+        })
+    }
+}
+
+/// Sphere glowing from its outside with the radiance of a texture, looked
+/// up at the sphere's texture coordinates. Points outside it sample
+/// directions uniformly in the cone it subtends, every one of which sees
+/// it; points inside see only its dark inside.
+pub struct SphereLight {
+    sphere: Rc<Sphere>,
+    emit: Rc<dyn Texture>,
+    power: Color, // Power emitted, from the radiance averaged over the surface
+}
+
+impl SphereLight {
+    /// Cones narrower than this have 1 - cos θ computed from its series.
+    const MIN_SIN2_MAX: f64 = 1e-3;
+
+    pub fn new(sphere: Rc<Sphere>, emit: Rc<dyn Texture>) -> Self {
+        assert!(
+            sphere.center().dir() == Vec3(0., 0., 0.) && sphere.radius() > 0.,
+            "only stationary spheres with an outside can be lights"
+        );
+        let mut light = Self {
+            sphere,
+            emit,
+            power: Color::new(0., 0., 0.),
+        };
+        // Average the radiance over stratified points, uniform by area,
+        // so that a texture that is mostly dark gives little power.
+        let n = 8;
+        let mut total = Color::new(0., 0., 0.);
+        for i in 0..n {
+            for j in 0..n {
+                let z = 1. - 2. * (i as f64 + 0.5) / n as f64;
+                let phi = 2. * PI * (j as f64 + 0.5) / n as f64;
+                let r = (1. - z * z).sqrt();
+                total += light.radiance_toward(Vec3(r * phi.cos(), r * phi.sin(), z));
+            }
+        }
+        let area = 4. * PI * light.sphere.radius() * light.sphere.radius();
+        light.power = PI * area * total / (n * n) as f64;
+        light
+    }
+
+    fn center(&self) -> Vec3 {
+        self.sphere.center().origin()
+    }
+
+    /// Radiance emitted at the surface point in the unit direction d from
+    /// the center.
+    fn radiance_toward(&self, d: Vec3) -> Color {
+        let ray = Ray::new(self.center() + 2. * self.sphere.radius() * d, -d, 0.);
+        self.sphere
+            .hit(&ray, &Interval::new(0., f64::INFINITY))
+            .map_or(Color::new(0., 0., 0.), |rec| {
+                self.emit.value_at(&rec.tex_coords())
+            })
+    }
+
+    /// Unit axis toward the center of the cone the sphere subtends seen
+    /// from point, and 1 - cos of its half angle, or None if the point is
+    /// not outside the sphere.
+    fn cone(&self, point: Vec3) -> Option<(Vec3, f64)> {
+        let to_center = self.center() - point;
+        let radius = self.sphere.radius();
+        let sin2_max = radius * radius / to_center.length_squared();
+        if sin2_max >= 1. {
+            return None;
+        }
+        let one_minus_cos = match sin2_max < Self::MIN_SIN2_MAX {
+            true => sin2_max / 2. + sin2_max * sin2_max / 8.,
+            false => 1. - (1. - sin2_max).sqrt(),
+        };
+        Some((to_center.unit(), one_minus_cos))
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let (axis, one_minus_cos) = self.cone(point)?;
+        let cos_theta = 1. - rand::random::<f64>() * one_minus_cos;
+        let sin_theta = ((1. - cos_theta) * (1. + cos_theta)).max(0.).sqrt();
+        let phi = 2. * PI * rand::random::<f64>();
+        let local = Vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let wi = Onb::new(axis).to_world(local);
+        // Directions grazing the edge of the cone may just miss.
+        let rec = self
+            .sphere
+            .hit(&Ray::new(point, wi, 0.), &Interval::new(0., f64::INFINITY))?;
+        let solid_angle = 2. * PI * one_minus_cos;
+        Some(LightSample {
+            wi,
+            distance: rec.t,
+            radiance: solid_angle * self.emit.value_at(&rec.tex_coords()),
+            pdf: Some(1. / solid_angle),
+        })
+    }
+
+    fn pdf(&self, point: Vec3, wi: Vec3) -> f64 {
+        let Some((axis, one_minus_cos)) = self.cone(point) else {
+            return 0.;
+        };
+        if wi.unit().dot(&axis) < 1. - one_minus_cos {
+            return 0.;
+        }
+        1. / (2. * PI * one_minus_cos)
+    }
+
+    /// Radiance L from the outside sends π L per unit area.
+    fn power(&self, _: f64) -> Color {
+        self.power
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bbox: self.sphere.bounding_box().clone(),
+            power: self.power.luminance(),
+            axis: Vec3(0., 0., 1.),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_light_works() {
+        let light = PointLight::new(Vec3(1., 2., 3.), Color::new(8., 4., 2.));
+        let sample = light.sample(Vec3(1., 2., 1.)).unwrap();
+        assert_eq!(sample.wi, Vec3(0., 0., 1.));
+        assert_eq!(sample.distance, 2.);
+        // Irradiance falls off with the square of the distance.
+        assert_eq!(sample.radiance, Color::new(2., 1., 0.5));
+    }
+
+    #[test]
+    fn spot_light_works() {
+        let intensity = Color::new(1., 1., 1.);
+        let hard = SpotLight::new(Vec3(0., 0., 0.), Vec3(0., -2., 0.), intensity, 30.);
+        let soft =
+            SpotLight::new(Vec3(0., 0., 0.), Vec3(0., -2., 0.), intensity, 30.).with_falloff(10.);
+        let at_angle = |light: &SpotLight, degrees: f64| {
+            let theta = degrees_to_radians(degrees);
+            light
+                .sample(Vec3(theta.sin(), -theta.cos(), 0.))
+                .map_or(0., |s| s.radiance.x())
+        };
+
+        assert!((at_angle(&hard, 0.) - 1.).abs() < 1e-12);
+        assert!((at_angle(&hard, 29.) - 1.).abs() < 1e-12);
+        assert_eq!(at_angle(&hard, 31.), 0.);
+        assert!((at_angle(&soft, 9.) - 1.).abs() < 1e-12);
+        assert_eq!(at_angle(&soft, 31.), 0.);
+        let (mid, late) = (at_angle(&soft, 20.), at_angle(&soft, 28.));
+        assert!(0. < late && late < mid && mid < 0.9);
+        // Nothing shines backwards.
+        assert!(hard.sample(Vec3(0., 1., 0.)).is_none());
+    }
+
+    #[test]
+    fn light_bounds_work() {
+        let bounds = |position: Vec3, axis: Vec3, cos_theta_o: f64| LightBounds {
+            bbox: AABB::from_points(position, position),
+            power: 1.,
+            axis,
+            cos_theta_o,
+            cos_theta_e: 0.,
+        };
+        let up = bounds(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 1.);
+        let side = bounds(Vec3(2., 0., 0.), Vec3(1., 0., 0.), 1.);
+
+        // The union spans both positions and both cones.
+        let both = up.union(&side);
+        assert_eq!(
+            both.bbox,
+            AABB::from_points(Vec3(0., 0., 0.), Vec3(2., 0., 0.))
+        );
+        assert_eq!(both.power, 2.);
+        assert!((both.axis - Vec3(1., 1., 0.).unit()).length() < 1e-12);
+        assert!((both.cos_theta_o - (PI / 4.).cos()).abs() < 1e-12);
+        // Opposite cones make a full sphere.
+        let down = bounds(Vec3(0., 0., 0.), Vec3(0., -1., 0.), 1.);
+        assert_eq!(up.union(&down).cos_theta_o, -1.);
+
+        // Importance falls with the square of the distance, and ends past
+        // the edge of the emission.
+        let normal = Vec3(0., -1., 0.);
+        let near = up.importance(Vec3(0., 1., 0.), normal);
+        assert!((near - 1.).abs() < 1e-12);
+        assert!((up.importance(Vec3(0., 2., 0.), normal) - 0.25).abs() < 1e-12);
+        assert!(up.importance(Vec3(1., 1., 0.), normal) > 0.);
+        assert_eq!(up.importance(Vec3(1., -0.1, 0.), normal), 0.);
+    }
+
+    #[test]
+    fn directional_light_works() {
+        let irradiance = Color::new(3., 2., 1.);
+        let sun = DirectionalLight::new(Vec3(0., -1., -1.), irradiance);
+        let sample = sun.sample(Vec3(5., 0., 0.)).unwrap();
+        assert!((sample.wi - Vec3(0., 1., 1.).unit()).length() < 1e-12);
+        assert_eq!(sample.distance, f64::INFINITY);
+        assert_eq!(sample.radiance, irradiance);
+
+        // A disk gives the same irradiance on a surface facing it, from
+        // directions spread over its cone.
+        let disk = DirectionalLight::new(Vec3(0., -1., -1.), irradiance).with_angular_radius(10.);
+        let toward = Vec3(0., 1., 1.).unit();
+        let n = 100_000;
+        let mut total = Color::new(0., 0., 0.);
+        for _ in 0..n {
+            let sample = disk.sample(Vec3(0., 0., 0.)).unwrap();
+            let cos = sample.wi.dot(&toward);
+            assert!(cos >= degrees_to_radians(10.).cos() - 1e-12);
+            total += cos * sample.radiance;
+        }
+        assert!((total / n as f64 - irradiance).length() < 1e-3);
+    }
+
+    #[test]
+    fn triangle_light_works() {
+        use crate::material::{DiffuseLight, Material};
+        use crate::mesh::TriangleMesh;
+        use crate::texture::SolidColor;
+
+        // Glows upward from the right half of the unit square, whose
+        // points are also its texture coordinates.
+        let positions = vec![Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(1., 1., 0.)];
+        let mesh = Rc::new(TriangleMesh::new(positions, vec![[0, 1, 2]]));
+        let radiance = Color::new(2., 2., 2.);
+        let mat: Rc<dyn Material> = Rc::new(DiffuseLight::new(radiance));
+        let light = TriangleLight::new(
+            Rc::clone(&mesh.triangles(&mat)[0]),
+            Rc::new(SolidColor::new(radiance)),
+        );
+        assert!((light.power(0.) - PI * 0.5 * radiance).length() < 1e-12);
+
+        // Nearby points sample by solid angle, distant ones by area, and
+        // either way estimate the light arriving from the triangle.
+        for point in [Vec3(0.6, 0.3, 0.5), Vec3(0.6, 0.3, 200.)] {
+            let [p0, p1, p2] = light.triangle.vertices();
+            let solid_angle = spherical_triangle_area(p0 - point, p1 - point, p2 - point);
+            let n = 20_000;
+            let mut total = Color::new(0., 0., 0.);
+            for _ in 0..n {
+                let sample = light.sample(point).unwrap();
+                let pdf = sample.pdf.unwrap();
+                assert!((pdf - light.pdf(point, sample.wi)).abs() <= 1e-6 * pdf);
+                let hit = point + sample.distance * sample.wi;
+                assert!(hit.z().abs() < 1e-9 && hit.y() <= hit.x() + 1e-9);
+                total += sample.radiance;
+            }
+            assert!((total / n as f64 - solid_angle * radiance).length() < 0.02 * solid_angle);
+        }
+
+        // Nothing shines downward or misses the triangle.
+        assert!(light.sample(Vec3(0.6, 0.3, -1.)).is_none());
+        assert_eq!(light.pdf(Vec3(0.6, 0.3, -1.), Vec3(0., 0., 1.)), 0.);
+        assert_eq!(light.pdf(Vec3(0.3, 0.6, 1.), Vec3(0., 0., -1.)), 0.);
+
+        // A texture dark where u < 1/2 leaves three quarters of the power.
+        struct Stripe;
+        impl Texture for Stripe {
+            fn value(&self, u: f64, _: f64, _: Vec3) -> Color {
+                match u < 0.5 {
+                    true => Color::new(0., 0., 0.),
+                    false => Color::new(2., 2., 2.),
+                }
+            }
+        }
+        let striped = TriangleLight::new(Rc::clone(&light.triangle), Rc::new(Stripe));
+        let expected = 0.75 * light.power(0.);
+        assert!((striped.power(0.) - expected).length() < 0.05 * expected.length());
+    }
+
+    #[test]
+    fn sphere_light_works() {
+        use crate::material::{DiffuseLight, Material};
+        use crate::texture::SolidColor;
+
+        let radiance = Color::new(2., 2., 2.);
+        let mat: Rc<dyn Material> = Rc::new(DiffuseLight::new(radiance));
+        let sphere = Rc::new(Sphere::stationary(Vec3(0., 1., 0.), 0.5, &mat));
+        let light = SphereLight::new(Rc::clone(&sphere), Rc::new(SolidColor::new(radiance)));
+        let expected = PI * 4. * PI * 0.25 * radiance;
+        assert!((light.power(0.) - expected).length() < 1e-9 * expected.length());
+
+        // Nearby and distant points alike sample the cone the sphere
+        // subtends, and estimate the light arriving from it.
+        for point in [Vec3(0., 1., 1.), Vec3(0., 1., 2000.)] {
+            let sin2_max = 0.25 / (point - Vec3(0., 1., 0.)).length_squared();
+            let solid_angle = 2. * PI * (1. - (1. - sin2_max).sqrt());
+            let n = 20_000;
+            let mut total = Color::new(0., 0., 0.);
+            for _ in 0..n {
+                let sample = light.sample(point).unwrap();
+                let pdf = sample.pdf.unwrap();
+                assert!((pdf - light.pdf(point, sample.wi)).abs() <= 1e-6 * pdf);
+                let hit = point + sample.distance * sample.wi;
+                assert!(((hit - Vec3(0., 1., 0.)).length() - 0.5).abs() < 1e-9);
+                assert!(hit.z() > 0.);
+                total += sample.radiance;
+            }
+            let error = (total / n as f64 - solid_angle * radiance).length();
+            assert!(error < 0.02 * solid_angle);
+        }
+
+        // Nothing reaches points inside, or directions away from it.
+        assert!(light.sample(Vec3(0., 1.2, 0.)).is_none());
+        assert_eq!(light.pdf(Vec3(0., 1.2, 0.), Vec3(0., 1., 0.)), 0.);
+        assert_eq!(light.pdf(Vec3(0., 1., 1.), Vec3(0., 0., 1.)), 0.);
+
+        // A texture dark over the lower half leaves half the power.
+        struct Stripe;
+        impl Texture for Stripe {
+            fn value(&self, _: f64, v: f64, _: Vec3) -> Color {
+                match v < 0.5 {
+                    true => Color::new(0., 0., 0.),
+                    false => Color::new(2., 2., 2.),
+                }
+            }
+        }
+        let striped = SphereLight::new(sphere, Rc::new(Stripe));
+        let expected = 0.5 * light.power(0.);
+        assert!((striped.power(0.) - expected).length() < 0.05 * expected.length());
+    }
+
+    #[test]
+    fn emission_units_work() {
+        use crate::material::{DiffuseLight, Material};
+        use crate::mesh::TriangleMesh;
+        use crate::spectrum::{blackbody, blackbody_efficacy};
+
+        assert_eq!(Power::Lumens(683.).watts(), 1.);
+        assert_eq!(
+            Power::Watts {
+                watts: 2.,
+                efficacy: LUMINOUS_EFFICACY
+            }
+            .watts(),
+            2.
+        );
+
+        // A warm 800 lm bulb keeps the color it was given.
+        let warm = blackbody(2700.);
+        let bulb = PointLight::new(Vec3(0., 0., 0.), warm).with_power(Power::Lumens(800.));
+        let power = bulb.power(0.);
+        assert!((power.luminance() * LUMINOUS_EFFICACY - 800.).abs() < 1e-9);
+        assert!((power / power.luminance() - warm).length() < 1e-12);
+        // A 60 W filament at 2700 K gives about as much light as that bulb.
+        let filament = Power::Watts {
+            watts: 60.,
+            efficacy: blackbody_efficacy(2700.),
+        };
+        let spot = SpotLight::new(Vec3(0., 0., 0.), Vec3(0., -1., 0.), warm, 40.)
+            .with_falloff(20.)
+            .with_power(filament);
+        let lumens = spot.power(0.).luminance() * LUMINOUS_EFFICACY;
+        assert!((lumens - 60. * blackbody_efficacy(2700.)).abs() < 1e-9);
+        assert!((600. ..1000.).contains(&lumens));
+
+        // A panel's triangles share its power, and its radiance follows
+        // from its luminance.
+        let positions = vec![
+            Vec3(0., 0., 0.),
+            Vec3(2., 0., 0.),
+            Vec3(2., 1., 0.),
+            Vec3(0., 1., 0.),
+        ];
+        let mesh = Rc::new(TriangleMesh::new(positions, vec![[0, 1, 2], [0, 2, 3]]));
+        assert!((mesh.area() - 2.).abs() < 1e-12);
+        let panel: Rc<dyn Material> = Rc::new(DiffuseLight::from_power(
+            Color::new(1., 1., 1.),
+            Power::Lumens(1000.),
+            mesh.area(),
+        ));
+        let emit = panel.emission().unwrap();
+        let total = mesh
+            .triangles(&panel)
+            .into_iter()
+            .map(|t| {
+                TriangleLight::new(t, Rc::clone(&emit))
+                    .power(0.)
+                    .luminance()
+            })
+            .sum::<f64>();
+        assert!((total * LUMINOUS_EFFICACY - 1000.).abs() < 1e-6);
+        let screen = DiffuseLight::from_luminance(warm, 250.).emission().unwrap();
+        let radiance = screen.value(0., 0., Vec3(0., 0., 0.));
+        assert!((radiance.luminance() * LUMINOUS_EFFICACY - 250.).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,374 @@
+//! Choosing which light to sample at a shading point, for scenes with too
+//! many lights to sample them all.
+
+use std::rc::Rc;
+
+use crate::{
+    bvh::AABB,
+    light::{Light, LightBounds},
+    sampling::Distribution1D,
+    vec3::Vec3,
+};
+
+/// How direct lighting picks among the world's lights at each hit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LightSelection {
+    /// Samples every light, best for a handful of them.
+    #[default]
+    All,
+    /// One light, each as likely.
+    Uniform,
+    /// One light, in proportion to its power.
+    Power,
+    /// One light, in proportion to its estimated contribution at the hit,
+    /// found by descending a BVH over the lights.
+    Bvh,
+}
+
+/// Node of the light BVH, whose children are stored depth first: the first
+/// right after it.
+struct LightNode {
+    bounds: LightBounds,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Leaf(usize),     // Index of the light
+    Interior(usize), // Index of the second child
+}
+
+/// Picks lights with one of the `LightSelection` strategies, and gives the
+/// probability of picking a light for weighting its samples.
+pub struct LightSampler {
+    selection: LightSelection,
+    count: usize,                  // Number of lights
+    power: Option<Distribution1D>, // By power, for `Power`
+    infinite: Vec<usize>,          // Lights without bounds, outside the BVH
+    nodes: Vec<LightNode>,         // BVH over the other lights, root first
+    trails: Vec<Option<u64>>, // Branches from the root to each light, bit k set for a second child at depth k
+}
+
+impl LightSampler {
+    /// Sampler choosing among lights, in a scene whose bounding sphere has
+    /// scene_radius.
+    pub fn new(lights: &[Rc<dyn Light>], selection: LightSelection, scene_radius: f64) -> Self {
+        let mut sampler = Self {
+            selection,
+            count: lights.len(),
+            power: None,
+            infinite: vec![],
+            nodes: vec![],
+            trails: vec![None; lights.len()],
+        };
+        match selection {
+            LightSelection::Power if !lights.is_empty() => {
+                let power = lights
+                    .iter()
+                    .map(|light| light.power(scene_radius).luminance().max(0.))
+                    .collect();
+                sampler.power = Some(Distribution1D::new(power));
+            }
+            LightSelection::Bvh => {
+                let mut bounded = vec![];
+                for (k, light) in lights.iter().enumerate() {
+                    match light.bounds() {
+                        Some(bounds) if bounds.power > 0. => bounded.push((k, bounds)),
+                        Some(_) => {}
+                        None => sampler.infinite.push(k),
+                    }
+                }
+                if !bounded.is_empty() {
+                    sampler.build(&mut bounded, 0, 0);
+                }
+            }
+            _ => {}
+        }
+        sampler
+    }
+
+    /// Adds the subtree over lights, depth levels below the root and
+    /// reached through trail, returning the index of its root.
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], depth: u32, trail: u64) -> usize {
+        let index = self.nodes.len();
+        if let [(k, bounds)] = lights {
+            self.nodes.push(LightNode {
+                bounds: bounds.clone(),
+                kind: NodeKind::Leaf(*k),
+            });
+            self.trails[*k] = Some(trail);
+            return index;
+        }
+        assert!(depth < 64, "light BVH too deep");
+
+        // Split at the median centroid along the longest axis of the
+        // centroids, like the BVH over objects.
+        let bounds = lights[1..]
+            .iter()
+            .fold(lights[0].1.clone(), |b, (_, other)| b.union(other));
+        let centroids = lights.iter().fold(AABB::empty(), |b, (_, l)| {
+            AABB::from_boxes(&b, &AABB::from_points(l.centroid(), l.centroid()))
+        });
+        let axis = centroids.longest_axis();
+        let coordinate = |l: &LightBounds| {
+            let c = l.centroid();
+            [c.x(), c.y(), c.z()][axis]
+        };
+        lights.sort_by(|a, b| coordinate(&a.1).total_cmp(&coordinate(&b.1)));
+
+        self.nodes.push(LightNode {
+            bounds,
+            kind: NodeKind::Interior(0),
+        });
+        let mid = lights.len() / 2;
+        let (first, second) = lights.split_at_mut(mid);
+        self.build(first, depth + 1, trail);
+        let second = self.build(second, depth + 1, trail | 1 << depth);
+        self.nodes[index].kind = NodeKind::Interior(second);
+        index
+    }
+
+    /// Probability that the BVH sampler picks a light at infinity.
+    fn infinite_probability(&self) -> f64 {
+        let bounded = usize::from(!self.nodes.is_empty());
+        match self.infinite.len() {
+            0 => 0.,
+            n => n as f64 / (n + bounded) as f64,
+        }
+    }
+
+    /// Probabilities of descending into each child of an interior node,
+    /// None if neither can light point.
+    fn child_probabilities(
+        &self,
+        node: usize,
+        second: usize,
+        point: Vec3,
+        normal: Vec3,
+    ) -> Option<(f64, f64)> {
+        let first = self.nodes[node + 1].bounds.importance(point, normal);
+        let second = self.nodes[second].bounds.importance(point, normal);
+        let total = first + second;
+        (total > 0.).then(|| (first / total, second / total))
+    }
+
+    /// Picks a light to sample at point, on a surface with the given
+    /// normal, returning its index and the probability of picking it. None
+    /// if no light can reach the point.
+    pub fn sample(&self, point: Vec3, normal: Vec3) -> Option<(usize, f64)> {
+        if self.count == 0 {
+            return None;
+        }
+        match self.selection {
+            LightSelection::All | LightSelection::Uniform => {
+                let k = rand::random_range(0..self.count);
+                Some((k, 1. / self.count as f64))
+            }
+            LightSelection::Power => {
+                let (k, prob) = self.power.as_ref()?.sample_discrete(rand::random());
+                (prob > 0.).then_some((k, prob))
+            }
+            LightSelection::Bvh => {
+                let p_infinite = self.infinite_probability();
+                if rand::random::<f64>() < p_infinite {
+                    let k = self.infinite[rand::random_range(0..self.infinite.len())];
+                    return Some((k, p_infinite / self.infinite.len() as f64));
+                }
+                if self.nodes.is_empty() {
+                    return None;
+                }
+
+                let (mut node, mut prob) = (0, 1. - p_infinite);
+                loop {
+                    match self.nodes[node].kind {
+                        NodeKind::Interior(second) => {
+                            let (p_first, p_second) =
+                                self.child_probabilities(node, second, point, normal)?;
+                            if rand::random::<f64>() < p_first {
+                                node += 1;
+                                prob *= p_first;
+                            } else {
+                                node = second;
+                                prob *= p_second;
+                            }
+                        }
+                        NodeKind::Leaf(k) => {
+                            let lit =
+                                node > 0 || self.nodes[0].bounds.importance(point, normal) > 0.;
+                            return lit.then_some((k, prob));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability that `sample` picks light k at point, on a surface with
+    /// the given normal.
+    pub fn pmf(&self, point: Vec3, normal: Vec3, k: usize) -> f64 {
+        match self.selection {
+            LightSelection::All => 1.,
+            LightSelection::Uniform => 1. / self.count as f64,
+            LightSelection::Power => self.power.as_ref().map_or(0., |d| d.discrete_pdf(k)),
+            LightSelection::Bvh => {
+                let p_infinite = self.infinite_probability();
+                if self.infinite.contains(&k) {
+                    return p_infinite / self.infinite.len() as f64;
+                }
+                let Some(trail) = self.trails[k] else {
+                    return 0.;
+                };
+
+                let (mut node, mut prob, mut depth) = (0, 1. - p_infinite, 0);
+                loop {
+                    match self.nodes[node].kind {
+                        NodeKind::Interior(second) => {
+                            let Some((p_first, p_second)) =
+                                self.child_probabilities(node, second, point, normal)
+                            else {
+                                return 0.;
+                            };
+                            if trail >> depth & 1 == 0 {
+                                node += 1;
+                                prob *= p_first;
+                            } else {
+                                node = second;
+                                prob *= p_second;
+                            }
+                            depth += 1;
+                        }
+                        NodeKind::Leaf(_) => {
+                            let lit =
+                                node > 0 || self.nodes[0].bounds.importance(point, normal) > 0.;
+                            return if lit { prob } else { 0. };
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::light::{DirectionalLight, PointLight, SpotLight};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn point_light(position: Vec3, intensity: f64) -> Rc<dyn Light> {
+        Rc::new(PointLight::new(
+            position,
+            Color::new(intensity, intensity, intensity),
+        ))
+    }
+
+    /// Frequencies with which sampler picks each of count lights.
+    fn frequencies(sampler: &LightSampler, count: usize, point: Vec3, normal: Vec3) -> Vec<f64> {
+        let n = 200_000;
+        let mut counts = vec![0.; count];
+        for _ in 0..n {
+            if let Some((k, prob)) = sampler.sample(point, normal) {
+                assert!((prob - sampler.pmf(point, normal, k)).abs() < 1e-12);
+                counts[k] += 1. / n as f64;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn power_selection_follows_power() {
+        let lights = [
+            point_light(Vec3(0., 0., 0.), 1.),
+            point_light(Vec3(1., 0., 0.), 2.),
+            point_light(Vec3(2., 0., 0.), 5.),
+        ];
+        let sampler = LightSampler::new(&lights, LightSelection::Power, 1.);
+        let (point, normal) = (Vec3(0., 1., 0.), Vec3(0., 1., 0.));
+        let counts = frequencies(&sampler, 3, point, normal);
+        for (k, expected) in [1. / 8., 2. / 8., 5. / 8.].into_iter().enumerate() {
+            assert!((sampler.pmf(point, normal, k) - expected).abs() < 1e-12);
+            assert!((counts[k] - expected).abs() < 5e-3);
+        }
+
+        let uniform = LightSampler::new(&lights, LightSelection::Uniform, 1.);
+        assert_eq!(uniform.pmf(point, normal, 2), 1. / 3.);
+        let empty = LightSampler::new(&[], LightSelection::Power, 1.);
+        assert!(empty.sample(point, normal).is_none());
+    }
+
+    #[test]
+    fn bvh_selection_matches_its_pmf() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut lights: Vec<Rc<dyn Light>> = (0..100)
+            .map(|_| {
+                let position = Vec3(
+                    rng.random_range(-10. ..10.),
+                    rng.random_range(0. ..5.),
+                    rng.random_range(-10. ..10.),
+                );
+                let intensity = Color::new(1., 1., 1.) * rng.random_range(0.1..10.);
+                let light: Rc<dyn Light> = match rng.random_bool(0.5) {
+                    true => Rc::new(PointLight::new(position, intensity)),
+                    false => Rc::new(
+                        SpotLight::new(position, position - Vec3(0., 1., 0.), intensity, 40.)
+                            .with_falloff(30.),
+                    ),
+                };
+                light
+            })
+            .collect();
+        lights.push(Rc::new(DirectionalLight::new(
+            Vec3(0., -1., 0.),
+            Color::new(1., 1., 1.),
+        )));
+        let sampler = LightSampler::new(&lights, LightSelection::Bvh, 20.);
+
+        let (point, normal) = (Vec3(1., 0., 2.), Vec3(0., 1., 0.));
+        let total: f64 = (0..lights.len())
+            .map(|k| sampler.pmf(point, normal, k))
+            .sum();
+        assert!((total - 1.).abs() < 1e-9);
+        // The light at infinity is picked as often as the BVH.
+        assert!((sampler.pmf(point, normal, 100) - 0.5).abs() < 1e-12);
+        let counts = frequencies(&sampler, lights.len(), point, normal);
+        for (k, count) in counts.iter().enumerate() {
+            assert!((count - sampler.pmf(point, normal, k)).abs() < 5e-3);
+        }
+    }
+
+    #[test]
+    fn bvh_selection_prefers_contributing_lights() {
+        let (point, normal) = (Vec3(0., 0., 0.), Vec3(0., 1., 0.));
+        let pmfs = |lights: &[Rc<dyn Light>]| {
+            let sampler = LightSampler::new(lights, LightSelection::Bvh, 10.);
+            (0..lights.len())
+                .map(|k| sampler.pmf(point, normal, k))
+                .collect::<Vec<_>>()
+        };
+
+        // Near lights are picked far more often than distant ones.
+        let pmf = pmfs(&[
+            point_light(Vec3(0., 1., 0.), 1.),
+            point_light(Vec3(0., 1., 10.), 1.),
+        ]);
+        assert!(pmf[0] > 10. * pmf[1]);
+
+        // A spot light pointing away is never picked, however bright.
+        let spot = SpotLight::new(
+            Vec3(0., 1., 1.),
+            Vec3(0., 2., 1.),
+            Color::new(100., 100., 100.),
+            30.,
+        );
+        let pmf = pmfs(&[point_light(Vec3(0., 1., 0.), 1.), Rc::new(spot)]);
+        assert_eq!(pmf, [1., 0.]);
+
+        // Lights grazing the surface count for less than those above it.
+        let pmf = pmfs(&[
+            point_light(Vec3(0., 1., 0.), 1.),
+            point_light(Vec3(1., 0.01, 0.), 1.),
+        ]);
+        assert!(pmf[0] > 0.9);
+    }
+}
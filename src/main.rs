@@ -1,177 +1,295 @@
-use std::rc::Rc;
+use std::io::BufWriter;
+use std::process::{Command, Stdio};
+use std::time::Duration;
 
-use tracerust::bvh::BVHNode;
+use tracerust::aov::Aov;
 use tracerust::camera::Camera;
-use tracerust::color::Color;
-use tracerust::hittable::{HittableList, Sphere};
-use tracerust::material::{Dielectric, Lambertian, Material, Metal};
-use tracerust::texture::CheckerTexture;
-use tracerust::util;
-use tracerust::vec3::Vec3;
+use tracerust::color::{DisplayTransform, Dither, Quantizer, ToneMap};
+use tracerust::denoise::Denoiser;
+use tracerust::distributed::{self, Coordinator, WorkItem};
+use tracerust::framebuffer::{Framebuffer, Tile};
 
-fn main() {
-    let mut world;
-    let cam;
-    match 2 {
-        1 => (world, cam) = bouncing_spheres(),
-        2 => (world, cam) = checkered_spheres(),
-        _ => panic!(),
-    }
+mod scenes;
+
+/// Crop window given on the command line.
+#[derive(Clone, Copy)]
+enum CropWindow {
+    Pixels(Tile),                   // x, y, width, height in pixels
+    Normalized(f64, f64, f64, f64), // x0, y0, x1, y1 in [0, 1]
+}
+
+/// Command line options of the renderer.
+struct Options {
+    time_limit: Option<Duration>, // Wall-clock budget for progressive rendering
+    crop: Option<CropWindow>,     // Region of the image to render
+    crop_full_size: bool,         // Write the crop into a full-size black image
+    scene: String,                // Name of the scene to render
+    seed: u64,                    // Seed for randomly generated scenes
+    serve: Option<String>,        // Coordinate a distributed render on this address
+    worker: Option<String>,       // Render for the coordinator at this address
+    local_workers: u32,           // Worker processes to start next to the coordinator
+    tile_size: u32,               // Edge length of distributed tiles in pixels
+    sample_chunk: Option<u32>,    // Samples per pixel in one distributed work item
+    aovs: Vec<Aov>,               // Output variables to write next to the image
+    aov_prefix: String,           // Path prefix of the AOV files
+    denoise: Option<f64>,         // Strength of the denoiser applied to the image
+    display: DisplayTransform,    // Exposure and tone mapping of the written image
+    quantizer: Quantizer,         // Rounding and dithering of the written image
+    spectral: bool,               // Trace hero wavelengths instead of RGB
+}
 
-    let count = world.count();
-    let world = HittableList::from_hittable(BVHNode::new(&mut world.objects, 0, count));
+/// Parses the value following a flag.
+fn parse_value<T: std::str::FromStr>(value: Option<String>, flag: &str) -> T {
+    value
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| panic!("{} expects a value", flag))
+}
 
-    cam.render(world);
+/// Parses a comma separated list of exactly n numbers.
+fn parse_list<T: std::str::FromStr>(value: Option<String>, n: usize, flag: &str) -> Vec<T> {
+    let list: Vec<T> = value
+        .iter()
+        .flat_map(|s| s.split(','))
+        .map(|s| s.trim().parse().ok())
+        .collect::<Option<_>>()
+        .unwrap_or_default();
+    assert!(
+        list.len() == n,
+        "{} expects {} comma separated numbers",
+        flag,
+        n
+    );
+    list
 }
 
-fn bouncing_spheres() -> (HittableList, Camera) {
-    let mut world = HittableList::new();
-
-    // let ground_material: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    let checker = Rc::new(CheckerTexture::from_colors(
-        0.32,
-        Vec3(0.2, 0.3, 0.1),
-        Vec3(0.9, 0.9, 0.9),
-    ));
-    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(0., -1000., 0.),
-        1000.,
-        &ground_material,
-    )));
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = rand::random::<f64>();
-            let center = Vec3(
-                a as f64 + 0.9 * rand::random::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rand::random::<f64>(),
-            );
-
-            if (center - Vec3(4., 0.2, 0.)).length() > 0.9 {
-                if choose_mat < 0.8 {
-                    //  diffuse
-                    let albedo = Color::random() * Color::random();
-                    let material = Rc::new(Lambertian::new(albedo));
-                    let center2 = center + Vec3(0., util::random_f64(0., 0.2), 0.);
-                    let sphere = Sphere::moving(center, center2, 0.2, material);
-                    world.add(Rc::new(sphere));
-                } else if choose_mat < 0.95 {
-                    // metal
-                    let albedo = Color::random_mm(0.5, 1.);
-                    let fuzz = util::random_f64(0., 0.5);
-                    let material: Rc<dyn Material> = Rc::new(Metal::new(albedo, fuzz));
-                    let sphere = Sphere::stationary(center, 0.2, &material);
-                    world.add(Rc::new(sphere));
-                } else {
-                    // glass
-                    let material: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
-                    let sphere = Sphere::stationary(center, 0.2, &material);
-                    world.add(Rc::new(sphere));
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Options {
+            time_limit: None,
+            crop: None,
+            crop_full_size: false,
+            scene: String::from("checkered_spheres"),
+            seed: rand::random(),
+            serve: None,
+            worker: None,
+            local_workers: 0,
+            tile_size: 32,
+            sample_chunk: None,
+            aovs: Vec::new(),
+            aov_prefix: String::from("aov"),
+            denoise: None,
+            display: DisplayTransform::default(),
+            quantizer: Quantizer::default(),
+            spectral: false,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--time-limit" => {
+                    let secs: f64 = args
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .expect("--time-limit expects a number of seconds");
+                    options.time_limit = Some(Duration::from_secs_f64(secs));
+                }
+                "--crop" => {
+                    let c: Vec<u32> = parse_list(args.next(), 4, "--crop");
+                    options.crop = Some(CropWindow::Pixels(Tile::new(c[0], c[1], c[2], c[3])));
                 }
+                "--crop-normalized" => {
+                    let c: Vec<f64> = parse_list(args.next(), 4, "--crop-normalized");
+                    options.crop = Some(CropWindow::Normalized(c[0], c[1], c[2], c[3]));
+                }
+                "--crop-full-size" => options.crop_full_size = true,
+                "--scene" => options.scene = parse_value(args.next(), "--scene"),
+                "--seed" => options.seed = parse_value(args.next(), "--seed"),
+                "--serve" => options.serve = Some(parse_value(args.next(), "--serve")),
+                "--worker" => options.worker = Some(parse_value(args.next(), "--worker")),
+                "--local-workers" => {
+                    options.local_workers = parse_value(args.next(), "--local-workers")
+                }
+                "--tile-size" => options.tile_size = parse_value(args.next(), "--tile-size"),
+                "--sample-chunk" => {
+                    options.sample_chunk = Some(parse_value(args.next(), "--sample-chunk"))
+                }
+                "--aov" => {
+                    let names: String = parse_value(args.next(), "--aov");
+                    options.aovs = match names.as_str() {
+                        "all" => Aov::ALL.to_vec(),
+                        _ => names
+                            .split(',')
+                            .map(|name| {
+                                Aov::from_name(name)
+                                    .unwrap_or_else(|| panic!("unknown AOV: {}", name))
+                            })
+                            .collect(),
+                    };
+                }
+                "--aov-prefix" => options.aov_prefix = parse_value(args.next(), "--aov-prefix"),
+                "--denoise" => options.denoise = Some(parse_value(args.next(), "--denoise")),
+                "--exposure" => options.display.exposure = parse_value(args.next(), "--exposure"),
+                "--tonemap" => {
+                    let name: String = parse_value(args.next(), "--tonemap");
+                    options.display.tone_map = match name.split_once(':') {
+                        None if name == "clamp" => ToneMap::Clamp,
+                        None if name == "reinhard" => ToneMap::Reinhard,
+                        None if name == "aces" => ToneMap::Aces,
+                        None if name == "hable" => ToneMap::Hable,
+                        Some(("reinhard-extended", white)) => {
+                            let white: f64 = white.parse().expect("white point must be a number");
+                            assert!(
+                                white.is_finite() && white > 0.,
+                                "white point must be positive"
+                            );
+                            ToneMap::ReinhardExtended(white)
+                        }
+                        _ => panic!(
+                            "unknown tone map: {} (expected clamp, reinhard, \
+                             reinhard-extended:<white>, aces or hable)",
+                            name
+                        ),
+                    };
+                }
+                "--quantize" => {
+                    let name: String = parse_value(args.next(), "--quantize");
+                    options.quantizer.round_to_nearest = match name.as_str() {
+                        "truncate" => false,
+                        "nearest" => true,
+                        _ => panic!(
+                            "unknown quantization: {} (expected truncate or nearest)",
+                            name
+                        ),
+                    };
+                }
+                "--dither" => {
+                    let name: String = parse_value(args.next(), "--dither");
+                    options.quantizer.dither = match name.as_str() {
+                        "none" => Dither::None,
+                        "triangular" => Dither::Triangular,
+                        "bayer" => Dither::Bayer,
+                        "blue-noise" => Dither::BlueNoise,
+                        _ => panic!(
+                            "unknown dither: {} (expected none, triangular, bayer or blue-noise)",
+                            name
+                        ),
+                    };
+                }
+                "--spectral" => options.spectral = true,
+                _ => panic!("unknown argument: {}", arg),
+            }
+        }
+
+        options
+    }
+}
+
+fn main() {
+    let options = Options::from_args();
+
+    if let Some(addr) = &options.worker {
+        distributed::run_worker(addr.as_str(), |description| {
+            let mut words = description.split_whitespace();
+            let name = words.next().expect("scene name");
+            let seed = words
+                .next()
+                .and_then(|s| s.parse().ok())
+                .expect("scene seed");
+            let (world, mut cam) = scenes::build(name, seed);
+            cam.spectral = words.next() == Some("spectral");
+            (world, cam)
+        })
+        .expect("worker failed");
+        return;
+    }
+
+    let (world, mut cam) = scenes::build(&options.scene, options.seed);
+
+    cam.time_budget = options.time_limit;
+    cam.crop = options.crop.map(|crop| match crop {
+        CropWindow::Pixels(tile) => tile,
+        CropWindow::Normalized(x0, y0, x1, y1) => {
+            Tile::from_normalized(x0, y0, x1, y1, cam.image_width, cam.image_height())
+        }
+    });
+    cam.crop_full_size = options.crop_full_size;
+    cam.spectral = options.spectral;
+    cam.aovs = options.aovs.clone();
+    if options.denoise.is_some() {
+        // The denoiser is guided by these, whether or not they are written.
+        for aov in [Aov::Albedo, Aov::Normal, Aov::Depth] {
+            if !cam.aovs.contains(&aov) {
+                cam.aovs.push(aov);
             }
         }
     }
 
-    let material_1: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(0., 1., 0.),
-        1.,
-        &material_1,
-    )));
-
-    let material_3: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(4., 1., 0.),
-        1.0,
-        &material_3,
-    )));
-    let material_2: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(-4., 1., 0.),
-        1.,
-        &material_2,
-    )));
-
-    // Set up camera
-    let aspect_ratio = 16.0_f64 / 9.0_f64;
-    // let image_width = 800;
-    // let samples_per_pixel = 100;
-    // let max_depth = 50;
-    let image_width = 400;
-    let samples_per_pixel = 20;
-    let max_depth = 20;
-
-    let vfov = 20.;
-    let lookfrom = Vec3(13., 2., 3.);
-    let lookat = Vec3(0., 0., 0.);
-    let vup = Vec3(0., 1., 0.);
-    let defocus_angle = 0.6;
-    let focus_distance = 10.0;
-
-    let camera = Camera::new(
-        aspect_ratio,
-        image_width,
-        samples_per_pixel,
-        max_depth,
-        vfov,
-        lookfrom,
-        lookat,
-        vup,
-        focus_distance,
-        defocus_angle,
-    );
+    match &options.serve {
+        Some(addr) => serve(&options, addr, &cam),
+        None => {
+            let fb = cam.render_progressive(&world);
+            if !options.aovs.is_empty() {
+                fb.aovs()
+                    .unwrap()
+                    .write_files(&options.aov_prefix, &options.aovs)
+                    .expect("cannot write AOV files");
+            }
+            let fb = match options.denoise {
+                Some(strength) => Denoiser::new(strength).denoise(&fb),
+                None => fb,
+            };
+            write_image(&fb, options.display, options.quantizer);
+        }
+    }
+}
 
-    (world, camera)
+/// Writes the framebuffer to stdout as a PPM image.
+fn write_image(fb: &Framebuffer, display: DisplayTransform, quantizer: Quantizer) {
+    fb.to_ppm()
+        .with_display(display)
+        .with_quantizer(quantizer)
+        .write_to_buffer(&mut BufWriter::new(std::io::stdout()));
 }
 
-fn checkered_spheres() -> (HittableList, Camera) {
-    let mut world = HittableList::new();
-
-    let checker = Rc::new(CheckerTexture::from_colors(
-        0.32,
-        Vec3(0.2, 0.3, 0.1),
-        Vec3(0.9, 0.9, 0.9),
-    ));
-
-    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(0., -10., 0.),
-        10.,
-        &ground_material,
-    )));
-    world.add(Rc::new(Sphere::stationary(
-        Vec3(0., 10., 0.),
-        10.,
-        &ground_material,
-    )));
-
-    // Set up camera
-    let aspect_ratio = 16.0_f64 / 9.0_f64;
-    let image_width = 400;
-    let samples_per_pixel = 100;
-    let max_depth = 50;
-
-    let vfov = 20.;
-    let lookfrom = Vec3(13., 2., 3.);
-    let lookat = Vec3(0., 0., 0.);
-    let vup = Vec3(0., 1., 0.);
-    let defocus_angle = 0.;
-    let focus_distance = 10.0;
-
-    let camera = Camera::new(
-        aspect_ratio,
-        image_width,
-        samples_per_pixel,
-        max_depth,
-        vfov,
-        lookfrom,
-        lookat,
-        vup,
-        focus_distance,
-        defocus_angle,
+/// Renders the scene by handing out tiles to workers connecting to addr,
+/// including any local worker processes requested.
+fn serve(options: &Options, addr: &str, cam: &Camera) {
+    assert!(
+        options.time_limit.is_none() && options.aovs.is_empty() && options.denoise.is_none(),
+        "--time-limit, --aov and --denoise are not supported for distributed renders"
     );
+    let coordinator = Coordinator::bind(addr).expect("cannot listen on address");
+    let local_addr = coordinator.local_addr().unwrap().to_string();
+
+    let exe = std::env::current_exe().expect("cannot locate own executable");
+    let mut children: Vec<_> = (0..options.local_workers)
+        .map(|_| {
+            Command::new(&exe)
+                .args(["--worker", &local_addr])
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("cannot start local worker")
+        })
+        .collect();
+
+    let region = cam.region();
+    let samples = cam.samples_per_pixel;
+    let chunk = options.sample_chunk.unwrap_or(samples);
+    let items = WorkItem::split(&region, options.tile_size, samples, chunk);
+    let mut description = format!("{} {}", options.scene, options.seed);
+    if options.spectral {
+        description.push_str(" spectral");
+    }
+    let fb = coordinator
+        .run(&description, cam.image_width, cam.image_height(), items)
+        .expect("distributed render failed");
+    for child in &mut children {
+        child.wait().expect("local worker failed");
+    }
 
-    (world, camera)
+    let fb = match cam.crop_full_size {
+        true => fb,
+        false => fb.crop(&region),
+    };
+    write_image(&fb, options.display, options.quantizer);
 }
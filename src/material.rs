@@ -1,20 +1,59 @@
+use std::f64::consts::PI;
 use std::rc::Rc;
 
 use crate::{
     color::Color,
     hittable::HitRecord,
+    light::Power,
+    medium::{Ior, Medium},
+    microfacet::{Ggx, fresnel_conductor, fresnel_dielectric},
     ray::Ray,
-    texture::{SolidColor, Texture},
-    vec3::Vec3,
+    spectrum::LUMINOUS_EFFICACY,
+    texture::{SolidColor, TexCoords, Texture},
+    vec3::{Onb, Vec3},
 };
 
 pub struct ScatterResult {
     pub scattered: Ray,
-    pub attenuation: Color,
+    pub attenuation: Color, // BSDF times cosine over pdf of the scattered direction
+    pub pdf: Option<f64>,   // Solid angle density of the direction; None if specular
 }
 
+/// In `eval` and `pdf`, r_in is the ray arriving at the hit, as passed to
+/// `scatter`, and wi the unit direction from the hit towards the light.
 pub trait Material {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult>;
+
+    /// Surface color at the hit, as written to the albedo AOV.
+    fn albedo(&self, rec: &HitRecord) -> Color;
+
+    /// BSDF times the cosine of wi to the normal. Zero for specular
+    /// materials, whose directions can only be sampled.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    /// Density with which `scatter` picks wi.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f64 {
+        0.
+    }
+
+    /// Medium filling the inside of the surface, entered by rays that
+    /// are transmitted through its front face.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Radiance the surface emits at the hit back along r_in.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    /// Texture of the radiance emitted from the front face, for
+    /// registering surfaces made of the material as lights.
+    fn emission(&self) -> Option<Rc<dyn Texture>> {
+        None
+    }
 }
 
 pub struct Lambertian {
@@ -42,11 +81,83 @@ impl Material for Lambertian {
             scattered_direction = rec.normal;
         }
         let scattered = Ray::new(rec.point, scattered_direction, r_in.time());
+        let pdf = self.pdf(r_in, rec, scattered.dir().unit());
         Some(ScatterResult {
             scattered,
-            attenuation: self.tex.value(rec.u, rec.v, rec.point),
+            attenuation: self.tex.value_at(&rec.tex_coords()),
+            pdf: Some(pdf),
         })
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.value_at(&rec.tex_coords())
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.tex.value_at(&rec.tex_coords()) * (wi.dot(&rec.normal).max(0.) / PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        wi.dot(&rec.normal).max(0.) / PI
+    }
+}
+
+/// Surface glowing with the radiance of a texture from its front face,
+/// such as a lamp shade or a neon tube. It reflects nothing.
+pub struct DiffuseLight {
+    emit: Rc<dyn Texture>, // Emitted radiance in W/(m² sr)
+}
+
+impl DiffuseLight {
+    pub fn new(radiance: Color) -> Self {
+        Self {
+            emit: Rc::new(SolidColor::new(radiance)),
+        }
+    }
+
+    pub fn from_texture(emit: Rc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+
+    /// Surface of the given area emitting power, in the color of color,
+    /// such as a panel rated in lumens. Meshes give their area with
+    /// `TriangleMesh::area`.
+    pub fn from_power(color: Color, power: Power, area: f64) -> Self {
+        assert!(
+            area > 0.,
+            "an emitter needs an area to spread its power over"
+        );
+        assert!(color.luminance() > 0., "an emitter needs a visible color");
+        Self::new(color * (power.watts() / (PI * area * color.luminance())))
+    }
+
+    /// Surface emitting the photometric luminance nits in cd/m², in the
+    /// color of color, as the brightness of screens and panels is rated.
+    pub fn from_luminance(color: Color, nits: f64) -> Self {
+        assert!(color.luminance() > 0., "an emitter needs a visible color");
+        Self::new(color * (nits / (LUMINOUS_EFFICACY * color.luminance())))
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterResult> {
+        None
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::new(0., 0., 0.)
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        match rec.front_face {
+            true => self.emit.value_at(&rec.tex_coords()),
+            false => Color::new(0., 0., 0.),
+        }
+    }
+
+    fn emission(&self) -> Option<Rc<dyn Texture>> {
+        Some(Rc::clone(&self.emit))
+    }
 }
 
 pub struct Metal {
@@ -56,13 +167,7 @@ pub struct Metal {
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Self {
-        let fuzz = if fuzz < 0. {
-            0.
-        } else if fuzz > 1. {
-            1.
-        } else {
-            fuzz
-        };
+        let fuzz = fuzz.clamp(0., 1.);
         Self { albedo, fuzz }
     }
 }
@@ -71,24 +176,56 @@ impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
         let reflected =
             r_in.dir().reflect(&rec.normal).unit() + self.fuzz * Vec3::random_unit_vector();
-        let scattered = Ray::new(rec.point, reflected, r_in.time());
+        let scattered = Ray::new(rec.point, reflected, r_in.time())
+            .with_differential(rec.reflected_differential(r_in, reflected));
         match scattered.dir().dot(&rec.normal) > 0. {
             true => Some(ScatterResult {
                 scattered,
                 attenuation: self.albedo,
+                pdf: None,
             }),
             false => None,
         }
     }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Dielectric {
-    refraction_index: f64,
+    medium: Medium,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::from_ior(Ior::Constant(refraction_index))
+    }
+
+    /// Glass whose refractive index varies with wavelength, dispersing
+    /// light in spectral rendering.
+    pub fn from_ior(ior: Ior) -> Self {
+        Self {
+            medium: Medium::new(ior),
+        }
+    }
+
+    /// Sets the absorption coefficient of the inside per unit distance.
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.medium.absorption = absorption;
+        self
+    }
+
+    /// Sets the absorption so that light keeps the fraction transmittance
+    /// of its intensity after travelling distance inside.
+    pub fn with_transmittance(self, transmittance: Color, distance: f64) -> Self {
+        self.with_absorption(Medium::absorption_for(transmittance, distance))
+    }
+
+    /// Sets the priority of the inside where it overlaps other media.
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.medium.priority = priority;
+        self
     }
 
     fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -100,26 +237,1367 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
-        let ri = if rec.front_face {
-            1. / self.refraction_index
-        } else {
-            self.refraction_index
-        };
+        let (eta_i, eta_t) =
+            r_in.media()
+                .refraction_indices(&self.medium, rec.front_face, r_in.wavelength());
+        let ri = eta_i / eta_t;
 
         let unit_direction = r_in.dir().unit();
         let cos_theta = -unit_direction.dot(&rec.normal.unit());
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
         let cannot_refract = ri * sin_theta > 1.;
 
-        let dir = if cannot_refract || Self::reflectance(cos_theta, ri) > rand::random::<f64>() {
-            unit_direction.reflect(&rec.normal)
-        } else {
-            unit_direction.refract(&rec.normal.unit(), ri).unit()
-        };
+        let (dir, differential) =
+            if cannot_refract || Self::reflectance(cos_theta, ri) > rand::random::<f64>() {
+                let dir = unit_direction.reflect(&rec.normal);
+                (dir, rec.reflected_differential(r_in, dir))
+            } else {
+                let dir = unit_direction.refract(&rec.normal.unit(), ri).unit();
+                (dir, rec.refracted_differential(r_in, dir, ri))
+            };
 
         Some(ScatterResult {
-            scattered: Ray::new(rec.point, dir, r_in.time()),
+            scattered: Ray::new(rec.point, dir, r_in.time()).with_differential(differential),
             attenuation: Color::new(1., 1., 1.),
+            pdf: None,
+        })
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
+
+/// Rough metal: GGX microfacets with the Fresnel reflectance of a complex
+/// refractive index eta + ik given per color channel.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    ggx: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self {
+            eta,
+            k,
+            ggx: Ggx::new(roughness),
+        }
+    }
+
+    // Refractive indices at 650, 550 and 450 nm.
+
+    pub fn gold(roughness: f64) -> Self {
+        let eta = Color::new(0.143, 0.374, 1.442);
+        let k = Color::new(3.983, 2.385, 1.603);
+        Self::new(eta, k, roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        let eta = Color::new(0.200, 0.924, 1.102);
+        let k = Color::new(3.912, 2.452, 2.142);
+        Self::new(eta, k, roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        let eta = Color::new(1.657, 0.880, 0.521);
+        let k = Color::new(9.224, 6.270, 4.837);
+        Self::new(eta, k, roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Self {
+        let eta = Color::new(0.155, 0.117, 0.138);
+        let k = Color::new(4.828, 3.122, 2.147);
+        Self::new(eta, k, roughness)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let onb = Onb::new(rec.normal);
+        let wo = onb.to_local(-r_in.dir().unit());
+        let wi = self.ggx.sample_reflection(wo);
+        let (m, value, pdf) = self.ggx.reflection(wo, wi)?;
+
+        Some(ScatterResult {
+            scattered: Ray::new(rec.point, onb.to_world(wi), r_in.time()),
+            attenuation: fresnel_conductor(wo.dot(&m), self.eta, self.k) * (value / pdf),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        fresnel_conductor(1., self.eta, self.k)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let onb = Onb::new(rec.normal);
+        let (wo, wi) = (onb.to_local(-r_in.dir().unit()), onb.to_local(wi));
+        match self.ggx.reflection(wo, wi) {
+            Some((m, value, _)) => fresnel_conductor(wo.dot(&m), self.eta, self.k) * value,
+            None => Color::new(0., 0., 0.),
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let onb = Onb::new(rec.normal);
+        let (wo, wi) = (onb.to_local(-r_in.dir().unit()), onb.to_local(wi));
+        self.ggx.reflection(wo, wi).map_or(0., |(_, _, pdf)| pdf)
+    }
+}
+
+/// Rough glass: GGX microfacets that reflect or refract, after Walter et
+/// al., "Microfacet Models for Refraction through Rough Surfaces" (2007).
+pub struct RoughDielectric {
+    medium: Medium,
+    ggx: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: f64) -> Self {
+        Self::from_ior(Ior::Constant(refraction_index), roughness)
+    }
+
+    pub fn from_ior(ior: Ior, roughness: f64) -> Self {
+        Self {
+            medium: Medium::new(ior),
+            ggx: Ggx::new(roughness),
+        }
+    }
+
+    /// Sets the absorption coefficient of the inside per unit distance.
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.medium.absorption = absorption;
+        self
+    }
+
+    /// Sets the absorption so that light keeps the fraction transmittance
+    /// of its intensity after travelling distance inside.
+    pub fn with_transmittance(self, transmittance: Color, distance: f64) -> Self {
+        self.with_absorption(Medium::absorption_for(transmittance, distance))
+    }
+
+    /// Sets the priority of the inside where it overlaps other media.
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.medium.priority = priority;
+        self
+    }
+
+    /// Ratio of the refractive index behind the surface to the one in
+    /// front of it, as seen from the side of the normal.
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let (eta_i, eta_t) =
+            r_in.media()
+                .refraction_indices(&self.medium, rec.front_face, r_in.wavelength());
+        eta_t / eta_i
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let eta = self.eta(r_in, rec);
+        let onb = Onb::new(rec.normal);
+        let wo = onb.to_local(-r_in.dir().unit());
+        let wi = self.ggx.sample_dielectric(eta, wo);
+        let (value, pdf) = self.ggx.dielectric(eta, wo, wi);
+        if pdf == 0. {
+            return None;
+        }
+
+        Some(ScatterResult {
+            scattered: Ray::new(rec.point, onb.to_world(wi), r_in.time()),
+            attenuation: Color::new(1., 1., 1.) * (value / pdf),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::new(1., 1., 1.)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let onb = Onb::new(rec.normal);
+        let wo = onb.to_local(-r_in.dir().unit());
+        let (value, _) = self
+            .ggx
+            .dielectric(self.eta(r_in, rec), wo, onb.to_local(wi));
+        Color::new(value, value, value)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let onb = Onb::new(rec.normal);
+        let wo = onb.to_local(-r_in.dir().unit());
+        self.ggx
+            .dielectric(self.eta(r_in, rec), wo, onb.to_local(wi))
+            .1
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+}
+
+/// Luminance weights of the Disney BRDF, used for tints and lobe choice.
+fn luminance(c: Color) -> f64 {
+    0.3 * c.x() + 0.6 * c.y() + 0.1 * c.z()
+}
+
+/// Schlick's approximation of the Fresnel factor, (1 - cos)^5.
+fn schlick_weight(cos: f64) -> f64 {
+    (1. - cos.clamp(0., 1.)).powi(5)
+}
+
+fn schlick(f0: Color, cos: f64) -> Color {
+    f0 + (Color::new(1., 1., 1.) - f0) * schlick_weight(cos)
+}
+
+/// Principled BSDF in the style of Burley, "Physically Based Shading at
+/// Disney" (2012) and "Extending the Disney BRDF to a BSDF with Integrated
+/// Subsurface Scattering" (2015).
+///
+/// Blends a diffuse base with sheen, a specular GGX layer, a glass lobe
+/// and a clear coat. Every parameter is a texture; scalar parameters read
+/// its first channel. Each scatter picks one lobe by its estimated
+/// contribution and weights the sample by the full BSDF over the combined
+/// density of all lobes.
+pub struct Principled {
+    base_color: Rc<dyn Texture>,
+    metallic: Rc<dyn Texture>,            // Blend from dielectric to metal
+    roughness: Rc<dyn Texture>,           // Roughness of the specular and glass lobes
+    specular: Rc<dyn Texture>,            // Dielectric reflectance, 0.5 for 4% at normal incidence
+    specular_tint: Rc<dyn Texture>,       // Tints dielectric reflection with the base hue
+    sheen: Rc<dyn Texture>,               // Grazing retroreflection of cloth
+    clearcoat: Rc<dyn Texture>,           // Strength of a clear coat layer
+    clearcoat_roughness: Rc<dyn Texture>, // Roughness of the clear coat
+    transmission: Rc<dyn Texture>,        // Blend from opaque to glass
+    ior: Rc<dyn Texture>,                 // Refractive index of the glass lobe
+    anisotropic: Rc<dyn Texture>,         // Stretches highlights along the shading frame's u axis
+}
+
+/// Parameters of a Principled material at one hit, with the lobe weights
+/// and selection probabilities derived from them.
+struct PrincipledLobes {
+    base: Color,
+    tint: Color,
+    roughness: f64,
+    sheen: f64,
+    clearcoat: f64,
+    eta: f64,
+    spec0: Color,
+    ggx: Ggx,
+    coat: Ggx,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    glass_weight: f64,
+    probabilities: [f64; 4], // Diffuse, specular, glass, clear coat
+}
+
+impl Principled {
+    /// Creates a rough dielectric with the given base color and the
+    /// defaults of the Disney BRDF for everything else.
+    pub fn new(base_color: Rc<dyn Texture>) -> Self {
+        let gray = |x: f64| Rc::new(SolidColor::gray(x)) as Rc<dyn Texture>;
+        Self {
+            base_color,
+            metallic: gray(0.),
+            roughness: gray(0.5),
+            specular: gray(0.5),
+            specular_tint: gray(0.),
+            sheen: gray(0.),
+            clearcoat: gray(0.),
+            clearcoat_roughness: gray(0.03),
+            transmission: gray(0.),
+            ior: gray(1.5),
+            anisotropic: gray(0.),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Rc<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Rc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_specular(mut self, specular: Rc<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_specular_tint(mut self, specular_tint: Rc<dyn Texture>) -> Self {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: Rc<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Rc<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn with_clearcoat_roughness(mut self, clearcoat_roughness: Rc<dyn Texture>) -> Self {
+        self.clearcoat_roughness = clearcoat_roughness;
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: Rc<dyn Texture>) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn with_ior(mut self, ior: Rc<dyn Texture>) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn with_anisotropic(mut self, anisotropic: Rc<dyn Texture>) -> Self {
+        self.anisotropic = anisotropic;
+        self
+    }
+
+    /// Looks up the parameters at a hit, seen from the local direction wo.
+    fn lobes(&self, rec: &HitRecord, wo: Vec3) -> PrincipledLobes {
+        let coords = rec.tex_coords();
+        let color = |tex: &Rc<dyn Texture>| tex.value_at(&coords);
+        let scalar = |tex: &Rc<dyn Texture>| color(tex).x().clamp(0., 1.);
+
+        let base = color(&self.base_color);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+        let ior = color(&self.ior).x().max(1.);
+        let eta = if rec.front_face { ior } else { 1. / ior };
+
+        let white = Color::new(1., 1., 1.);
+        let lum = luminance(base);
+        let tint = if lum > 0. { base / lum } else { white };
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_spec0 =
+            0.08 * scalar(&self.specular) * ((1. - specular_tint) * white + specular_tint * tint);
+        let spec0 = (1. - metallic) * dielectric_spec0 + metallic * base;
+
+        let diffuse_weight = (1. - metallic) * (1. - transmission);
+        let glass_weight = (1. - metallic) * transmission;
+        let specular_weight = 1. - glass_weight;
+
+        let probabilities = [
+            diffuse_weight * luminance(base).max(scalar(&self.sheen)),
+            specular_weight * luminance(schlick(spec0, wo.z())),
+            glass_weight,
+            0.25 * clearcoat * schlick_weight(wo.z()).max(0.04),
+        ];
+        let total: f64 = probabilities.iter().sum();
+
+        PrincipledLobes {
+            base,
+            tint,
+            roughness,
+            sheen: scalar(&self.sheen),
+            clearcoat,
+            eta,
+            spec0,
+            ggx: Ggx::anisotropic(roughness, scalar(&self.anisotropic)),
+            coat: Ggx::new(scalar(&self.clearcoat_roughness)),
+            diffuse_weight,
+            specular_weight,
+            glass_weight,
+            probabilities: match total > 0. {
+                true => probabilities.map(|p| p / total),
+                false => [0.; 4],
+            },
+        }
+    }
+}
+
+impl PrincipledLobes {
+    /// BSDF times cosine, in the local frame.
+    fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        let mut value = Color::new(0., 0., 0.);
+
+        if wo.z() > 0. && wi.z() > 0. {
+            let h = (wo + wi).unit();
+            let cos_d = wi.dot(&h);
+
+            if self.diffuse_weight > 0. {
+                // Burley's diffuse with retroreflection at grazing angles.
+                let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+                let fl = 1. + (fd90 - 1.) * schlick_weight(wi.z());
+                let fv = 1. + (fd90 - 1.) * schlick_weight(wo.z());
+                let diffuse = self.base * (fl * fv / PI);
+                // Sheen tinted halfway towards the base hue, as in Disney's
+                // default sheen tint.
+                let sheen_color = 0.5 * (Color::new(1., 1., 1.) + self.tint);
+                let sheen = sheen_color * (self.sheen * schlick_weight(cos_d));
+                value += (diffuse + sheen) * (self.diffuse_weight * wi.z());
+            }
+
+            if let Some((m, specular, _)) = self.ggx.reflection(wo, wi) {
+                value += schlick(self.spec0, wo.dot(&m)) * (self.specular_weight * specular);
+            }
+
+            if self.clearcoat > 0.
+                && let Some((m, coat, _)) = self.coat.reflection(wo, wi)
+            {
+                let f = 0.04 + 0.96 * schlick_weight(wo.dot(&m));
+                value += Color::new(1., 1., 1.) * (0.25 * self.clearcoat * f * coat);
+            }
+        }
+
+        if self.glass_weight > 0. {
+            let (glass, _) = self.ggx.dielectric(self.eta, wo, wi);
+            // Light passing through the glass takes on the base color.
+            let tint = if wi.z() < 0. {
+                self.base
+            } else {
+                Color::new(1., 1., 1.)
+            };
+            value += tint * (self.glass_weight * glass);
+        }
+
+        value
+    }
+
+    /// Combined density of the lobes, in the local frame.
+    fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let [diffuse, specular, glass, coat] = self.probabilities;
+        let mut pdf = 0.;
+        if wo.z() > 0. && wi.z() > 0. {
+            pdf += diffuse * wi.z() / PI;
+            pdf += specular * self.ggx.reflection(wo, wi).map_or(0., |r| r.2);
+            pdf += coat * self.coat.reflection(wo, wi).map_or(0., |r| r.2);
+        }
+        if glass > 0. {
+            pdf += glass * self.ggx.dielectric(self.eta, wo, wi).1;
+        }
+        pdf
+    }
+
+    /// Samples a direction from one lobe, picked by its probability.
+    fn sample(&self, wo: Vec3) -> Option<Vec3> {
+        let mut u = rand::random::<f64>();
+        let lobe = self.probabilities.iter().position(|&p| {
+            u -= p;
+            u < 0.
+        })?;
+        Some(match lobe {
+            0 => cosine_direction(),
+            1 => self.ggx.sample_reflection(wo),
+            2 => self.ggx.sample_dielectric(self.eta, wo),
+            _ => self.coat.sample_reflection(wo),
+        })
+    }
+}
+
+/// Cosine-distributed direction around +z.
+fn cosine_direction() -> Vec3 {
+    let r = rand::random::<f64>().sqrt();
+    let phi = 2. * PI * rand::random::<f64>();
+    Vec3(r * phi.cos(), r * phi.sin(), (1. - r * r).sqrt())
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let onb = rec.shading_frame();
+        let wo = onb.to_local(-r_in.dir().unit());
+        let lobes = self.lobes(rec, wo);
+        let wi = lobes.sample(wo)?;
+        let pdf = lobes.pdf(wo, wi);
+        if pdf == 0. {
+            return None;
+        }
+
+        Some(ScatterResult {
+            scattered: Ray::new(rec.point, onb.to_world(wi), r_in.time()),
+            attenuation: lobes.eval(wo, wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.value_at(&rec.tex_coords())
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let onb = rec.shading_frame();
+        let wo = onb.to_local(-r_in.dir().unit());
+        self.lobes(rec, wo).eval(wo, onb.to_local(wi))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let onb = rec.shading_frame();
+        let wo = onb.to_local(-r_in.dir().unit());
+        self.lobes(rec, wo).pdf(wo, onb.to_local(wi))
+    }
+}
+
+/// Blend of two materials by a scalar mask texture, such as paint worn
+/// through to the metal underneath. Where the mask is 0 the surface is a,
+/// where it is 1 it is b, and in between each scatter picks one of them
+/// with the mask's probability. Both materials must fill the inside with
+/// the same medium, if any, and emission blends like the rest.
+pub struct MixMaterial {
+    a: Rc<dyn Material>,
+    b: Rc<dyn Material>,
+    mask: Rc<dyn Texture>, // Weight of b, read from the first channel
+}
+
+impl MixMaterial {
+    pub fn new(a: Rc<dyn Material>, b: Rc<dyn Material>, mask: Rc<dyn Texture>) -> Self {
+        assert!(
+            a.medium() == b.medium(),
+            "mixed materials must share their medium"
+        );
+        Self { a, b, mask }
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        self.mask.value_at(&rec.tex_coords()).x().clamp(0., 1.)
+    }
+}
+
+/// Emission of a mix, blending the emission of either material by the
+/// mask.
+struct MixedEmission {
+    a: Option<Rc<dyn Texture>>,
+    b: Option<Rc<dyn Texture>>,
+    mask: Rc<dyn Texture>,
+}
+
+impl MixedEmission {
+    fn blend(&self, value: impl Fn(&dyn Texture) -> Color) -> Color {
+        let m = value(self.mask.as_ref()).x().clamp(0., 1.);
+        let emit = |tex: &Option<Rc<dyn Texture>>| {
+            tex.as_ref()
+                .map_or(Color::new(0., 0., 0.), |tex| value(tex.as_ref()))
+        };
+        (1. - m) * emit(&self.a) + m * emit(&self.b)
+    }
+}
+
+impl Texture for MixedEmission {
+    fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
+        self.blend(|tex| tex.value(u, v, point))
+    }
+
+    fn value_at(&self, coords: &TexCoords) -> Color {
+        self.blend(|tex| tex.value_at(coords))
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let chosen = match rand::random::<f64>() < self.weight(rec) {
+            true => &self.b,
+            false => &self.a,
+        };
+        let res = chosen.scatter(r_in, rec)?;
+        // The other material has no density along a specular direction,
+        // and the chance of picking this one cancels its weight.
+        if res.pdf.is_none() {
+            return Some(res);
+        }
+
+        let wi = res.scattered.dir().unit();
+        let pdf = self.pdf(r_in, rec, wi);
+        if pdf == 0. {
+            return None;
+        }
+        Some(ScatterResult {
+            attenuation: self.eval(r_in, rec, wi) / pdf,
+            pdf: Some(pdf),
+            ..res
+        })
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        let m = self.weight(rec);
+        (1. - m) * self.a.albedo(rec) + m * self.b.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let m = self.weight(rec);
+        (1. - m) * self.a.eval(r_in, rec, wi) + m * self.b.eval(r_in, rec, wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let m = self.weight(rec);
+        (1. - m) * self.a.pdf(r_in, rec, wi) + m * self.b.pdf(r_in, rec, wi)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.a.medium()
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let m = self.weight(rec);
+        (1. - m) * self.a.emitted(r_in, rec) + m * self.b.emitted(r_in, rec)
+    }
+
+    fn emission(&self) -> Option<Rc<dyn Texture>> {
+        let (a, b) = (self.a.emission(), self.b.emission());
+        if a.is_none() && b.is_none() {
+            return None;
+        }
+        Some(Rc::new(MixedEmission {
+            a,
+            b,
+            mask: Rc::clone(&self.mask),
+        }))
+    }
+}
+
+/// Smooth dielectric coating over any base material, like varnish on wood
+/// or lacquer on paint. Light reflects off the coat with its Fresnel
+/// reflectance; the rest passes through to the base, and what the base
+/// reflects passes through the coat again. Interreflections between the
+/// coat and the base are ignored, so a coated white base loses a little
+/// energy. The back face is left uncoated.
+pub struct ClearCoat {
+    base: Rc<dyn Material>,
+    refraction_index: f64,
+    tint: Color, // Transmittance of a pass through the coat at normal incidence
+}
+
+impl ClearCoat {
+    pub fn new(base: Rc<dyn Material>, refraction_index: f64) -> Self {
+        Self {
+            base,
+            refraction_index,
+            tint: Color::new(1., 1., 1.),
+        }
+    }
+
+    /// Colors the coat, which absorbs more along slanted paths.
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// Fraction of light crossing the coat at cos to the normal, through
+    /// its surface and its thickness.
+    fn transmission(&self, cos: f64) -> Color {
+        let fresnel = fresnel_dielectric(cos, self.refraction_index);
+        let sin2_t = (1. - cos * cos) / (self.refraction_index * self.refraction_index);
+        let path = 1. / (1. - sin2_t).sqrt();
+        (1. - fresnel)
+            * Color::new(
+                self.tint.x().powf(path),
+                self.tint.y().powf(path),
+                self.tint.z().powf(path),
+            )
+    }
+
+    /// Attenuation by the coat of light leaving the base towards wi. Light
+    /// transmitted into the inside of the base crosses the coat only once.
+    fn exit(&self, rec: &HitRecord, wi: Vec3) -> Color {
+        let cos_i = wi.dot(&rec.normal);
+        match cos_i > 0. {
+            true => self.transmission(cos_i),
+            false => Color::new(1., 1., 1.),
+        }
+    }
+}
+
+impl Material for ClearCoat {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec);
+        }
+        let unit_direction = r_in.dir().unit();
+        let cos_o = -unit_direction.dot(&rec.normal);
+        let reflectance = fresnel_dielectric(cos_o, self.refraction_index);
+        if rand::random::<f64>() < reflectance {
+            let dir = unit_direction.reflect(&rec.normal);
+            return Some(ScatterResult {
+                scattered: Ray::new(rec.point, dir, r_in.time())
+                    .with_differential(rec.reflected_differential(r_in, dir)),
+                attenuation: Color::new(1., 1., 1.),
+                pdf: None,
+            });
+        }
+
+        let res = self.base.scatter(r_in, rec)?;
+        let wi = res.scattered.dir().unit();
+        let coat = self.transmission(cos_o) / (1. - reflectance) * self.exit(rec, wi);
+        Some(ScatterResult {
+            attenuation: coat * res.attenuation,
+            pdf: res.pdf.map(|pdf| (1. - reflectance) * pdf),
+            ..res
         })
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let value = self.base.eval(r_in, rec, wi);
+        if !rec.front_face {
+            return value;
+        }
+        let cos_o = -r_in.dir().unit().dot(&rec.normal);
+        self.transmission(cos_o) * self.exit(rec, wi) * value
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let pdf = self.base.pdf(r_in, rec, wi);
+        if !rec.front_face {
+            return pdf;
+        }
+        let cos_o = -r_in.dir().unit().dot(&rec.normal);
+        (1. - fresnel_dielectric(cos_o, self.refraction_index)) * pdf
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+}
+
+/// Surface with different materials on its two sides, such as a leaf or
+/// a printed page. Hits on the back face reach the back material as if
+/// they were on its front, so both sides must fill the inside with the
+/// same medium, if any.
+pub struct TwoSided {
+    front: Rc<dyn Material>,
+    back: Rc<dyn Material>,
+}
+
+impl TwoSided {
+    pub fn new(front: Rc<dyn Material>, back: Rc<dyn Material>) -> Self {
+        assert!(
+            front.medium() == back.medium(),
+            "both sides must share their medium"
+        );
+        Self { front, back }
+    }
+
+    /// Calls f with the material of the side hit and the hit as that
+    /// material sees it.
+    fn side<T>(&self, rec: &HitRecord, f: impl FnOnce(&dyn Material, &HitRecord) -> T) -> T {
+        if rec.front_face {
+            f(self.front.as_ref(), rec)
+        } else {
+            let mut rec = rec.clone();
+            rec.front_face = true;
+            f(self.back.as_ref(), &rec)
+        }
+    }
+}
+
+impl Material for TwoSided {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        self.side(rec, |mat, rec| mat.scatter(r_in, rec))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.side(rec, |mat, rec| mat.albedo(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        self.side(rec, |mat, rec| mat.eval(r_in, rec, wi))
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        self.side(rec, |mat, rec| mat.pdf(r_in, rec, wi))
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.side(rec, |mat, rec| mat.emitted(r_in, rec))
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.front.medium()
+    }
+}
+
+/// Hit as seen with the shading normal n, given on the side the ray
+/// arrives from. Normals tilted past the direction towards the viewer
+/// would scatter light into the surface, so they are bent back until
+/// they just face it.
+fn with_shading_normal(r_in: &Ray, rec: &HitRecord, n: Vec3) -> HitRecord {
+    let wo = -r_in.dir().unit();
+    let min_cos = 0.01;
+    let cos = n.dot(&wo);
+    let mut rec = rec.clone();
+    rec.normal = match cos < min_cos {
+        true => (n + (min_cos - cos) * wo).unit(),
+        false => n,
+    };
+    rec
+}
+
+/// Tangent space normal map over a base material: the map's red, green
+/// and blue channels, scaled from [0, 1] to [-1, 1], give the shading
+/// normal along dp/du, dp/dv and the surface normal. Load the map with
+/// `ImageTexture::load_linear`.
+pub struct NormalMap {
+    base: Rc<dyn Material>,
+    map: Rc<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(base: Rc<dyn Material>, map: Rc<dyn Texture>) -> Self {
+        Self { base, map }
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        let (dpdu, dpdv) = rec.tangents();
+        let n = rec.normal;
+        let t = (dpdu - dpdu.dot(&n) * n).unit();
+        // Keep the bitangent along dp/dv on both faces, so that the map
+        // tilts the normal towards the same texture directions.
+        let b = n.cross(&t);
+        let b = if b.dot(&dpdv) < 0. { -b } else { b };
+
+        let c = self.map.value_at(&rec.tex_coords());
+        let (x, y, z) = (2. * c.x() - 1., 2. * c.y() - 1., 2. * c.z() - 1.);
+        (x * t + y * b + z.max(0.) * n).unit()
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.scatter(r_in, &rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.eval(r_in, &rec, wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.pdf(r_in, &rec, wi)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+}
+
+/// Bump map over a base material: shades the surface as if displaced
+/// along its outward normal by a height read from the first channel of a
+/// texture, times scale. Any texture works, including solid noise.
+pub struct BumpMap {
+    base: Rc<dyn Material>,
+    height: Rc<dyn Texture>,
+    scale: f64, // Displacement in world units for a texture value of 1
+}
+
+impl BumpMap {
+    pub fn new(base: Rc<dyn Material>, height: Rc<dyn Texture>, scale: f64) -> Self {
+        Self {
+            base,
+            height,
+            scale,
+        }
+    }
+
+    /// Normal of the displaced surface, from finite differences of the
+    /// height along the texture coordinates (Blinn, "Simulation of
+    /// Wrinkled Surfaces", 1978).
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        let (dpdu, dpdv) = rec.tangents();
+        let delta = 1e-4;
+        let coords = rec.tex_coords();
+        let height = |du: f64, dv: f64| {
+            let offset = du * dpdu + dv * dpdv;
+            let shifted = TexCoords {
+                u: coords.u + du,
+                v: coords.v + dv,
+                point: coords.point + offset,
+                object_point: coords.object_point + offset,
+                ..coords
+            };
+            self.scale * self.height.value_at(&shifted).x()
+        };
+        let h = height(0., 0.);
+        let dhdu = (height(delta, 0.) - h) / delta;
+        let dhdv = (height(0., delta) - h) / delta;
+
+        // The displaced dp/du and dp/dv gain dh/du and dh/dv times the
+        // normal. Dividing their cross product by that of the undisplaced
+        // ones keeps it outwards whatever the handedness of the tangents.
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let jacobian = dpdu.cross(&dpdv).dot(&outward);
+        let n = outward + (dhdu * outward.cross(&dpdv) + dhdv * dpdu.cross(&outward)) / jacobian;
+        if rec.front_face { n.unit() } else { -n.unit() }
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.scatter(r_in, &rec)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Color {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.eval(r_in, &rec, wi)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f64 {
+        let rec = with_shading_normal(r_in, rec, self.shading_normal(rec));
+        self.base.pdf(r_in, &rec, wi)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hit on the front of a surface facing +z, for a ray arriving along
+    /// -wo, with the material under test.
+    fn hit_record(mat: Rc<dyn Material>, wo: Vec3, front: bool) -> (Ray, HitRecord) {
+        let normal = if front {
+            Vec3(0., 0., 1.)
+        } else {
+            Vec3(0., 0., -1.)
+        };
+        let ray = Ray::new(wo, -wo, 0.);
+        let rec = HitRecord::new(Vec3(0., 0., 0.), 1., &ray, normal, mat);
+        (ray, rec)
+    }
+
+    /// Checks that the sampled weights and densities agree with `eval` and
+    /// `pdf`, and returns the estimated directional albedo.
+    fn check_sampling(mat: Rc<dyn Material>, wo: Vec3, front: bool) -> Color {
+        let (ray, rec) = hit_record(mat.clone(), wo, front);
+        let n = 20_000;
+        let mut albedo = Color::new(0., 0., 0.);
+        for _ in 0..n {
+            let Some(res) = mat.scatter(&ray, &rec) else {
+                continue;
+            };
+            let wi = res.scattered.dir().unit();
+            let pdf = mat.pdf(&ray, &rec, wi);
+            assert!(
+                (res.pdf.unwrap() - pdf).abs() <= 1e-6 * pdf,
+                "{:?} {}",
+                res.pdf,
+                pdf
+            );
+            let expected = mat.eval(&ray, &rec, wi) / pdf;
+            assert!((res.attenuation - expected).length() <= 1e-6 * expected.length());
+            albedo += res.attenuation / n as f64;
+        }
+        albedo
+    }
+
+    #[test]
+    fn lambertian_sampling_matches_pdf() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let albedo = check_sampling(mat, Vec3(0.6, 0., 0.8), true);
+        assert!((albedo - Color::new(0.5, 0.5, 0.5)).length() < 1e-6);
+    }
+
+    #[test]
+    fn conductor_sampling_matches_pdf() {
+        let wo = Vec3(0.6, 0., 0.8);
+        let albedos: Vec<Color> = [0.1, 0.5, 0.9]
+            .into_iter()
+            .map(|roughness| check_sampling(Rc::new(Conductor::gold(roughness)), wo, true))
+            .collect();
+        // Gold reflects red strongly and blue weakly.
+        for albedo in &albedos {
+            assert!(albedo.x() < 1. && albedo.z() < 0.5 * albedo.x());
+        }
+        // Single scattering microfacets lose more energy as roughness grows.
+        assert!(albedos[0].x() > 0.9);
+        assert!(albedos[0].x() > albedos[1].x() && albedos[1].x() > albedos[2].x());
+    }
+
+    #[test]
+    fn rough_dielectric_sampling_matches_pdf() {
+        let wo = Vec3(0.6, 0., 0.8);
+        for (roughness, front) in [(0.1, true), (0.5, true), (0.3, false)] {
+            let mat: Rc<dyn Material> = Rc::new(RoughDielectric::new(1.5, roughness));
+            let albedo = check_sampling(mat, wo, front);
+            // Without absorption, only the energy lost between microfacets
+            // goes missing.
+            assert!(albedo.x() > 0.9 && albedo.x() <= 1.05, "{:?}", albedo);
+        }
+    }
+
+    #[test]
+    fn pdfs_integrate_to_scatter_probability() {
+        // Integrates the densities over the sphere, against the fraction
+        // of successful scatters.
+        let wo = Vec3(0.6, 0., 0.8);
+        let materials: [Rc<dyn Material>; 3] = [
+            Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            Rc::new(Conductor::copper(0.6)),
+            Rc::new(RoughDielectric::new(1.5, 0.6)),
+        ];
+        for mat in materials {
+            let (ray, rec) = hit_record(mat.clone(), wo, true);
+            let steps = 400;
+            let mut integral = 0.;
+            for a in 0..steps {
+                let theta = (a as f64 + 0.5) / steps as f64 * PI;
+                for b in 0..2 * steps {
+                    let phi = (b as f64 + 0.5) / steps as f64 * PI;
+                    let wi = Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    integral += mat.pdf(&ray, &rec, wi) * theta.sin() * (PI / steps as f64).powi(2);
+                }
+            }
+
+            let n = 100_000;
+            let scattered =
+                (0..n).filter(|_| mat.scatter(&ray, &rec).is_some()).count() as f64 / n as f64;
+            assert!(
+                (integral - scattered).abs() < 0.01,
+                "{} {}",
+                integral,
+                scattered
+            );
+        }
+    }
+
+    #[test]
+    fn conductor_presets_work() {
+        // Gold and copper are red; silver and aluminium are nearly neutral.
+        let rec = hit_record(Rc::new(Conductor::gold(0.)), Vec3(0., 0., 1.), true).1;
+        let f0 = |mat: &dyn Material| mat.albedo(&rec);
+        let gold = f0(&Conductor::gold(0.));
+        let copper = f0(&Conductor::copper(0.));
+        let silver = f0(&Conductor::silver(0.));
+        let aluminium = f0(&Conductor::aluminium(0.));
+        assert!(gold.x() > 0.9 && gold.z() < 0.5);
+        assert!(copper.x() > 0.9 && copper.z() < 0.6);
+        assert!(silver.x() > 0.9 && silver.z() > 0.9);
+        assert!(aluminium.x() > 0.85 && aluminium.z() > 0.85);
+    }
+
+    fn gray(x: f64) -> Rc<dyn Texture> {
+        Rc::new(SolidColor::gray(x))
+    }
+
+    fn principled_variants() -> Vec<Principled> {
+        let base = || Rc::new(SolidColor::new(Color::new(0.8, 0.4, 0.2))) as Rc<dyn Texture>;
+        vec![
+            Principled::new(base()),
+            Principled::new(base())
+                .with_metallic(gray(1.))
+                .with_roughness(gray(0.3))
+                .with_anisotropic(gray(0.8)),
+            Principled::new(base())
+                .with_transmission(gray(1.))
+                .with_roughness(gray(0.2))
+                .with_ior(gray(1.4)),
+            Principled::new(base())
+                .with_sheen(gray(1.))
+                .with_clearcoat(gray(1.))
+                .with_specular_tint(gray(0.5))
+                .with_metallic(gray(0.3))
+                .with_transmission(gray(0.3)),
+        ]
+    }
+
+    #[test]
+    fn principled_sampling_matches_pdf() {
+        for front in [true, false] {
+            for mat in principled_variants() {
+                let albedo = check_sampling(Rc::new(mat), Vec3(0.6, 0., 0.8), front);
+                assert!(albedo.x() > 0. && albedo.x() < 1.05, "{:?}", albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn principled_pdf_integrates_to_scatter_probability() {
+        let wo = Vec3(0.6, 0., 0.8);
+        for mat in principled_variants() {
+            let mat: Rc<dyn Material> = Rc::new(mat);
+            let (ray, rec) = hit_record(mat.clone(), wo, true);
+            let steps = 400;
+            let mut integral = 0.;
+            for a in 0..steps {
+                let theta = (a as f64 + 0.5) / steps as f64 * PI;
+                for b in 0..2 * steps {
+                    let phi = (b as f64 + 0.5) / steps as f64 * PI;
+                    let wi = Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    integral += mat.pdf(&ray, &rec, wi) * theta.sin() * (PI / steps as f64).powi(2);
+                }
+            }
+
+            let n = 100_000;
+            let scattered =
+                (0..n).filter(|_| mat.scatter(&ray, &rec).is_some()).count() as f64 / n as f64;
+            // The midpoint rule undershoots the narrow clear coat lobe by
+            // about 0.01 at this resolution.
+            assert!(
+                (integral - scattered).abs() < 0.02,
+                "{} {}",
+                integral,
+                scattered
+            );
+        }
+    }
+
+    #[test]
+    fn principled_lobes_respond_to_parameters() {
+        let base = Rc::new(SolidColor::new(Color::new(0.8, 0.4, 0.2))) as Rc<dyn Texture>;
+        let eval = |mat: Principled, wo: Vec3, wi: Vec3| {
+            let mat: Rc<dyn Material> = Rc::new(mat);
+            let (ray, rec) = hit_record(mat.clone(), wo, true);
+            mat.eval(&ray, &rec, wi)
+        };
+        let wo = Vec3(0.6, 0., 0.8);
+        let mirror = Vec3(-0.6, 0., 0.8);
+        let below = Vec3(-0.3, 0., -0.9).unit();
+
+        // Only glass transmits, tinted by the base color.
+        let opaque = eval(Principled::new(base.clone()), wo, below);
+        assert_eq!(opaque, Color::new(0., 0., 0.));
+        let glass = eval(
+            Principled::new(base.clone()).with_transmission(gray(1.)),
+            wo,
+            below,
+        );
+        assert!(glass.x() > 0. && glass.x() > glass.z());
+
+        // Smooth metals reflect the base color in the mirror direction.
+        let metal = || {
+            Principled::new(base.clone())
+                .with_metallic(gray(1.))
+                .with_roughness(gray(0.2))
+        };
+        let reflected = eval(metal(), wo, mirror);
+        assert!(reflected.x() > 1. && reflected.x() > reflected.z());
+
+        // Turning the directions about the normal changes anisotropic
+        // highlights only.
+        let (wo_turned, mirror_turned) = (Vec3(0., 0.6, 0.8), Vec3(0., -0.6, 0.8));
+        let turned = eval(metal(), wo_turned, mirror_turned);
+        assert!((reflected - turned).length() < 1e-9);
+        let aniso = || metal().with_anisotropic(gray(0.9));
+        let turned = eval(aniso(), wo_turned, mirror_turned);
+        assert!((eval(aniso(), wo, mirror) - turned).length() > 1e-3);
+    }
+
+    #[test]
+    fn anisotropy_follows_surface_tangent() {
+        let base = Rc::new(SolidColor::new(Color::new(0.8, 0.4, 0.2))) as Rc<dyn Texture>;
+        let mat: Rc<dyn Material> = Rc::new(
+            Principled::new(base)
+                .with_metallic(gray(1.))
+                .with_roughness(gray(0.2))
+                .with_anisotropic(gray(0.9)),
+        );
+        let (wo, mirror) = (Vec3(0.6, 0., 0.8), Vec3(-0.6, 0., 0.8));
+        let (ray, rec) = hit_record(mat.clone(), wo, true);
+        let along_x = mat.eval(&ray, &rec, mirror);
+        let pdf_along_x = mat.pdf(&ray, &rec, mirror);
+
+        // With u along y the same highlight is seen from directions turned
+        // a quarter about the normal.
+        let (wo, mirror) = (Vec3(0., 0.6, 0.8), Vec3(0., -0.6, 0.8));
+        let (ray, mut rec) = hit_record(mat.clone(), wo, true);
+        assert!((mat.eval(&ray, &rec, mirror) - along_x).length() > 1e-3);
+        rec.dpdu = Vec3(0., 2., 0.);
+        rec.dpdv = Vec3(-2., 0., 0.);
+        assert!((mat.eval(&ray, &rec, mirror) - along_x).length() < 1e-9);
+        assert!((mat.pdf(&ray, &rec, mirror) - pdf_along_x).abs() < 1e-9);
+    }
+
+    #[test]
+    fn mix_sampling_matches_pdf() {
+        let paint: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.4, 0.2)));
+        let metal: Rc<dyn Material> = Rc::new(Conductor::gold(0.3));
+        let wo = Vec3(0.6, 0., 0.8);
+        let mix = |m| -> Rc<dyn Material> {
+            Rc::new(MixMaterial::new(paint.clone(), metal.clone(), gray(m)))
+        };
+        check_sampling(mix(0.3), wo, true);
+
+        // At the ends of the mask the mix is one of the materials.
+        let (ray, rec) = hit_record(paint.clone(), wo, true);
+        let wi = Vec3(-0.6, 0., 0.8);
+        assert_eq!(mix(0.).eval(&ray, &rec, wi), paint.eval(&ray, &rec, wi));
+        assert_eq!(mix(1.).eval(&ray, &rec, wi), metal.eval(&ray, &rec, wi));
+        assert_eq!(mix(1.).albedo(&rec), metal.albedo(&rec));
+        assert!(mix(0.5).emission().is_none());
+
+        // Masked light glows where the mask lets it through, and is
+        // registered as a light with the same blend.
+        let light: Rc<dyn Material> = Rc::new(DiffuseLight::new(Color::new(4., 4., 4.)));
+        let glow = MixMaterial::new(paint.clone(), light, gray(0.25));
+        let (ray, rec) = hit_record(paint.clone(), wo, true);
+        assert_eq!(glow.emitted(&ray, &rec), Color::new(1., 1., 1.));
+        let emit = glow.emission().unwrap();
+        assert_eq!(emit.value_at(&rec.tex_coords()), Color::new(1., 1., 1.));
+
+        // Glass mixed with glass keeps the medium they share.
+        let clear: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+        let frosted: Rc<dyn Material> = Rc::new(RoughDielectric::new(1.5, 0.3));
+        let glass = MixMaterial::new(clear.clone(), frosted, gray(0.5));
+        assert_eq!(glass.medium(), clear.medium());
+    }
+
+    #[test]
+    fn clear_coat_works() {
+        let white: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(1., 1., 1.)));
+        let coat: Rc<dyn Material> = Rc::new(ClearCoat::new(white.clone(), 1.5));
+        let wo = Vec3(0.6, 0., 0.8);
+        let (ray, rec) = hit_record(coat.clone(), wo, true);
+
+        let n = 20_000;
+        let mut specular = 0;
+        let mut albedo = Color::new(0., 0., 0.);
+        for _ in 0..n {
+            let res = coat.scatter(&ray, &rec).unwrap();
+            let wi = res.scattered.dir().unit();
+            match res.pdf {
+                None => {
+                    specular += 1;
+                    assert!((wi - Vec3(-0.6, 0., 0.8)).length() < 1e-12);
+                }
+                Some(pdf) => {
+                    assert!((pdf - coat.pdf(&ray, &rec, wi)).abs() <= 1e-9 * pdf);
+                    let expected = coat.eval(&ray, &rec, wi) / pdf;
+                    assert!((res.attenuation - expected).length() <= 1e-9);
+                }
+            }
+            albedo += res.attenuation / n as f64;
+        }
+
+        // Reflections off the coat follow the Fresnel reflectance, and the
+        // coat only ever takes energy from the base.
+        let reflectance = fresnel_dielectric(0.8, 1.5);
+        assert!((specular as f64 / n as f64 - reflectance).abs() < 0.01);
+        assert!(albedo.x() > 0.85 && albedo.x() < 1.);
+
+        // A tinted coat darkens the base in the colors it absorbs, the
+        // more the longer the path through it.
+        let tinted = ClearCoat::new(white, 1.5).with_tint(Color::new(1., 0.5, 0.5));
+        let wi = Vec3(0., 0., 1.);
+        let value = tinted.eval(&ray, &rec, wi);
+        let cos_t = (1. - 0.36 / 2.25f64).sqrt();
+        assert!((value.y() / value.x() - 0.5f64.powf(1. + 1. / cos_t)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn two_sided_works() {
+        let red = Color::new(0.8, 0.1, 0.1);
+        let blue = Color::new(0.1, 0.1, 0.8);
+        let mat: Rc<dyn Material> = Rc::new(TwoSided::new(
+            Rc::new(Lambertian::new(red)),
+            Rc::new(ClearCoat::new(Rc::new(Lambertian::new(blue)), 1.5)),
+        ));
+        let wo = Vec3(0.6, 0., 0.8);
+        let (_, front) = hit_record(mat.clone(), wo, true);
+        assert_eq!(mat.albedo(&front), red);
+
+        // The coat of the back material applies on the back face.
+        let (ray, back) = hit_record(mat.clone(), wo, false);
+        assert_eq!(mat.albedo(&back), blue);
+        let wi = Vec3(0., 0., 1.);
+        let uncoated = Lambertian::new(blue).eval(&ray, &back, wi);
+        assert!(mat.eval(&ray, &back, wi).z() < 0.95 * uncoated.z());
+        for _ in 0..100 {
+            let res = mat.scatter(&ray, &back).unwrap();
+            assert!(res.scattered.dir().dot(&back.normal) > 0.);
+        }
+
+        // Sides of the same glass keep its medium.
+        let clear: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+        let frosted: Rc<dyn Material> = Rc::new(RoughDielectric::new(1.5, 0.3));
+        let pane = TwoSided::new(clear.clone(), frosted);
+        assert_eq!(pane.medium(), clear.medium());
+    }
+
+    /// Hit on a surface facing +z with texture coordinates x and y.
+    fn planar_hit_record(mat: Rc<dyn Material>, front: bool) -> HitRecord {
+        let (_, mut rec) = hit_record(mat, Vec3(0., 0., 1.), front);
+        (rec.dpdu, rec.dpdv) = (Vec3(1., 0., 0.), Vec3(0., 1., 0.));
+        rec
+    }
+
+    #[test]
+    fn normal_map_works() {
+        let base: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let map =
+            |r, g, b| NormalMap::new(base.clone(), Rc::new(SolidColor::new(Color::new(r, g, b))));
+        let rec = planar_hit_record(base.clone(), true);
+
+        let flat = map(0.5, 0.5, 1.).shading_normal(&rec);
+        assert!((flat - Vec3(0., 0., 1.)).length() < 1e-12);
+        let tilted = map(0.8, 0.2, 0.9).shading_normal(&rec);
+        assert!((tilted - Vec3(0.6, -0.6, 0.8).unit()).length() < 1e-12);
+
+        // From behind, where the surface faces -z, the map tilts the normal
+        // facing the ray towards the same texture directions.
+        let back = planar_hit_record(base.clone(), false);
+        let tilted = map(0.8, 0.2, 0.9).shading_normal(&back);
+        assert!((tilted - Vec3(0.6, -0.6, 0.8).unit()).length() < 1e-12);
+    }
+
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, _: f64, _: f64, point: Vec3) -> Color {
+            Color::new(point.x(), 0., 0.)
+        }
+    }
+
+    #[test]
+    fn bump_map_works() {
+        let base: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        // Heights 0.5 x make a slope whose normal leans towards -x.
+        let bump = BumpMap::new(base.clone(), Rc::new(Ramp), 0.5);
+        let expected = Vec3(-0.5, 0., 1.).unit();
+        let front = planar_hit_record(base.clone(), true);
+        assert!((bump.shading_normal(&front) - expected).length() < 1e-9);
+
+        // Seen from behind, the surface facing -z slopes the other way.
+        // The handedness of the tangents does not matter.
+        let mut back = planar_hit_record(base.clone(), false);
+        let expected_back = Vec3(0.5, 0., 1.).unit();
+        assert!((bump.shading_normal(&back) - expected_back).length() < 1e-9);
+        back.dpdv = -back.dpdv;
+        assert!((bump.shading_normal(&back) - expected_back).length() < 1e-9);
+
+        // Shading follows the bumped normal.
+        let (ray, _) = hit_record(base.clone(), Vec3(0., 0., 1.), true);
+        let albedo = bump.eval(&ray, &front, expected) * PI;
+        assert!((albedo - Color::new(0.5, 0.5, 0.5)).length() < 1e-9);
+    }
 }
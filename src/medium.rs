@@ -0,0 +1,245 @@
+//! Homogeneous absorbing media inside dielectrics, and the stack of media
+//! a ray is travelling through.
+//!
+//! Nested media follow Schmidt and Budge, "Simple Nested Dielectrics in
+//! Ray Traced Images" (2002): every medium has a priority, and where media
+//! overlap the one with the highest priority fills the space. The surfaces
+//! of the others are false intersections there, which rays pass straight
+//! through. Liquid in a glass is modelled by letting the liquid overlap the
+//! glass wall and giving the glass the higher priority.
+
+use crate::color::Color;
+
+/// Wavelength at which dispersive indices are evaluated outside of
+/// spectral rendering: the helium d line, in nanometres.
+const D_LINE: f64 = 587.56;
+
+/// Refractive index, possibly varying with wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ior {
+    Constant(f64),
+    Cauchy(f64, f64),              // n = A + B / lambda^2, lambda in micrometres
+    Sellmeier([f64; 3], [f64; 3]), // n^2 = 1 + sum B_i lambda^2 / (lambda^2 - C_i)
+}
+
+impl Ior {
+    /// Borosilicate crown glass (Schott N-BK7).
+    pub const BK7: Ior = Ior::Sellmeier(
+        [1.03961212, 0.231792344, 1.01046945],
+        [0.00600069867, 0.0200179144, 103.560653],
+    );
+
+    /// Dense flint glass (Schott N-SF11), strongly dispersive.
+    pub const SF11: Ior = Ior::Sellmeier(
+        [1.73759695, 0.313747346, 1.89878101],
+        [0.013188707, 0.0623068142, 155.23629],
+    );
+
+    pub const DIAMOND: Ior = Ior::Sellmeier([0.3306, 4.3356, 0.], [0.030625, 0.011236, 0.]);
+
+    pub const WATER: Ior = Ior::Cauchy(1.3245, 0.00291);
+
+    /// Index at lambda in nanometres, or at the d line if there is none.
+    pub fn at(&self, lambda: Option<f64>) -> f64 {
+        let micrometres = lambda.unwrap_or(D_LINE) / 1000.;
+        let l2 = micrometres * micrometres;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Medium {
+    pub absorption: Color,     // Absorption coefficient per unit distance
+    pub refraction_index: Ior, // Index of refraction
+    pub priority: u32,         // Higher priorities win where media overlap
+}
+
+impl Medium {
+    /// Clear medium with the given refractive index and priority 0.
+    pub fn new(refraction_index: Ior) -> Self {
+        Self {
+            absorption: Color::new(0., 0., 0.),
+            refraction_index,
+            priority: 0,
+        }
+    }
+
+    /// Absorption coefficient for which light keeps the fraction
+    /// transmittance of its intensity after travelling distance.
+    pub fn absorption_for(transmittance: Color, distance: f64) -> Color {
+        let sigma = |t: f64| -t.clamp(1e-12, 1.).ln() / distance;
+        Color::new(
+            sigma(transmittance.x()),
+            sigma(transmittance.y()),
+            sigma(transmittance.z()),
+        )
+    }
+
+    /// Fraction of light left after travelling distance through the medium.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let a = self.absorption;
+        Color::new(
+            (-a.x() * distance).exp(),
+            (-a.y() * distance).exp(),
+            (-a.z() * distance).exp(),
+        )
+    }
+}
+
+/// Media a ray is inside, in the order it entered them.
+#[derive(Clone, Debug, Default)]
+pub struct MediumStack {
+    media: Vec<Medium>,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The medium filling the space around the ray: the one with the
+    /// highest priority, and of those the most recently entered.
+    pub fn current(&self) -> Option<&Medium> {
+        self.media
+            .iter()
+            .rev()
+            .reduce(|a, b| if b.priority > a.priority { b } else { a })
+    }
+
+    pub fn enter(&mut self, medium: Medium) {
+        self.media.push(medium);
+    }
+
+    /// Leaves the most recently entered medium equal to medium.
+    pub fn exit(&mut self, medium: &Medium) {
+        if let Some(i) = self.media.iter().rposition(|m| m == medium) {
+            self.media.remove(i);
+        }
+    }
+
+    /// Enters the medium when crossing its surface from the front and
+    /// leaves it when crossing from the back.
+    pub fn cross(&mut self, medium: Medium, front_face: bool) {
+        if front_face {
+            self.enter(medium);
+        } else {
+            self.exit(&medium);
+        }
+    }
+
+    /// A surface of medium is a false intersection if a medium of higher
+    /// priority fills the space around it.
+    pub fn is_false_intersection(&self, medium: &Medium) -> bool {
+        self.current()
+            .is_some_and(|current| current.priority > medium.priority)
+    }
+
+    /// Refractive indices on the incident and the transmitted side of a
+    /// surface of medium at wavelength lambda, outside of any medium being
+    /// vacuum.
+    pub fn refraction_indices(
+        &self,
+        medium: &Medium,
+        front_face: bool,
+        lambda: Option<f64>,
+    ) -> (f64, f64) {
+        let index = |stack: &Self| {
+            stack
+                .current()
+                .map_or(1., |m| m.refraction_index.at(lambda))
+        };
+        let own = medium.refraction_index.at(lambda);
+        if front_face {
+            (index(self), own)
+        } else {
+            let mut outside = self.clone();
+            outside.exit(medium);
+            (own, index(&outside))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn medium(refraction_index: f64, priority: u32) -> Medium {
+        Medium {
+            priority,
+            ..Medium::new(Ior::Constant(refraction_index))
+        }
+    }
+
+    #[test]
+    fn transmittance_works() {
+        let target = Color::new(0.8, 0.5, 0.1);
+        let m = Medium {
+            absorption: Medium::absorption_for(target, 2.),
+            ..Medium::new(Ior::Constant(1.5))
+        };
+        assert!((m.transmittance(2.) - target).length() < 1e-12);
+        assert!((m.transmittance(4.) - target * target).length() < 1e-12);
+        assert_eq!(m.transmittance(0.), Color::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn stack_tracks_highest_priority() {
+        let glass = medium(1.5, 2);
+        let water = medium(1.33, 1);
+        let mut stack = MediumStack::new();
+        assert!(stack.current().is_none());
+        assert_eq!(stack.refraction_indices(&glass, true, None), (1., 1.5));
+
+        // Through the glass wall into the water overlapping it.
+        stack.enter(glass);
+        assert!(stack.is_false_intersection(&water));
+        stack.enter(water);
+        assert_eq!(stack.current(), Some(&glass));
+
+        // Leaving the glass at its inner wall refracts into the water.
+        assert!(!stack.is_false_intersection(&glass));
+        assert_eq!(stack.refraction_indices(&glass, false, None), (1.5, 1.33));
+        stack.exit(&glass);
+        assert_eq!(stack.current(), Some(&water));
+        assert_eq!(stack.refraction_indices(&water, false, None), (1.33, 1.));
+    }
+
+    #[test]
+    fn equal_priorities_use_the_latest_medium() {
+        let a = medium(1.2, 0);
+        let b = medium(1.4, 0);
+        let mut stack = MediumStack::new();
+        stack.cross(a, true);
+        stack.cross(b, true);
+        assert_eq!(stack.current(), Some(&b));
+        assert!(!stack.is_false_intersection(&a));
+        stack.cross(b, false);
+        assert_eq!(stack.current(), Some(&a));
+    }
+
+    #[test]
+    fn ior_works() {
+        assert_eq!(Ior::Constant(1.5).at(Some(400.)), 1.5);
+        assert!((Ior::BK7.at(None) - 1.5168).abs() < 1e-4);
+        assert!((Ior::SF11.at(None) - 1.7847).abs() < 1e-4);
+        assert!((Ior::DIAMOND.at(Some(589.3)) - 2.417).abs() < 2e-3);
+        assert!((Ior::WATER.at(None) - 1.333).abs() < 1e-3);
+
+        // Normal dispersion: blue light bends more than red.
+        for ior in [Ior::BK7, Ior::SF11, Ior::DIAMOND, Ior::WATER] {
+            assert!(ior.is_dispersive());
+            assert!(ior.at(Some(450.)) > ior.at(Some(650.)));
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Triangle meshes: vertices shared between faces, each face a hittable
+//! triangle.
+
+use std::rc::Rc;
+
+use crate::{
+    bvh::AABB,
+    hittable::{HitRecord, Hittable, next_object_id},
+    material::Material,
+    ray::Ray,
+    util::Interval,
+    vec3::{Onb, Vec3},
+};
+
+/// Vertex positions with optional normals and texture coordinates, and
+/// faces of three vertex indices each. Faces are counterclockwise seen
+/// from their front.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>, // Shading normals, interpolated across faces
+    uvs: Option<Vec<(f64, f64)>>, // Texture coordinates of the vertices
+    faces: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vec3>, faces: Vec<[usize; 3]>) -> Self {
+        assert!(faces.iter().flatten().all(|&i| i < positions.len()));
+        Self {
+            positions,
+            normals: None,
+            uvs: None,
+            faces,
+        }
+    }
+
+    /// Closed box with opposite corners a and b, its faces facing out.
+    pub fn cuboid(a: Vec3, b: Vec3) -> Self {
+        // Vertex i has the coordinates of b where its bits 1, 2 and 4 are
+        // set, of a elsewhere.
+        let pick = |bit: usize, i: usize, a: f64, b: f64| if i & bit == 0 { a } else { b };
+        let positions = (0..8)
+            .map(|i| {
+                Vec3(
+                    pick(1, i, a.x(), b.x()),
+                    pick(2, i, a.y(), b.y()),
+                    pick(4, i, a.z(), b.z()),
+                )
+            })
+            .collect();
+        let quads = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let faces = quads
+            .iter()
+            .flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]])
+            .collect();
+        Self::new(positions, faces)
+    }
+
+    /// Sets a normal per vertex, for shading that hides the facets.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(normals.len(), self.positions.len());
+        self.normals = Some(normals.iter().map(|n| n.unit()).collect());
+        self
+    }
+
+    /// Sets texture coordinates per vertex. Without them each face maps
+    /// (0, 0), (1, 0) and (1, 1) to its vertices.
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len());
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// Total area of the faces.
+    pub fn area(&self) -> f64 {
+        self.faces
+            .iter()
+            .map(|face| {
+                let [p0, p1, p2] = face.map(|i| self.positions[i]);
+                0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+            })
+            .sum()
+    }
+
+    /// Triangles of all faces, made of material.
+    pub fn triangles(self: &Rc<Self>, material: &Rc<dyn Material>) -> Vec<Rc<Triangle>> {
+        (0..self.faces.len())
+            .map(|face| Rc::new(Triangle::new(self, face, material)))
+            .collect()
+    }
+}
+
+/// One face of a mesh.
+pub struct Triangle {
+    mesh: Rc<TriangleMesh>,
+    face: usize,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Triangle {
+    pub fn new(mesh: &Rc<TriangleMesh>, face: usize, material: &Rc<dyn Material>) -> Self {
+        let [p0, p1, p2] = mesh.faces[face].map(|i| mesh.positions[i]);
+        let bbox = AABB::from_boxes(&AABB::from_points(p0, p1), &AABB::from_points(p2, p2));
+        // Pad the box so that triangles in an axis plane have some depth.
+        let bbox = AABB::new(
+            bbox.x.expand(1e-4),
+            bbox.y.expand(1e-4),
+            bbox.z.expand(1e-4),
+        );
+        Self {
+            mesh: Rc::clone(mesh),
+            face,
+            material: Rc::clone(material),
+            bbox,
+            id: next_object_id(),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn material(&self) -> &Rc<dyn Material> {
+        &self.material
+    }
+
+    pub fn vertices(&self) -> [Vec3; 3] {
+        self.mesh.faces[self.face].map(|i| self.mesh.positions[i])
+    }
+
+    fn uvs(&self) -> [(f64, f64); 3] {
+        match &self.mesh.uvs {
+            Some(uvs) => self.mesh.faces[self.face].map(|i| uvs[i]),
+            None => [(0., 0.), (1., 0.), (1., 1.)],
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        let [p0, p1, p2] = self.vertices();
+        0.5 * (p1 - p0).cross(&(p2 - p0)).length()
+    }
+
+    /// Unit normal on the front side of the face.
+    pub fn normal(&self) -> Vec3 {
+        let [p0, p1, p2] = self.vertices();
+        (p1 - p0).cross(&(p2 - p0)).unit()
+    }
+
+    /// Point and texture coordinates at barycentric coordinates b.
+    pub fn point_at(&self, b: (f64, f64, f64)) -> (Vec3, f64, f64) {
+        let [p0, p1, p2] = self.vertices();
+        let [uv0, uv1, uv2] = self.uvs();
+        (
+            b.0 * p0 + b.1 * p1 + b.2 * p2,
+            b.0 * uv0.0 + b.1 * uv1.0 + b.2 * uv2.0,
+            b.0 * uv0.1 + b.1 * uv1.1 + b.2 * uv2.1,
+        )
+    }
+
+    /// Distance along ray to the face and the barycentric coordinates
+    /// there, by the Möller–Trumbore algorithm.
+    fn intersect(&self, ray: &Ray, ray_t: &Interval) -> Option<(f64, (f64, f64, f64))> {
+        let [p0, p1, p2] = self.vertices();
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let pvec = ray.dir().cross(&e2);
+        let det = e1.dot(&pvec);
+        if det == 0. || !det.is_finite() {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let tvec = ray.origin() - p0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let b2 = ray.dir().dot(&qvec) * inv_det;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+        let t = e2.dot(&qvec) * inv_det;
+        ray_t.surrounds(t).then_some((t, (1. - b1 - b2, b1, b2)))
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (t, b) = self.intersect(ray, ray_t)?;
+        let (point, u, v) = self.point_at(b);
+        let mut rec = HitRecord::new(point, t, ray, self.normal(), Rc::clone(&self.material));
+        (rec.u, rec.v) = (u, v);
+
+        // Derivatives from the changes in position and uv along two edges.
+        let [p0, p1, p2] = self.vertices();
+        let [uv0, uv1, uv2] = self.uvs();
+        let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let det = du02 * dv12 - dv02 * du12;
+        let along_uv =
+            |d0: Vec3, d1: Vec3| ((dv12 * d0 - dv02 * d1) / det, (du02 * d1 - du12 * d0) / det);
+        if det.abs() > 1e-12 {
+            (rec.dpdu, rec.dpdv) = along_uv(p0 - p2, p1 - p2);
+        } else {
+            let onb = Onb::new(self.normal());
+            (rec.dpdu, rec.dpdv) = (onb.u, onb.v);
+        }
+
+        // Shading normals, on the side of the geometric one the ray hits.
+        if let Some(normals) = &self.mesh.normals {
+            let [n0, n1, n2] = self.mesh.faces[self.face].map(|i| normals[i]);
+            let side = if rec.front_face { 1. } else { -1. };
+            rec.normal = side * (b.0 * n0 + b.1 * n1 + b.2 * n2).unit();
+            if det.abs() > 1e-12 {
+                let (dndu, dndv) = along_uv(n0 - n2, n1 - n2);
+                (rec.dndu, rec.dndv) = (side * dndu, side * dndv);
+            }
+        }
+        rec.object_id = self.id;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn quad(material: &Rc<dyn Material>) -> Vec<Rc<Triangle>> {
+        let positions = vec![
+            Vec3(0., 0., 0.),
+            Vec3(2., 0., 0.),
+            Vec3(2., 1., 0.),
+            Vec3(0., 1., 0.),
+        ];
+        let mesh = TriangleMesh::new(positions, vec![[0, 1, 2], [0, 2, 3]]).with_uvs(vec![
+            (0., 0.),
+            (1., 0.),
+            (1., 1.),
+            (0., 1.),
+        ]);
+        Rc::new(mesh).triangles(material)
+    }
+
+    #[test]
+    fn triangles_work() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let triangles = quad(&mat);
+        let t = Interval::new(0.001, f64::INFINITY);
+        let ray = |x: f64, y: f64| Ray::new(Vec3(x, y, 1.), Vec3(0., 0., -1.), 0.);
+
+        // Each point of the quad is hit by exactly one of its triangles.
+        let rec = triangles[0].hit(&ray(1.5, 0.25), &t).unwrap();
+        assert!(triangles[1].hit(&ray(1.5, 0.25), &t).is_none());
+        assert!((rec.point - Vec3(1.5, 0.25, 0.)).length() < 1e-12);
+        assert_eq!(rec.t, 1.);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., 1.));
+        assert_eq!(rec.object_id, triangles[0].id());
+        // Texture coordinates follow the vertices', with derivatives
+        // along the sides of the quad.
+        assert!((rec.u - 0.75).abs() < 1e-12 && (rec.v - 0.25).abs() < 1e-12);
+        assert!((rec.dpdu - Vec3(2., 0., 0.)).length() < 1e-12);
+        assert!((rec.dpdv - Vec3(0., 1., 0.)).length() < 1e-12);
+        assert!(triangles[1].hit(&ray(0.5, 0.75), &t).is_some());
+        assert!(
+            triangles
+                .iter()
+                .all(|tri| tri.hit(&ray(2.5, 0.5), &t).is_none())
+        );
+
+        // From behind, the normal faces the ray.
+        let back = Ray::new(Vec3(1.5, 0.25, -1.), Vec3(0., 0., 1.), 0.);
+        let rec = triangles[0].hit(&back, &t).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., -1.));
+        assert!((triangles[0].area() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn shading_normals_interpolate() {
+        let mat: Rc<dyn Material> = Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)));
+        let positions = vec![Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(0., 1., 0.)];
+        let normals = vec![Vec3(0., 0., 1.), Vec3(1., 0., 1.), Vec3(0., 1., 1.)];
+        let mesh = Rc::new(TriangleMesh::new(positions, vec![[0, 1, 2]]).with_normals(normals));
+        let triangle = &mesh.triangles(&mat)[0];
+        let t = Interval::new(0.001, f64::INFINITY);
+
+        let rec = triangle
+            .hit(&Ray::new(Vec3(0., 0., 1.), Vec3(0., 0., -1.), 0.), &t)
+            .unwrap();
+        assert!((rec.normal - Vec3(0., 0., 1.)).length() < 1e-12);
+        let rec = triangle
+            .hit(&Ray::new(Vec3(0.5, 0., 1.), Vec3(0., 0., -1.), 0.), &t)
+            .unwrap();
+        let expected = (0.5 * Vec3(0., 0., 1.) + 0.5 * Vec3(1., 0., 1.).unit()).unit();
+        assert!((rec.normal - expected).length() < 1e-12);
+        // Seen from below, it is flipped with the geometric normal.
+        let rec = triangle
+            .hit(&Ray::new(Vec3(0.5, 0., -1.), Vec3(0., 0., 1.), 0.), &t)
+            .unwrap();
+        assert!((rec.normal + expected).length() < 1e-12);
+    }
+}
@@ -0,0 +1,296 @@
+//! GGX (Trowbridge-Reitz) microfacet distribution with Smith
+//! masking-shadowing and sampling of the visible normals.
+//!
+//! All directions are unit vectors in a local shading frame with the
+//! macrosurface normal along +z, as produced by `Onb::to_local`.
+
+use std::f64::consts::PI;
+
+use crate::{color::Color, vec3::Vec3};
+
+/// Smallest alpha used, to keep nearly smooth surfaces numerically stable.
+const MIN_ALPHA: f64 = 1e-4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    alpha_x: f64, // Roughness along the frame's x axis
+    alpha_y: f64, // Roughness along the frame's y axis
+}
+
+impl Ggx {
+    /// Creates the distribution for a perceptual roughness in [0, 1],
+    /// mapped to alpha = roughness^2.
+    pub fn new(roughness: f64) -> Self {
+        Self::anisotropic(roughness, 0.)
+    }
+
+    /// Creates a distribution stretched along the x axis by an anisotropy
+    /// in [0, 1], with the aspect ratio of the Disney BRDF.
+    pub fn anisotropic(roughness: f64, anisotropy: f64) -> Self {
+        let roughness = roughness.clamp(0., 1.);
+        let aspect = (1. - 0.9 * anisotropy.clamp(0., 1.)).sqrt();
+        let alpha = roughness * roughness;
+        Self {
+            alpha_x: (alpha / aspect).max(MIN_ALPHA),
+            alpha_y: (alpha * aspect).max(MIN_ALPHA),
+        }
+    }
+
+    /// Density of microfacet normals m per unit projected area.
+    pub fn d(&self, m: Vec3) -> f64 {
+        if m.z() <= 0. {
+            return 0.;
+        }
+        let (x, y) = (m.x() / self.alpha_x, m.y() / self.alpha_y);
+        let denom = x * x + y * y + m.z() * m.z();
+        1. / (PI * self.alpha_x * self.alpha_y * denom * denom)
+    }
+
+    /// Smith's auxiliary function for direction w.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0. {
+            return f64::INFINITY;
+        }
+        let (x, y) = (self.alpha_x * w.x(), self.alpha_y * w.y());
+        (-1. + (1. + (x * x + y * y) / cos2).sqrt()) / 2.
+    }
+
+    /// Fraction of the microfacets facing w that are visible from w.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing for the pair wo, wi.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the microfacet normals visible from wo.
+    pub fn visible_pdf(&self, wo: Vec3, m: Vec3) -> f64 {
+        if wo.z() <= 0. {
+            return 0.;
+        }
+        self.g1(wo) * wo.dot(&m).max(0.) * self.d(m) / wo.z()
+    }
+
+    /// Samples a microfacet normal visible from wo, which must lie above
+    /// the surface, from two uniform numbers in [0, 1) (Heitz 2018).
+    pub fn sample_visible(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch wo to the configuration of a hemisphere of unit roughness.
+        let vh = Vec3(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit();
+
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > 0. {
+            Vec3(-vh.y(), vh.x(), 0.) / len2.sqrt()
+        } else {
+            Vec3(1., 0., 0.)
+        };
+        let t2 = vh.cross(&t1);
+
+        // Uniform point on the projected disk, warped onto its visible part.
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z());
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+        Vec3(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(0.)).unit()
+    }
+
+    /// Samples a direction mirrored about a visible microfacet. The
+    /// result may lie below the surface, where it carries no energy.
+    pub fn sample_reflection(&self, wo: Vec3) -> Vec3 {
+        let m = self.sample_visible(wo, rand::random(), rand::random());
+        (-wo).reflect(&m)
+    }
+
+    /// Reflection from wo to wi, both above the surface. Returns the
+    /// microfacet normal, the BRDF times cosine without the Fresnel
+    /// factor, and the density of `sample_reflection`.
+    pub fn reflection(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, f64, f64)> {
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return None;
+        }
+        let m = (wo + wi).unit();
+        let value = self.d(m) * self.g2(wo, wi) / (4. * wo.z());
+        let pdf = self.visible_pdf(wo, m) / (4. * wo.dot(&m));
+        Some((m, value, pdf))
+    }
+
+    /// Samples a direction reflected or refracted by a visible microfacet
+    /// of a dielectric boundary, choosing by the Fresnel reflectance.
+    /// eta is the ratio of the refractive index below the surface to the
+    /// one above it.
+    pub fn sample_dielectric(&self, eta: f64, wo: Vec3) -> Vec3 {
+        let m = self.sample_visible(wo, rand::random(), rand::random());
+        // The Fresnel reflectance is 1 under total internal reflection.
+        if rand::random::<f64>() < fresnel_dielectric(wo.dot(&m), eta) {
+            (-wo).reflect(&m)
+        } else {
+            (-wo).refract(&m, 1. / eta).unit()
+        }
+    }
+
+    /// BSDF times cosine and density of `sample_dielectric` from wo to
+    /// wi, after Walter et al., "Microfacet Models for Refraction through
+    /// Rough Surfaces" (2007).
+    pub fn dielectric(&self, eta: f64, wo: Vec3, wi: Vec3) -> (f64, f64) {
+        if wo.z() <= 0. || wi.z() == 0. {
+            return (0., 0.);
+        }
+
+        if wi.z() > 0. {
+            let Some((m, value, pdf)) = self.reflection(wo, wi) else {
+                return (0., 0.);
+            };
+            let f = fresnel_dielectric(wo.dot(&m), eta);
+            return (f * value, f * pdf);
+        }
+
+        // The generalized half vector of refraction.
+        let mut m = -(wo + eta * wi).unit();
+        if m.z() < 0. {
+            m = -m;
+        }
+        let (cos_o, cos_i) = (wo.dot(&m), wi.dot(&m));
+        if cos_o <= 0. || cos_i >= 0. {
+            return (0., 0.);
+        }
+        let f = fresnel_dielectric(cos_o, eta);
+        let denom = (cos_o + eta * cos_i).powi(2);
+        let jacobian = eta * eta * cos_i.abs() / denom;
+        let value = (1. - f) * self.d(m) * self.g2(wo, wi) * cos_o * jacobian / wo.z();
+        let pdf = (1. - f) * self.visible_pdf(wo, m) * jacobian;
+        (value, pdf)
+    }
+}
+
+/// Fresnel reflectance of a dielectric boundary for light arriving at
+/// cos_i to the normal, where eta is the ratio of the refractive index
+/// on the far side to the near side.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / 2.
+}
+
+/// Fresnel reflectance of a conductor with complex refractive index
+/// eta + ik, per color channel.
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_i.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2b2 + cos2;
+        let a = (0.5 * (a2b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_i.clamp(0., 1.) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        (rs + rp) / 2.
+    };
+    Color::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normals_project_to_unit_area() {
+        // The projected areas of the microfacets sum up to the macrosurface:
+        // the integral of D(m) cos(theta_m) over the hemisphere is 1.
+        for (roughness, anisotropy) in [(0.2, 0.), (0.5, 0.), (1., 0.), (0.5, 0.8)] {
+            let ggx = Ggx::anisotropic(roughness, anisotropy);
+            let n = 1000;
+            let mut integral = 0.;
+            for a in 0..n {
+                let theta = (a as f64 + 0.5) / n as f64 * PI / 2.;
+                for b in 0..n {
+                    let phi = (b as f64 + 0.5) / n as f64 * 2. * PI;
+                    let m = Vec3(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    integral += ggx.d(m) * theta.cos() * theta.sin() * PI * PI / (n * n) as f64;
+                }
+            }
+            assert!((integral - 1.).abs() < 1e-3, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn visible_normals_face_wo() {
+        let ggx = Ggx::new(0.6);
+        let wo = Vec3(0.6, 0., 0.8);
+        for _ in 0..1000 {
+            let m = ggx.sample_visible(wo, rand::random(), rand::random());
+            assert!((m.length() - 1.).abs() < 1e-9);
+            assert!(m.z() >= 0.);
+            assert!(wo.dot(&m) >= -1e-9);
+        }
+    }
+
+    #[test]
+    fn visible_normal_sampling_matches_pdf() {
+        // Histogram of sampled cos(theta_m) against the integrated density.
+        let ggx = Ggx::anisotropic(0.5, 0.5);
+        let wo = Vec3(0.64, 0.48, 0.6);
+        let n = 200_000;
+        let bins = 10;
+        let mut counts = vec![0.; bins];
+        for _ in 0..n {
+            let m = ggx.sample_visible(wo, rand::random(), rand::random());
+            counts[((m.z() * bins as f64) as usize).min(bins - 1)] += 1. / n as f64;
+        }
+
+        let steps = 400;
+        for (bin, &count) in counts.iter().enumerate() {
+            let mut expected = 0.;
+            for a in 0..steps {
+                let cos = (bin as f64 + (a as f64 + 0.5) / steps as f64) / bins as f64;
+                let sin = (1. - cos * cos).sqrt();
+                for b in 0..steps {
+                    let phi = (b as f64 + 0.5) / steps as f64 * 2. * PI;
+                    let m = Vec3(sin * phi.cos(), sin * phi.sin(), cos);
+                    expected +=
+                        ggx.visible_pdf(wo, m) / (bins * steps) as f64 * 2. * PI / steps as f64;
+                }
+            }
+            assert!((count - expected).abs() < 0.01, "{} {}", count, expected);
+        }
+    }
+
+    #[test]
+    fn fresnel_works() {
+        // Normal incidence on glass reflects 4%, grazing incidence everything.
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(0., 1.5) - 1.).abs() < 1e-12);
+        // Total internal reflection leaving glass.
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+
+        // At normal incidence a conductor reflects ((n-1)^2+k^2)/((n+1)^2+k^2).
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.) * (eta - 1.) + k * k) / ((eta + 1.) * (eta + 1.) + k * k);
+        let f = fresnel_conductor(1., Color::new(eta, eta, eta), Color::new(k, k, k));
+        assert!((f.x() - expected).abs() < 1e-12);
+        // A conductor with k = 0 is a dielectric.
+        let f = fresnel_conductor(0.7, Color::new(1.5, 1.5, 1.5), Color::new(0., 0., 0.));
+        assert!((f.x() - fresnel_dielectric(0.7, 1.5)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,289 @@
+//! Image pyramids for texture lookups filtered over a footprint.
+
+use crate::color::Color;
+
+/// How an image lookup averages the pixels under its footprint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Blends the four nearest pixels of the full image, ignoring the
+    /// footprint.
+    Bilinear,
+    /// Blends bilinear lookups in the two levels whose pixels are closest
+    /// in size to the widest extent of the footprint.
+    Trilinear,
+    /// Averages the pixels under the elliptical footprint with Gaussian
+    /// weights (Heckbert's elliptical weighted average), in the levels
+    /// where the short axis spans about a pixel. Ellipses longer than
+    /// max_anisotropy times their width are widened to bound the cost.
+    Ewa { max_anisotropy: f64 },
+}
+
+/// One level of the pyramid, with lookups that repeat the image.
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>, // Top row first
+}
+
+impl Level {
+    fn pixel(&self, i: i64, j: i64) -> Color {
+        let i = i.rem_euclid(self.width as i64) as usize;
+        let j = j.rem_euclid(self.height as i64) as usize;
+        self.pixels[j * self.width + i]
+    }
+
+    /// Half the size, rounded up, averaging blocks of two by two pixels.
+    /// An odd last row or column is averaged with itself.
+    fn downsample(&self) -> Level {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let (i0, i1) = (2 * i, (2 * i + 1).min(self.width - 1));
+                let (j0, j1) = (2 * j, (2 * j + 1).min(self.height - 1));
+                let sum = self.pixel(i0 as i64, j0 as i64)
+                    + self.pixel(i1 as i64, j0 as i64)
+                    + self.pixel(i0 as i64, j1 as i64)
+                    + self.pixel(i1 as i64, j1 as i64);
+                pixels.push(0.25 * sum);
+            }
+        }
+        Level {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn bilinear(&self, s: f64, t: f64) -> Color {
+        // Continuous pixel coordinates with pixel centers at whole numbers.
+        let x = s * self.width as f64 - 0.5;
+        let y = t * self.height as f64 - 0.5;
+        let (i, j) = (x.floor() as i64, y.floor() as i64);
+        let (a, b) = (x - x.floor(), y - y.floor());
+
+        (1. - b) * ((1. - a) * self.pixel(i, j) + a * self.pixel(i + 1, j))
+            + b * ((1. - a) * self.pixel(i, j + 1) + a * self.pixel(i + 1, j + 1))
+    }
+
+    /// Gaussian weighted average over the ellipse with axes d0 and d1
+    /// around (s, t), all in texture units.
+    fn ewa(&self, s: f64, t: f64, d0: (f64, f64), d1: (f64, f64)) -> Color {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let (x, y) = (s * w - 0.5, t * h - 0.5);
+        let (d0, d1) = ((d0.0 * w, d0.1 * h), (d1.0 * w, d1.1 * h));
+
+        // Implicit ellipse a x^2 + b x y + c y^2 < 1, widened by a pixel so
+        // that it always covers some pixel centers.
+        let a = d0.1 * d0.1 + d1.1 * d1.1 + 1.;
+        let b = -2. * (d0.0 * d0.1 + d1.0 * d1.1);
+        let c = d0.0 * d0.0 + d1.0 * d1.0 + 1.;
+        let inv_f = 1. / (a * c - 0.25 * b * b);
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+        let det = 4. * a * c - b * b;
+        let (x_extent, y_extent) = (2. * (c / det).sqrt(), 2. * (a / det).sqrt());
+        let (x0, x1) = ((x - x_extent).ceil() as i64, (x + x_extent).floor() as i64);
+        let (y0, y1) = ((y - y_extent).ceil() as i64, (y + y_extent).floor() as i64);
+
+        const ALPHA: f64 = 2.;
+        let mut sum = Color::new(0., 0., 0.);
+        let mut total = 0.;
+        for j in y0..=y1 {
+            let dy = j as f64 - y;
+            for i in x0..=x1 {
+                let dx = i as f64 - x;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1. {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += weight * self.pixel(i, j);
+                    total += weight;
+                }
+            }
+        }
+        if total > 0. {
+            sum / total
+        } else {
+            self.bilinear(s, t)
+        }
+    }
+}
+
+/// An image and its successively halved averages, down to a single pixel.
+/// Lookups take coordinates s to the right and t down, both in [0, 1]
+/// across the image, and repeat it outside.
+pub struct MipMap {
+    levels: Vec<Level>,
+}
+
+impl MipMap {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        assert!(width > 0 && height > 0);
+        let mut levels = vec![Level {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        }];
+        while let Some(last) = levels.last()
+            && (last.width > 1 || last.height > 1)
+        {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Number of levels, the first being the full image.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Bilinear lookup in the given level.
+    pub fn bilinear(&self, level: usize, s: f64, t: f64) -> Color {
+        self.levels[level.min(self.levels.len() - 1)].bilinear(s, t)
+    }
+
+    /// Filtered lookup at (s, t) over the footprint whose sides are the
+    /// changes dx = (ds, dt) and dy from one pixel to the next.
+    pub fn lookup(&self, filter: Filter, s: f64, t: f64, dx: (f64, f64), dy: (f64, f64)) -> Color {
+        match filter {
+            Filter::Bilinear => self.bilinear(0, s, t),
+            Filter::Trilinear => {
+                let width = [dx.0, dx.1, dy.0, dy.1]
+                    .into_iter()
+                    .fold(0., |w: f64, d| w.max(2. * d.abs()));
+                self.blend_levels(width, |level| level.bilinear(s, t))
+            }
+            Filter::Ewa { max_anisotropy } => {
+                let length = |d: (f64, f64)| d.0.hypot(d.1);
+                let (mut major_axis, mut minor_axis) = (dx, dy);
+                if length(major_axis) < length(minor_axis) {
+                    std::mem::swap(&mut major_axis, &mut minor_axis);
+                }
+                let (major, mut minor) = (length(major_axis), length(minor_axis));
+                if minor == 0. || !minor.is_finite() {
+                    return self.bilinear(0, s, t);
+                }
+                if minor * max_anisotropy < major {
+                    let scale = major / (minor * max_anisotropy);
+                    minor_axis = (scale * minor_axis.0, scale * minor_axis.1);
+                    minor *= scale;
+                }
+                self.blend_levels(minor, |level| level.ewa(s, t, major_axis, minor_axis))
+            }
+        }
+    }
+
+    /// Blends lookups in the two levels whose pixels are closest in size to
+    /// width, in texture units. Footprints wider than the coarsest level
+    /// take its lookup.
+    fn blend_levels(&self, width: f64, lookup: impl Fn(&Level) -> Color) -> Color {
+        let first = &self.levels[0];
+        let last = self.levels.len() - 1;
+        let lod = (width * first.width.max(first.height) as f64).log2();
+        if lod.is_nan() || lod <= 0. {
+            return lookup(first);
+        }
+        if lod >= last as f64 {
+            return lookup(&self.levels[last]);
+        }
+        let k = lod.floor();
+        let frac = lod - k;
+        let k = k as usize;
+        (1. - frac) * lookup(&self.levels[k]) + frac * lookup(&self.levels[k + 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(size: u32) -> MipMap {
+        let pixels = (0..size * size)
+            .map(|k| match (k % size + k / size) % 2 {
+                0 => Color::new(0., 0., 0.),
+                _ => Color::new(1., 1., 1.),
+            })
+            .collect();
+        MipMap::new(size, size, pixels)
+    }
+
+    #[test]
+    fn mip_levels_average() {
+        let image = checkerboard(8);
+        assert_eq!(image.levels(), 4);
+        for level in 1..4 {
+            assert_eq!(image.bilinear(level, 0.3, 0.6), Color::new(0.5, 0.5, 0.5));
+        }
+
+        // Odd sizes round up, down to a single pixel.
+        let pixels = (0..15).map(|k| Color::new(k as f64, 0., 0.)).collect();
+        let image = MipMap::new(5, 3, pixels);
+        assert_eq!(image.levels(), 4);
+        // The last column and row average with themselves.
+        assert_eq!(image.levels[1].width, 3);
+        assert_eq!(image.levels[1].pixel(2, 1).x(), 14.);
+        assert_eq!(image.levels[1].pixel(0, 0).x(), 3.);
+    }
+
+    #[test]
+    fn trilinear_filter_blurs_with_footprint() {
+        let image = checkerboard(16);
+        let (s, t) = (0.25 / 16., 0.25 / 16.);
+        let black = Color::new(0., 0., 0.);
+        let gray = Color::new(0.5, 0.5, 0.5);
+
+        // Without a footprint it is a bilinear lookup in the full image.
+        let point = image.lookup(Filter::Trilinear, s, t, (0., 0.), (0., 0.));
+        assert_eq!(point, image.bilinear(0, s, t));
+        assert_eq!(
+            image.lookup(Filter::Bilinear, s, t, (0.5, 0.), (0., 0.5)),
+            point
+        );
+        assert_eq!(image.bilinear(0, 0.5 / 16., 0.5 / 16.), black);
+
+        // A footprint of many pixels averages them.
+        let wide = image.lookup(Filter::Trilinear, s, t, (0.25, 0.), (0., 0.25));
+        assert!((wide - gray).length() < 1e-12);
+        // Blurring grows with the footprint.
+        let mut last = point;
+        for width in [0.5, 1., 2., 4.] {
+            let d = width / 16.;
+            let color = image.lookup(Filter::Trilinear, s, t, (d, 0.), (0., d));
+            assert!((color - gray).length() <= (last - gray).length());
+            last = color;
+        }
+    }
+
+    #[test]
+    fn ewa_filter_follows_footprint_shape() {
+        // Vertical stripes four pixels wide, looked up in the middle of a
+        // black one.
+        let pixels = (0..64 * 64)
+            .map(|k| match k % 8 {
+                0..4 => Color::new(0., 0., 0.),
+                _ => Color::new(1., 1., 1.),
+            })
+            .collect();
+        let image = MipMap::new(64, 64, pixels);
+        let ewa = Filter::Ewa { max_anisotropy: 8. };
+        let (s, t) = (2. / 64., 0.5);
+
+        // A footprint long along the stripes keeps them sharp, where the
+        // trilinear filter blurs them away.
+        let along = ((0.1 / 64., 0.), (0., 8. / 64.));
+        let color = image.lookup(ewa, s, t, along.0, along.1);
+        assert!(color.x() < 0.05);
+        let blurred = image.lookup(Filter::Trilinear, s, t, along.0, along.1);
+        assert!((blurred.x() - 0.5).abs() < 0.05);
+
+        // Across the stripes it averages them.
+        let across = image.lookup(ewa, s, t, (16. / 64., 0.), (0., 0.1 / 64.));
+        assert!((across.x() - 0.5).abs() < 0.05);
+
+        // The same footprint given in either order filters alike.
+        let swapped = image.lookup(ewa, s, t, along.1, along.0);
+        assert!((swapped - color).length() < 1e-12);
+    }
+}
@@ -0,0 +1,453 @@
+//! Procedural noise for solid textures and bump maps.
+
+use std::rc::Rc;
+
+use rand::Rng;
+
+use crate::vec3::Vec3;
+
+const POINT_COUNT: usize = 256;
+
+/// Scalar pattern over space with values roughly in [0, 1], which noise
+/// textures map to colors.
+pub trait Pattern {
+    fn value(&self, p: Vec3) -> f64;
+}
+
+/// Random permutations of the lattice coordinates, hashing a lattice cell
+/// to one of POINT_COUNT entries.
+fn permutations<R: Rng>(rng: &mut R) -> [Vec<usize>; 3] {
+    let mut permutation = || {
+        let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+        for i in (1..POINT_COUNT).rev() {
+            p.swap(i, rng.random_range(0..=i));
+        }
+        p
+    };
+    [permutation(), permutation(), permutation()]
+}
+
+fn hash(perm: &[Vec<usize>; 3], cell: [i64; 3]) -> usize {
+    (0..3)
+        .map(|axis| perm[axis][(cell[axis] & (POINT_COUNT as i64 - 1)) as usize])
+        .fold(0, |hash, h| hash ^ h)
+}
+
+/// Perlin gradient noise: random unit gradients on the integer lattice,
+/// blended with a smoothed trilinear interpolation (Perlin, "An Image
+/// Synthesizer", 1985). Values lie roughly in [-1, 1] and are 0 at every
+/// lattice point.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3], // Lattice hash permutations for x, y and z
+}
+
+impl Perlin {
+    /// Creates noise with gradients drawn from rng, so that the same seed
+    /// gives the same noise.
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                loop {
+                    let v = Vec3(
+                        rng.random_range(-1. ..1.),
+                        rng.random_range(-1. ..1.),
+                        rng.random_range(-1. ..1.),
+                    );
+                    if v.length_squared() > 1e-6 && v.length_squared() <= 1. {
+                        break v.unit();
+                    }
+                }
+            })
+            .collect();
+        let perm = permutations(rng);
+        Self { gradients, perm }
+    }
+
+    pub fn noise(&self, p: Vec3) -> f64 {
+        let floor = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let frac = [p.x() - floor[0], p.y() - floor[1], p.z() - floor[2]];
+        let cell = floor.map(|f| f as i64);
+        // Hermite smoothing of the interpolation weights.
+        let smooth = frac.map(|t| t * t * (3. - 2. * t));
+
+        let mut sum = 0.;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let index = hash(
+                &self.perm,
+                [0, 1, 2].map(|axis| cell[axis] + offset[axis] as i64),
+            );
+            let to_point = Vec3(
+                frac[0] - offset[0] as f64,
+                frac[1] - offset[1] as f64,
+                frac[2] - offset[2] as f64,
+            );
+            let weight: f64 = (0..3)
+                .map(|axis| match offset[axis] {
+                    1 => smooth[axis],
+                    _ => 1. - smooth[axis],
+                })
+                .product();
+            sum += weight * self.gradients[index].dot(&to_point);
+        }
+        sum
+    }
+
+    /// Sum of the absolute noise over depth octaves of doubling frequency
+    /// and halving amplitude, giving a marbled or cloudy look.
+    pub fn turbulence(&self, p: Vec3, depth: u32) -> f64 {
+        let mut sum = 0.;
+        let mut p = p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            sum += weight * self.noise(p).abs();
+            weight *= 0.5;
+            p = 2. * p;
+        }
+        sum
+    }
+}
+
+impl Pattern for Perlin {
+    fn value(&self, p: Vec3) -> f64 {
+        0.5 * (1. + self.noise(p))
+    }
+}
+
+/// Octaves of Perlin noise summed at growing frequencies and shrinking
+/// amplitudes. Each octave's frequency is lacunarity times the last and
+/// its amplitude gain times the last.
+pub struct Fractal {
+    noise: Perlin,
+    octaves: u32,
+    lacunarity: f64,
+    gain: f64,
+    ridged: bool,
+}
+
+impl Fractal {
+    /// Fractal Brownian motion: a sum of plain octaves, like rolling
+    /// terrain or clouds.
+    pub fn fbm(noise: Perlin, octaves: u32) -> Self {
+        assert!(octaves > 0);
+        Self {
+            noise,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+            ridged: false,
+        }
+    }
+
+    /// Ridged multifractal (Musgrave): octaves folded into sharp ridges
+    /// where the noise crosses zero, each weighted by the one before, so
+    /// that detail gathers on the ridges like on eroded mountains.
+    pub fn ridged(noise: Perlin, octaves: u32) -> Self {
+        Self {
+            ridged: true,
+            ..Self::fbm(noise, octaves)
+        }
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: f64) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: f64) -> Self {
+        self.gain = gain;
+        self
+    }
+}
+
+impl Pattern for Fractal {
+    fn value(&self, p: Vec3) -> f64 {
+        // Ridges lie in [0, 1] and are normalized by the largest sum,
+        // plain octaves by its spread, keeping one octave's contrast.
+        let (mut sum, mut total) = (0., 0.);
+        let mut p = p;
+        let mut amplitude = 1.;
+        let mut weight = 1.;
+        for _ in 0..self.octaves {
+            let noise = self.noise.noise(p);
+            if self.ridged {
+                let signal = (1. - noise.abs()).powi(2) * weight;
+                sum += amplitude * signal;
+                weight = (2. * signal).clamp(0., 1.);
+            } else {
+                sum += amplitude * noise;
+            }
+            total += match self.ridged {
+                true => amplitude,
+                false => amplitude * amplitude,
+            };
+            amplitude *= self.gain;
+            p = self.lacunarity * p;
+        }
+        match self.ridged {
+            true => sum / total,
+            false => 0.5 * (1. + sum / total.sqrt()),
+        }
+    }
+}
+
+/// Which distances to the feature points a cellular pattern shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cellular {
+    F1,        // Nearest point: rounded cells, darkest at their centers
+    F2,        // Second nearest point
+    F2MinusF1, // Difference: thin lines along the cell borders
+}
+
+/// Worley's cellular noise: one random feature point in every lattice
+/// cell and a pattern of the distances to the nearest ones.
+pub struct Worley {
+    points: Vec<Vec3>, // Feature point offsets within their cells
+    perm: [Vec<usize>; 3],
+    distance: Cellular,
+}
+
+impl Worley {
+    /// Creates F1 noise with points drawn from rng.
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        let points = (0..POINT_COUNT)
+            .map(|_| Vec3(rng.random(), rng.random(), rng.random()))
+            .collect();
+        let perm = permutations(rng);
+        Self {
+            points,
+            perm,
+            distance: Cellular::F1,
+        }
+    }
+
+    pub fn with_distance(mut self, distance: Cellular) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    fn feature_point(&self, cell: [i64; 3]) -> Vec3 {
+        let corner = Vec3(cell[0] as f64, cell[1] as f64, cell[2] as f64);
+        corner + self.points[hash(&self.perm, cell)]
+    }
+
+    /// Distances from p to the nearest and the second nearest feature
+    /// points, searching the cell of p and its neighbours.
+    pub fn distances(&self, p: Vec3) -> (f64, f64) {
+        let cell = [p.x(), p.y(), p.z()].map(|x| x.floor() as i64);
+        let (mut f1, mut f2) = (f64::INFINITY, f64::INFINITY);
+        for k in 0..27 {
+            let offset = [k % 3 - 1, k / 3 % 3 - 1, k / 9 - 1];
+            let neighbour = [0, 1, 2].map(|axis| cell[axis] + offset[axis]);
+            let d = (self.feature_point(neighbour) - p).length();
+            if d < f1 {
+                (f1, f2) = (d, f1);
+            } else if d < f2 {
+                f2 = d;
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl Pattern for Worley {
+    fn value(&self, p: Vec3) -> f64 {
+        let (f1, f2) = self.distances(p);
+        match self.distance {
+            Cellular::F1 => f1,
+            Cellular::F2 => f2,
+            Cellular::F2MinusF1 => f2 - f1,
+        }
+    }
+}
+
+/// Domain warping: a pattern looked up at points displaced by fractal
+/// noise, which swirls and stretches it like marble or smoke.
+pub struct Warped {
+    pattern: Rc<dyn Pattern>,
+    warp: Fractal,
+    strength: f64, // Largest displacement along each axis
+}
+
+impl Warped {
+    pub fn new(pattern: Rc<dyn Pattern>, warp: Fractal, strength: f64) -> Self {
+        Self {
+            pattern,
+            warp,
+            strength,
+        }
+    }
+
+    /// Displacement of p, with each axis read from a distant region of the
+    /// warping noise so that the three are unrelated. Fractal noise strays
+    /// a little outside [0, 1], so each axis is clamped to stay within
+    /// strength.
+    fn displacement(&self, p: Vec3) -> Vec3 {
+        let axis = |offset: Vec3| (2. * self.warp.value(p + offset) - 1.).clamp(-1., 1.);
+        self.strength
+            * Vec3(
+                axis(Vec3(0., 0., 0.)),
+                axis(Vec3(5.2, 1.3, 2.8)),
+                axis(Vec3(1.7, 9.2, 4.1)),
+            )
+    }
+}
+
+impl Pattern for Warped {
+    fn value(&self, p: Vec3) -> f64 {
+        self.pattern.value(p + self.displacement(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn noise_works() {
+        let perlin = Perlin::new(&mut StdRng::seed_from_u64(1));
+        let again = Perlin::new(&mut StdRng::seed_from_u64(1));
+        let mut rng = StdRng::seed_from_u64(2);
+
+        assert_eq!(perlin.noise(Vec3(3., -2., 7.)), 0.);
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for _ in 0..10_000 {
+            let p = Vec3(
+                rng.random_range(-50. ..50.),
+                rng.random_range(-50. ..50.),
+                rng.random_range(-50. ..50.),
+            );
+            let n = perlin.noise(p);
+            assert_eq!(n, again.noise(p));
+            min = min.min(n);
+            max = max.max(n);
+
+            // Noise is continuous.
+            let nearby = perlin.noise(p + Vec3(1e-6, -1e-6, 1e-6));
+            assert!((n - nearby).abs() < 1e-4);
+        }
+        assert!(min > -1.5 && max < 1.5);
+        assert!(min < -0.4 && max > 0.4);
+    }
+
+    fn random_points(seed: u64, count: usize) -> Vec<Vec3> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                Vec3(
+                    rng.random_range(-20. ..20.),
+                    rng.random_range(-20. ..20.),
+                    rng.random_range(-20. ..20.),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fractals_work() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let perlin = Perlin::new(&mut StdRng::seed_from_u64(1));
+        let fbm = Fractal::fbm(Perlin::new(&mut rng), 5);
+        let ridged = Fractal::ridged(Perlin::new(&mut StdRng::seed_from_u64(1)), 5);
+        let single = Fractal::fbm(Perlin::new(&mut StdRng::seed_from_u64(1)), 1);
+
+        let (mut fbm_min, mut fbm_max) = (f64::INFINITY, f64::NEG_INFINITY);
+        for p in random_points(2, 10_000) {
+            // One octave is plain noise.
+            assert_eq!(single.value(p), perlin.value(p));
+            let value = fbm.value(p);
+            fbm_min = fbm_min.min(value);
+            fbm_max = fbm_max.max(value);
+            assert!((0. ..=1.).contains(&ridged.value(p)));
+        }
+        assert!(fbm_min > -0.25 && fbm_max < 1.25);
+        assert!(fbm_min < 0.3 && fbm_max > 0.7);
+
+        // Ridges peak where the first octave crosses zero, as on lattice
+        // points.
+        let on_lattice = ridged.value(Vec3(3., -2., 7.));
+        assert!(on_lattice > 0.5);
+        let perlin_ridged = Fractal::ridged(Perlin::new(&mut StdRng::seed_from_u64(1)), 1);
+        assert_eq!(perlin_ridged.value(Vec3(3., -2., 7.)), 1.);
+
+        // Later octaves add finer detail.
+        let p = Vec3(0.3, 0.7, 0.1);
+        let rough = Fractal::fbm(Perlin::new(&mut StdRng::seed_from_u64(1)), 5).with_gain(0.8);
+        let smooth = Fractal::fbm(Perlin::new(&mut StdRng::seed_from_u64(1)), 5).with_gain(0.2);
+        let step = Vec3(0.01, 0., 0.);
+        let wiggle = |f: &Fractal| {
+            (0..100)
+                .map(|k| {
+                    let q = p + k as f64 * step;
+                    (f.value(q + step) - 2. * f.value(q) + f.value(q - step)).abs()
+                })
+                .sum::<f64>()
+        };
+        assert!(wiggle(&rough) > 2. * wiggle(&smooth));
+    }
+
+    #[test]
+    fn worley_works() {
+        let worley = Worley::new(&mut StdRng::seed_from_u64(1));
+        let f2 = Worley::new(&mut StdRng::seed_from_u64(1)).with_distance(Cellular::F2);
+        let edges = Worley::new(&mut StdRng::seed_from_u64(1)).with_distance(Cellular::F2MinusF1);
+
+        for p in random_points(2, 10_000) {
+            let (d1, d2) = worley.distances(p);
+            assert!(0. <= d1 && d1 <= d2);
+            assert!(d1 < 3f64.sqrt());
+            assert_eq!(worley.value(p), d1);
+            assert_eq!(f2.value(p), d2);
+            assert_eq!(edges.value(p), d2 - d1);
+
+            // The nearest point is no farther than any feature point in
+            // a wider neighbourhood.
+            let cell = [p.x(), p.y(), p.z()].map(|x| x.floor() as i64);
+            for k in 0..125 {
+                let offset = [k % 5 - 2, k / 5 % 5 - 2, k / 25 - 2];
+                let neighbour = [0, 1, 2].map(|axis| cell[axis] + offset[axis]);
+                assert!((worley.feature_point(neighbour) - p).length() >= d1);
+            }
+        }
+
+        // Feature points are at distance 0, and repeat with the lattice
+        // hash.
+        let point = worley.feature_point([4, -3, 1]);
+        assert_eq!(worley.value(point), 0.);
+        assert!(edges.value(point) > 0.);
+        let period = POINT_COUNT as f64;
+        assert_eq!(
+            worley.feature_point([4 + POINT_COUNT as i64, -3, 1]),
+            point + Vec3(period, 0., 0.)
+        );
+    }
+
+    /// Pattern that is the x coordinate.
+    struct Ramp;
+
+    impl Pattern for Ramp {
+        fn value(&self, p: Vec3) -> f64 {
+            p.x()
+        }
+    }
+
+    #[test]
+    fn domain_warp_works() {
+        let warp = || Fractal::fbm(Perlin::new(&mut StdRng::seed_from_u64(1)), 3);
+        let still = Warped::new(Rc::new(Ramp), warp(), 0.);
+        let warped = Warped::new(Rc::new(Ramp), warp(), 0.5);
+
+        let mut moved = 0.;
+        for p in random_points(2, 1000) {
+            assert_eq!(still.value(p), p.x());
+            let shift = warped.value(p) - p.x();
+            assert!(shift.abs() <= 0.5);
+            assert!((shift - warped.displacement(p).x()).abs() < 1e-12);
+            moved += shift.abs();
+        }
+        assert!(moved / 1000. > 0.02);
+    }
+}
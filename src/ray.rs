@@ -1,20 +1,66 @@
 use core::f64;
 
-use crate::vec3::Vec3;
+use crate::{medium::MediumStack, vec3::Vec3};
+
+/// Rays offset by one pixel in x and in y from a camera ray, tracking how
+/// far apart the rays through neighbouring pixels land on surfaces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_dir: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_dir: Vec3,
+}
 
 #[derive(Debug, Clone)]
 pub struct Ray {
     origin: Vec3,
     dir: Vec3,
-    tm: f64
+    tm: f64,
+    media: MediumStack,                    // Media the ray travels through
+    wavelength: Option<f64>,               // Hero wavelength in nm when rendering spectrally
+    differential: Option<RayDifferential>, // Offset rays, if the footprint is tracked
 }
 
 impl Ray {
     pub fn new(origin: Vec3, dir: Vec3, time: f64) -> Self {
         Ray {
-            origin: origin,
+            origin,
             dir,
-            tm: time
+            tm: time,
+            media: MediumStack::new(),
+            wavelength: None,
+            differential: None,
+        }
+    }
+
+    /// Returns the ray travelling through the given media.
+    pub fn with_media(mut self, media: MediumStack) -> Self {
+        self.media = media;
+        self
+    }
+
+    /// Returns the ray carrying the given hero wavelength.
+    pub fn with_wavelength(mut self, wavelength: Option<f64>) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
+
+    /// Returns the ray with the given differential.
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
+
+    /// Scales the offsets of the differential rays by s, narrowing the
+    /// footprint when several samples share a pixel.
+    pub fn scale_differential(&mut self, s: f64) {
+        let (o, d) = (self.origin, self.dir);
+        if let Some(diff) = &mut self.differential {
+            diff.rx_origin = o + s * (diff.rx_origin - o);
+            diff.ry_origin = o + s * (diff.ry_origin - o);
+            diff.rx_dir = d + s * (diff.rx_dir - d);
+            diff.ry_dir = d + s * (diff.ry_dir - d);
         }
     }
 
@@ -30,8 +76,20 @@ impl Ray {
         self.tm
     }
 
+    pub fn media(&self) -> &MediumStack {
+        &self.media
+    }
+
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
+    }
+
+    pub fn differential(&self) -> Option<RayDifferential> {
+        self.differential
+    }
+
     pub fn at(&self, t: f64) -> Vec3 {
-        self.origin.clone() + self.dir.clone() * t
+        self.origin + self.dir * t
     }
 }
 
@@ -44,7 +102,10 @@ mod tests {
         let ray = Ray {
             origin: Vec3(1.0, 1.0, 0.0),
             dir: Vec3(0.0, 2.0, 1.0),
-            tm: 0.
+            tm: 0.,
+            media: MediumStack::new(),
+            wavelength: None,
+            differential: None,
         };
         assert_eq!(ray.at(-1.0), Vec3(1.0, -1.0, -1.0))
     }
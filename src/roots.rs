@@ -0,0 +1,166 @@
+//! Real roots of low degree polynomials, for intersecting rays with
+//! implicit surfaces.
+
+use std::f64::consts::PI;
+
+/// Real roots of a x² + b x + c in increasing order, computed without
+/// the cancellation of the textbook formula. A double root is returned
+/// twice; a linear polynomial gives its one root.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0. {
+        return (b != 0.).then(|| (-c / b, -c / b));
+    }
+    let disc = b * b - 4. * a * c;
+    if disc < 0. {
+        return None;
+    }
+    let q = -0.5 * (b + disc.sqrt().copysign(b));
+    let (t0, t1) = match q {
+        0. => (0., 0.),
+        _ => (q / a, c / q),
+    };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Real roots of x³ + a x² + b x + c in increasing order, by Cardano's
+/// formula or, with three real roots, the trigonometric method.
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Depressed cubic y³ + p y + q with x = y - a / 3.
+    let shift = a / 3.;
+    let p = b - a * shift;
+    let q = 2. * shift * shift * shift - b * shift + c;
+    let disc = (q / 2.).powi(2) + (p / 3.).powi(3);
+
+    let mut roots = if disc > 0. {
+        let s = disc.sqrt();
+        vec![(-q / 2. + s).cbrt() + (-q / 2. - s).cbrt()]
+    } else if p == 0. {
+        vec![0.]
+    } else {
+        let r = 2. * (-p / 3.).sqrt();
+        let phi = (3. * q / (p * r)).clamp(-1., 1.).acos() / 3.;
+        (0..3)
+            .map(|k| r * (phi - 2. * PI * k as f64 / 3.).cos())
+            .collect()
+    };
+    roots.iter_mut().for_each(|y| *y -= shift);
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+/// Real roots of x⁴ + a x³ + b x² + c x + d in increasing order, by
+/// Ferrari's method, polished with Newton steps. Roots of even
+/// multiplicity may be missed when rounding lifts the polynomial off zero.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depressed quartic y⁴ + p y² + q y + r with x = y - a / 4.
+    let shift = a / 4.;
+    let p = b - 6. * shift * shift;
+    let q = c - 2. * b * shift + 8. * shift * shift * shift;
+    let r = d - c * shift + b * shift * shift - 3. * shift.powi(4);
+
+    let mut roots = vec![];
+    let mut push_quadratic = |b: f64, c: f64| {
+        if let Some((y0, y1)) = solve_quadratic(1., b, c) {
+            roots.extend([y0, y1]);
+        }
+    };
+    if q.abs() < 1e-12 * (1. + p.abs() + r.abs()) {
+        // Biquadratic: a quadratic in y².
+        if let Some((z0, z1)) = solve_quadratic(1., p, r) {
+            for z in [z0, z1].into_iter().filter(|&z| z >= 0.) {
+                push_quadratic(0., -z);
+            }
+        }
+    } else {
+        // y⁴ + p y² + q y + r = (y² + p/2 + m)² - 2m (y - q / 4m)² for
+        // the largest root m of the resolvent cubic, which is positive.
+        let m = *solve_cubic(p, p * p / 4. - r, -q * q / 8.)
+            .last()
+            .expect("a cubic has a real root");
+        if m <= 0. {
+            return vec![];
+        }
+        let s = (2. * m).sqrt();
+        push_quadratic(-s, p / 2. + m + q / (2. * s));
+        push_quadratic(s, p / 2. + m - q / (2. * s));
+    }
+
+    let f = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+    let df = |x: f64| ((4. * x + 3. * a) * x + 2. * b) * x + c;
+    let mut roots: Vec<f64> = roots
+        .into_iter()
+        .map(|y| {
+            let mut x = y - shift;
+            for _ in 0..2 {
+                let slope = df(x);
+                if slope != 0. {
+                    x -= f(x) / slope;
+                }
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadratic_roots_work() {
+        assert_eq!(solve_quadratic(1., -3., 2.), Some((1., 2.)));
+        assert_eq!(solve_quadratic(1., 0., 1.), None);
+        assert_eq!(solve_quadratic(0., 2., -4.), Some((2., 2.)));
+        // Small roots next to large ones keep their precision.
+        let (t0, t1) = solve_quadratic(1., -1e8, 1.).unwrap();
+        assert!((t0 - 1e-8).abs() < 1e-20 && (t1 - 1e8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cubic_roots_work() {
+        // (x - 1)(x - 2)(x + 3) = x³ - 7x + 6.
+        let roots = solve_cubic(0., -7., 6.);
+        assert_eq!(roots.len(), 3);
+        for (root, expected) in roots.iter().zip([-3., 1., 2.]) {
+            assert!((root - expected).abs() < 1e-12);
+        }
+        // x³ - 1 has the single real root 1.
+        let roots = solve_cubic(0., 0., -1.);
+        assert_eq!(roots.len(), 1);
+        assert!((roots[0] - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn quartic_roots_work() {
+        let from_roots = |r: [f64; 4]| {
+            let a = -(r[0] + r[1] + r[2] + r[3]);
+            let b =
+                r[0] * r[1] + r[0] * r[2] + r[0] * r[3] + r[1] * r[2] + r[1] * r[3] + r[2] * r[3];
+            let c = -(r[0] * r[1] * r[2]
+                + r[0] * r[1] * r[3]
+                + r[0] * r[2] * r[3]
+                + r[1] * r[2] * r[3]);
+            let d = r[0] * r[1] * r[2] * r[3];
+            solve_quartic(a, b, c, d)
+        };
+        for expected in [
+            [-2., -1., 1., 2.],
+            [-3.5, 0.25, 1., 7.],
+            [0.1, 0.2, 5., 100.],
+        ] {
+            let roots = from_roots(expected);
+            assert_eq!(roots.len(), 4);
+            for (root, expected) in roots.iter().zip(expected) {
+                assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+            }
+        }
+        // (x² + 1)(x - 1)(x - 3) has two real roots.
+        let roots = solve_quartic(-4., 4., -4., 3.);
+        assert_eq!(roots.len(), 2);
+        assert!((roots[0] - 1.).abs() < 1e-9 && (roots[1] - 3.).abs() < 1e-9);
+        // x⁴ + 1 has none.
+        assert!(solve_quartic(0., 0., 0., 1.).is_empty());
+    }
+}
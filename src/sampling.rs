@@ -0,0 +1,307 @@
+//! Sampling in proportion to tabulated functions, and weights for
+//! combining samples from several strategies.
+
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+/// Piecewise-constant density on [0, 1) proportional to n nonnegative
+/// values, one per equal segment. All zero values give a uniform density.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>, // n + 1 values from 0 to 1 at the segment boundaries
+    integral: f64, // Integral of the function over [0, 1)
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        assert!(!func.is_empty());
+        assert!(func.iter().all(|&f| f >= 0. && f.is_finite()));
+        let n = func.len();
+        let mut cdf = vec![0.; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = match integral {
+                0. => i as f64 / n as f64,
+                _ => *c / integral,
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Number of segments.
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Segment holding the fraction u in [0, 1) of the total.
+    fn segment(&self, u: f64) -> usize {
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// Maps u in [0, 1) to a point x with the distribution's density,
+    /// returning x, its density and its segment.
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let i = self.segment(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = match width > 0. {
+            true => ((u - self.cdf[i]) / width).clamp(0., 1.),
+            false => 0.,
+        };
+        let x = ((i as f64 + du) / self.count() as f64).min(1. - f64::EPSILON);
+        (x, self.pdf(x), i)
+    }
+
+    /// Picks a segment with probability proportional to its value,
+    /// returning it and the probability.
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let i = self.segment(u);
+        (i, self.discrete_pdf(i))
+    }
+
+    /// Density at x in [0, 1).
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.discrete_pdf(i) * self.count() as f64
+    }
+
+    /// Probability that `sample_discrete` picks segment i.
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+}
+
+/// Piecewise-constant density on [0, 1)² proportional to a grid of values,
+/// sampled by picking a row from the marginal distribution of the row
+/// sums, then a point in the row from its conditional distribution.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>, // Along u in each row
+    marginal: Distribution1D,         // Of the rows along v
+}
+
+impl Distribution2D {
+    /// Distribution over the values of a grid nu wide and nv high, stored
+    /// row by row.
+    pub fn new(func: &[f64], nu: usize, nv: usize) -> Self {
+        assert_eq!(func.len(), nu * nv);
+        let conditional: Vec<_> = func
+            .chunks(nu)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Maps (u1, u2) in [0, 1)² to a point (u, v) with the distribution's
+    /// density, returning the point and its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u2);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u1);
+        ((u, v), pdf_v * pdf_u)
+    }
+
+    /// Density at (u, v).
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+/// Barycentric coordinates (b0, b1, b2) of a uniformly distributed point
+/// on a triangle, from (u1, u2) in [0, 1)².
+pub fn sample_uniform_triangle(u1: f64, u2: f64) -> (f64, f64, f64) {
+    let su1 = u1.sqrt();
+    let (b0, b1) = (1. - su1, u2 * su1);
+    (b0, b1, 1. - b0 - b1)
+}
+
+/// Solid angle of the triangle whose vertices lie in directions a, b and
+/// c, by the formula of Van Oosterom and Strackee.
+pub fn spherical_triangle_area(a: Vec3, b: Vec3, c: Vec3) -> f64 {
+    let (a, b, c) = (a.unit(), b.unit(), c.unit());
+    let numerator = a.dot(&b.cross(&c)).abs();
+    let denominator = 1. + a.dot(&b) + b.dot(&c) + c.dot(&a);
+    2. * numerator.atan2(denominator)
+}
+
+/// Uniformly distributed direction within the spherical triangle whose
+/// vertices lie in directions a, b and c, from (u1, u2) in [0, 1)², by
+/// Arvo's method ("Stratified Sampling of Spherical Triangles", 1995).
+/// None for triangles too small or thin to sample.
+pub fn sample_spherical_triangle(a: Vec3, b: Vec3, c: Vec3, u1: f64, u2: f64) -> Option<Vec3> {
+    let (a, b, c) = (a.unit(), b.unit(), c.unit());
+    let (n_ab, n_bc, n_ca) = (a.cross(&b), b.cross(&c), c.cross(&a));
+    if n_ab.near_zero() || n_bc.near_zero() || n_ca.near_zero() {
+        return None;
+    }
+    let (n_ab, n_bc, n_ca) = (n_ab.unit(), n_bc.unit(), n_ca.unit());
+
+    // Angles at the vertices, and the area as their spherical excess.
+    let angle = |v: Vec3, w: Vec3| v.dot(&w).clamp(-1., 1.).acos();
+    let alpha = angle(n_ab, -n_ca);
+    let beta = angle(n_bc, -n_ab);
+    let gamma = angle(n_ca, -n_bc);
+    let area = alpha + beta + gamma - PI;
+    if area <= 0. {
+        return None;
+    }
+
+    // Find the point c' on the arc from a to c such that the triangle
+    // a b c' has the fraction u1 of the area.
+    let sub_area = u1 * area;
+    let (sin_phi, cos_phi) = (PI + sub_area - alpha).sin_cos();
+    let (sin_alpha, cos_alpha) = alpha.sin_cos();
+    let k1 = cos_phi + cos_alpha;
+    let k2 = sin_phi - sin_alpha * a.dot(&b);
+    let cos_b = (k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha)
+        / ((k2 * sin_phi + k1 * cos_phi) * sin_alpha);
+    let cos_b = cos_b.clamp(-1., 1.);
+    let sin_b = (1. - cos_b * cos_b).max(0.).sqrt();
+    let c_prime = cos_b * a + sin_b * orthogonal_part(c, a)?;
+
+    // Then a point on the arc from b to c' at the fraction u2 of the way
+    // in cosine.
+    let cos_theta = 1. - u2 * (1. - c_prime.dot(&b));
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    Some(cos_theta * b + sin_theta * orthogonal_part(c_prime, b)?)
+}
+
+/// Unit vector along the part of v orthogonal to the unit vector w.
+fn orthogonal_part(v: Vec3, w: Vec3) -> Option<Vec3> {
+    let part = v - v.dot(&w) * w;
+    (!part.near_zero()).then(|| part.unit())
+}
+
+/// Veach's power heuristic with exponent 2: the weight of a sample drawn
+/// with density pdf, when another strategy could have drawn it with
+/// density other_pdf.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (f, g) = (pdf * pdf, other_pdf * other_pdf);
+    match f + g {
+        0. => 0.,
+        sum => f / sum,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_1d_works() {
+        let d = Distribution1D::new(vec![1., 0., 3.]);
+        assert!((d.integral() - 4. / 3.).abs() < 1e-12);
+        assert!((d.discrete_pdf(0) - 0.25).abs() < 1e-12);
+        assert_eq!(d.discrete_pdf(1), 0.);
+        assert!((d.pdf(0.9) - 2.25).abs() < 1e-12);
+
+        // The first quarter of u maps onto the first segment, the rest
+        // onto the last, skipping the empty one.
+        let (x, pdf, i) = d.sample_continuous(0.125);
+        assert!((x - 1. / 6.).abs() < 1e-12 && (pdf - 0.75).abs() < 1e-12 && i == 0);
+        let (x, _, i) = d.sample_continuous(0.25);
+        assert!((x - 2. / 3.).abs() < 1e-12 && i == 2);
+        assert_eq!(d.sample_discrete(0.99).0, 2);
+        assert!(d.sample_continuous(1. - 1e-16).0 < 1.);
+
+        // Zero functions sample uniformly.
+        let flat = Distribution1D::new(vec![0., 0.]);
+        assert_eq!(flat.sample_continuous(0.75).0, 0.75);
+        assert_eq!(flat.pdf(0.2), 1.);
+    }
+
+    #[test]
+    fn distribution_2d_matches_its_pdf() {
+        // Samples land in each cell in proportion to its value.
+        let func = [0., 1., 2., 3., 0., 6.];
+        let d = Distribution2D::new(&func, 3, 2);
+        let n = 300;
+        let mut counts = [0.; 6];
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let ((u, v), pdf) = d.sample(u1, u2);
+                assert!((pdf - d.pdf(u, v)).abs() < 1e-12);
+                counts[(v * 2.) as usize * 3 + (u * 3.) as usize] += 1.;
+            }
+        }
+        for (count, f) in counts.iter().zip(func) {
+            assert!((count / (n * n) as f64 - f / 12.).abs() < 1e-3);
+        }
+        // The density integrates to 1.
+        let integral: f64 = (0..6)
+            .map(|k| d.pdf((k % 3) as f64 / 3. + 0.1, (k / 3) as f64 / 2. + 0.1) / 6.)
+            .sum();
+        assert!((integral - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn triangle_sampling_works() {
+        let n = 100;
+        let mut mean = (0., 0., 0.);
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let (b0, b1, b2) = sample_uniform_triangle(u1, u2);
+                assert!(b0 >= 0. && b1 >= 0. && b2 >= -1e-15);
+                mean = (mean.0 + b0, mean.1 + b1, mean.2 + b2);
+            }
+        }
+        // Uniform points average to the centroid.
+        let count = (n * n) as f64;
+        for m in [mean.0, mean.1, mean.2] {
+            assert!((m / count - 1. / 3.).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn spherical_triangle_sampling_works() {
+        // The triangle between the axes covers an eighth of the sphere.
+        let (a, b, c) = (Vec3(1., 0., 0.), Vec3(0., 1., 0.), Vec3(0., 0., 1.));
+        assert!((spherical_triangle_area(a, b, c) - PI / 2.).abs() < 1e-12);
+        assert!((spherical_triangle_area(2. * a, b, 3. * c) - PI / 2.).abs() < 1e-12);
+
+        // Uniform directions in it lie above z = 0.5 half of the time, as
+        // the cap there covers half of it.
+        let n = 200;
+        let mut above = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let w = sample_spherical_triangle(a, b, c, u1, u2).unwrap();
+                assert!((w.length() - 1.).abs() < 1e-9);
+                assert!(w.x() >= -1e-9 && w.y() >= -1e-9 && w.z() >= -1e-9);
+                if w.z() > 0.5 {
+                    above += 1;
+                }
+            }
+        }
+        assert!((above as f64 / (n * n) as f64 - 0.5).abs() < 5e-3);
+        assert!(sample_spherical_triangle(a, a, c, 0.5, 0.5).is_none());
+    }
+
+    #[test]
+    fn power_heuristic_works() {
+        assert_eq!(power_heuristic(1., 0.), 1.);
+        assert_eq!(power_heuristic(0., 0.), 0.);
+        assert!((power_heuristic(1., 2.) - 0.2).abs() < 1e-12);
+        assert!((power_heuristic(3., 1.) + power_heuristic(1., 3.) - 1.).abs() < 1e-12);
+    }
+}
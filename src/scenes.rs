@@ -0,0 +1,1252 @@
+//! Demo scenes selected with --scene, each with a camera framing it.
+
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tracerust::camera::Camera;
+use tracerust::color::Color;
+use tracerust::csg::Csg;
+use tracerust::environment::EnvironmentMap;
+use tracerust::hittable::{AlphaMasked, Hittable, HittableList, Sphere};
+use tracerust::light::{DirectionalLight, PointLight, SpotLight};
+use tracerust::lightsampler::LightSelection;
+use tracerust::material::{
+    BumpMap, ClearCoat, Conductor, Dielectric, DiffuseLight, Lambertian, Material, Metal,
+    MixMaterial, NormalMap, Principled, RoughDielectric,
+};
+use tracerust::medium::Ior;
+use tracerust::mesh::TriangleMesh;
+use tracerust::mipmap::Filter;
+use tracerust::noise::{Cellular, Fractal, Perlin, Warped, Worley};
+use tracerust::shapes::{Cone, Cylinder, Disk, Plane, Torus};
+use tracerust::sky::PhysicalSky;
+use tracerust::spectrum::blackbody;
+use tracerust::texture::{
+    CheckerTexture, ColorRamp, ImageTexture, MappedTexture, Mapping, NoiseTexture, SolidColor,
+    Texture,
+};
+use tracerust::vec3::Vec3;
+
+/// Builds the named scene, with a BVH over its objects, and its camera.
+/// Random scenes are generated from seed, so that every worker of a
+/// distributed render builds the same scene.
+pub fn build(name: &str, seed: u64) -> (HittableList, Camera) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (world, cam) = match name {
+        "bouncing_spheres" => bouncing_spheres(&mut rng),
+        "checkered_spheres" => checkered_spheres(),
+        "microfacets" => microfacets(),
+        "principled" => principled(),
+        "dispersion" => dispersion(),
+        "layered" => layered(),
+        "bumps" => bumps(&mut rng),
+        "cutouts" => cutouts(&mut rng),
+        "mappings" => mappings(),
+        "filtering" => filtering(),
+        "patterns" => patterns(&mut rng),
+        "lights" => lights(),
+        "environment" => environment(),
+        "daylight" => daylight(),
+        "many_lights" => many_lights(&mut rng),
+        "neon" => neon(),
+        "shapes" => shapes(),
+        "csg" => csg(),
+        _ => panic!("unknown scene: {}", name),
+    };
+
+    (world.into_bvh(), cam)
+}
+
+fn random_color(rng: &mut StdRng, min: f64, max: f64) -> Color {
+    Color::new(
+        rng.random_range(min..max),
+        rng.random_range(min..max),
+        rng.random_range(min..max),
+    )
+}
+
+fn bouncing_spheres(rng: &mut StdRng) -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    // let ground_material: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.32,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rng.random::<f64>();
+            let center = Vec3(
+                a as f64 + 0.9 * rng.random::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.random::<f64>(),
+            );
+
+            if (center - Vec3(4., 0.2, 0.)).length() > 0.9 {
+                if choose_mat < 0.8 {
+                    //  diffuse
+                    let albedo = random_color(rng, 0., 1.) * random_color(rng, 0., 1.);
+                    let material = Rc::new(Lambertian::new(albedo));
+                    let center2 = center + Vec3(0., rng.random_range(0. ..0.2), 0.);
+                    let sphere = Sphere::moving(center, center2, 0.2, material);
+                    world.add(Rc::new(sphere));
+                } else if choose_mat < 0.95 {
+                    // metal
+                    let albedo = random_color(rng, 0.5, 1.);
+                    let fuzz = rng.random_range(0. ..0.5);
+                    let material: Rc<dyn Material> = Rc::new(Metal::new(albedo, fuzz));
+                    let sphere = Sphere::stationary(center, 0.2, &material);
+                    world.add(Rc::new(sphere));
+                } else {
+                    // glass
+                    let material: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+                    let sphere = Sphere::stationary(center, 0.2, &material);
+                    world.add(Rc::new(sphere));
+                }
+            }
+        }
+    }
+
+    let material_1: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., 1., 0.),
+        1.,
+        &material_1,
+    )));
+
+    let material_3: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(4., 1., 0.),
+        1.0,
+        &material_3,
+    )));
+    let material_2: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(-4., 1., 0.),
+        1.,
+        &material_2,
+    )));
+
+    // Set up camera
+    let aspect_ratio = 16.0_f64 / 9.0_f64;
+    // let image_width = 800;
+    // let samples_per_pixel = 100;
+    // let max_depth = 50;
+    let image_width = 400;
+    let samples_per_pixel = 20;
+    let max_depth = 20;
+
+    let vfov = 20.;
+    let lookfrom = Vec3(13., 2., 3.);
+    let lookat = Vec3(0., 0., 0.);
+    let vup = Vec3(0., 1., 0.);
+    let defocus_angle = 0.6;
+    let focus_distance = 10.0;
+
+    let camera = Camera::new(
+        aspect_ratio,
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        vfov,
+        lookfrom,
+        lookat,
+        vup,
+        focus_distance,
+        defocus_angle,
+    );
+
+    (world, camera)
+}
+
+fn checkered_spheres() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.32,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -10., 0.),
+        10.,
+        &ground_material,
+    )));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., 10., 0.),
+        10.,
+        &ground_material,
+    )));
+
+    // Set up camera
+    let aspect_ratio = 16.0_f64 / 9.0_f64;
+    let image_width = 400;
+    let samples_per_pixel = 100;
+    let max_depth = 50;
+
+    let vfov = 20.;
+    let lookfrom = Vec3(13., 2., 3.);
+    let lookat = Vec3(0., 0., 0.);
+    let vup = Vec3(0., 1., 0.);
+    let defocus_angle = 0.;
+    let focus_distance = 10.0;
+
+    let camera = Camera::new(
+        aspect_ratio,
+        image_width,
+        samples_per_pixel,
+        max_depth,
+        vfov,
+        lookfrom,
+        lookat,
+        vup,
+        focus_distance,
+        defocus_angle,
+    );
+
+    (world, camera)
+}
+
+/// Rough metals and glass next to each other on a checkered floor.
+fn microfacets() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let materials: [Rc<dyn Material>; 5] = [
+        Rc::new(Conductor::gold(0.2)),
+        Rc::new(Conductor::copper(0.4)),
+        Rc::new(RoughDielectric::new(1.5, 0.3)),
+        Rc::new(Conductor::aluminium(0.3)),
+        Rc::new(Conductor::silver(0.05)),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (k as f64 - 2.)),
+            1.,
+            material,
+        )));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        30.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Spheres showing the knobs of the principled material, from left to
+/// right: plastic, sheen, clear coated paint, brushed metal and tinted glass.
+fn principled() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let color = |r, g, b| Rc::new(SolidColor::new(Color::new(r, g, b))) as Rc<dyn Texture>;
+    let gray = |x| Rc::new(SolidColor::gray(x)) as Rc<dyn Texture>;
+    let materials: [Rc<dyn Material>; 5] = [
+        Rc::new(Principled::new(color(0.1, 0.3, 0.8)).with_roughness(gray(0.2))),
+        Rc::new(
+            Principled::new(color(0.5, 0.1, 0.3))
+                .with_roughness(gray(0.9))
+                .with_sheen(gray(1.)),
+        ),
+        Rc::new(
+            Principled::new(color(0.7, 0.05, 0.05))
+                .with_metallic(gray(0.5))
+                .with_clearcoat(gray(1.)),
+        ),
+        Rc::new(
+            Principled::new(color(0.9, 0.9, 0.9))
+                .with_metallic(gray(1.))
+                .with_roughness(gray(0.4))
+                .with_anisotropic(gray(0.9)),
+        ),
+        Rc::new(
+            Principled::new(color(0.6, 0.9, 0.7))
+                .with_transmission(gray(1.))
+                .with_roughness(gray(0.1)),
+        ),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (2. - k as f64)),
+            1.,
+            material,
+        )));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        30.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Spheres of increasingly dispersive glass, from left to right: water,
+/// crown glass, flint glass and diamond. Render with --spectral to see
+/// their colored fringes.
+fn dispersion() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.1, 0.1, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let iors = [Ior::WATER, Ior::BK7, Ior::SF11, Ior::DIAMOND];
+    for (k, ior) in iors.into_iter().enumerate() {
+        let material: Rc<dyn Material> = Rc::new(Dielectric::from_ior(ior));
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (1.5 - k as f64)),
+            1.,
+            &material,
+        )));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Spheres built from other materials, from left to right: paint worn
+/// through to aluminium in a checker pattern, lacquered red paint and
+/// amber varnish over a checkered base.
+fn layered() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let paint: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.1, 0.3, 0.7)));
+    let metal: Rc<dyn Material> = Rc::new(Conductor::aluminium(0.2));
+    let mask = Rc::new(CheckerTexture::from_colors(
+        0.2,
+        Vec3(0., 0., 0.),
+        Vec3(1., 1., 1.),
+    ));
+    let red: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.05, 0.05)));
+    let base = Rc::new(CheckerTexture::from_colors(
+        0.25,
+        Vec3(0.8, 0.7, 0.5),
+        Vec3(0.4, 0.25, 0.1),
+    ));
+    let wood: Rc<dyn Material> = Rc::new(Lambertian::from_texture(base));
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(MixMaterial::new(paint, metal, mask)),
+        Rc::new(ClearCoat::new(red, 1.5)),
+        Rc::new(ClearCoat::new(wood, 1.5).with_tint(Color::new(0.9, 0.6, 0.3))),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (1. - k as f64)),
+            1.,
+            material,
+        )));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Normal map of a grid of rounded studs, as a map loaded with
+/// `ImageTexture::load_linear` would give it.
+fn studs_normal_map(size: u32, studs: u32) -> ImageTexture {
+    let cell = size as f64 / studs as f64;
+    let mut pixels = Vec::new();
+    for j in 0..size {
+        for i in 0..size {
+            // Offset from the center of the stud, in units of its radius.
+            let x = ((i as f64 + 0.5) % cell / cell - 0.5) * 2.5;
+            let y = -((j as f64 + 0.5) % cell / cell - 0.5) * 2.5;
+            let r2 = x * x + y * y;
+            let n = match r2 < 1. {
+                true => Vec3(x, y, (1. - r2).sqrt() + 0.5).unit(),
+                false => Vec3(0., 0., 1.),
+            };
+            pixels.push(0.5 * (n + Vec3(1., 1., 1.)));
+        }
+    }
+    ImageTexture::new(size, size, pixels)
+}
+
+/// Spheres with perturbed normals, from left to right: plaster bumped by
+/// Perlin noise, hammered copper and a normal mapped grid of studs.
+fn bumps(rng: &mut StdRng) -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let plaster: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.75, 0.7)));
+    let copper: Rc<dyn Material> = Rc::new(Conductor::copper(0.15));
+    let paint: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.1, 0.1)));
+    let fine_noise = Rc::new(NoiseTexture::new(Perlin::new(rng), 8.));
+    let coarse_noise = Rc::new(NoiseTexture::new(Perlin::new(rng), 5.));
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(BumpMap::new(plaster, fine_noise, 0.02)),
+        Rc::new(BumpMap::new(copper, coarse_noise, 0.15)),
+        Rc::new(NormalMap::new(paint, Rc::new(studs_normal_map(256, 8)))),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        world.add(Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (1. - k as f64)),
+            1.,
+            material,
+        )));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Spheres with parts cut away by alpha, from left to right: a checker
+/// lattice, holes eaten by Perlin noise and a half transparent shell.
+fn cutouts(rng: &mut StdRng) -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let lattice = Rc::new(CheckerTexture::from_colors(
+        0.25,
+        Vec3(0., 0., 0.),
+        Vec3(1., 1., 1.),
+    ));
+    let noise = Rc::new(NoiseTexture::new(Perlin::new(rng), 4.));
+    let half = Rc::new(SolidColor::gray(0.5));
+    let alphas: [(Rc<dyn Texture>, Option<f64>); 3] =
+        [(lattice, Some(0.5)), (noise, Some(0.5)), (half, None)];
+    let colors = [
+        Color::new(0.8, 0.6, 0.2),
+        Color::new(0.2, 0.6, 0.3),
+        Color::new(0.2, 0.3, 0.8),
+    ];
+    for (k, ((alpha, threshold), color)) in alphas.into_iter().zip(colors).enumerate() {
+        let material: Rc<dyn Material> = Rc::new(Lambertian::new(color));
+        let sphere = Rc::new(Sphere::stationary(
+            Vec3(0., 1., 2.2 * (1. - k as f64)),
+            1.,
+            &material,
+        ));
+        let masked = AlphaMasked::new(sphere, alpha);
+        world.add(Rc::new(match threshold {
+            Some(threshold) => masked.with_threshold(threshold),
+            None => masked,
+        }));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Textures placed by mappings, from left to right: a checker fixed to a
+/// moving sphere, stripes wound around a sphere by a rotated UV mapping,
+/// and stripes projected onto a sphere from three sides.
+fn mappings() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.3, 0.1),
+        Vec3(0.9, 0.9, 0.9),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let stripes = Rc::new(ImageTexture::new(
+        2,
+        1,
+        vec![Color::new(0.8, 0.7, 0.1), Color::new(0.1, 0.2, 0.6)],
+    ));
+    let object_checker = MappedTexture::new(
+        Rc::new(CheckerTexture::from_colors(
+            0.3,
+            Vec3(0.8, 0.1, 0.1),
+            Vec3(0.9, 0.9, 0.9),
+        )),
+        Mapping::Object,
+    );
+    let spiral = MappedTexture::new(
+        stripes.clone(),
+        Mapping::Uv {
+            scale: (12., 2.),
+            rotation: 0.4,
+            offset: (0., 0.),
+        },
+    );
+    let projected = MappedTexture::new(
+        Rc::new(MappedTexture::new(stripes, Mapping::scale(3., 3.))),
+        Mapping::Triplanar { sharpness: 4. },
+    );
+    let textures: [Rc<dyn Texture>; 3] =
+        [Rc::new(object_checker), Rc::new(spiral), Rc::new(projected)];
+
+    for (k, texture) in textures.into_iter().enumerate() {
+        let material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(texture));
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(match k {
+            0 => Rc::new(Sphere::moving(
+                center,
+                center + Vec3(0., 0.4, 0.),
+                1.,
+                material,
+            )),
+            _ => Rc::new(Sphere::stationary(center, 1., &material)),
+        });
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Fine patterns that alias when point sampled: a box-filtered checker
+/// floor running into the distance, a sphere wrapped in a finely checked
+/// image filtered with EWA, and a mirror and a glass sphere showing both
+/// filtered through specular bounces.
+fn filtering() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let floor = CheckerTexture::from_colors(0.25, Vec3(0.1, 0.1, 0.1), Vec3(0.9, 0.9, 0.9))
+        .with_box_filter();
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(Rc::new(floor)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let (width, height) = (256, 128);
+    let pixels = (0..width * height)
+        .map(|k| match (k % width / 4 + k / width / 4) % 2 {
+            0 => Color::new(0.8, 0.2, 0.1),
+            _ => Color::new(0.9, 0.9, 0.8),
+        })
+        .collect();
+    let fine =
+        ImageTexture::new(width, height, pixels).with_filter(Filter::Ewa { max_anisotropy: 8. });
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(Lambertian::from_texture(Rc::new(fine))),
+        Rc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.)),
+        Rc::new(Dielectric::new(1.5)),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(Rc::new(Sphere::stationary(center, 1., material)));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 2., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Procedural patterns colored by ramps: a ridged mossy floor, marble of
+/// warped noise, glazed tiles along Worley cell borders and copper whose
+/// roughness follows Worley cells.
+fn patterns(rng: &mut StdRng) -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let moss = NoiseTexture::from_pattern(Rc::new(Fractal::ridged(Perlin::new(rng), 6)), 0.8)
+        .with_ramp(ColorRamp::new(vec![
+            (0.1, Color::new(0.05, 0.1, 0.02)),
+            (0.6, Color::new(0.2, 0.35, 0.05)),
+            (0.9, Color::new(0.6, 0.65, 0.3)),
+        ]));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(Rc::new(moss)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let veins = Warped::new(
+        Rc::new(Fractal::ridged(Perlin::new(rng), 3)),
+        Fractal::fbm(Perlin::new(rng), 4),
+        0.6,
+    );
+    let marble = NoiseTexture::from_pattern(Rc::new(veins), 1.5).with_ramp(ColorRamp::new(vec![
+        (0.5, Color::new(0.9, 0.88, 0.85)),
+        (0.9, Color::new(0.5, 0.45, 0.4)),
+        (1., Color::new(0.15, 0.12, 0.1)),
+    ]));
+
+    let cells = Worley::new(rng).with_distance(Cellular::F2MinusF1);
+    let tiles = NoiseTexture::from_pattern(Rc::new(cells), 3.).with_ramp(ColorRamp::new(vec![
+        (0.05, Color::new(0.1, 0.1, 0.1)),
+        (0.1, Color::new(0.1, 0.4, 0.5)),
+        (0.6, Color::new(0.2, 0.6, 0.7)),
+    ]));
+
+    let spots =
+        NoiseTexture::from_pattern(Rc::new(Worley::new(rng)), 4.).with_ramp(ColorRamp::new(vec![
+            (0.2, Color::new(0.1, 0.1, 0.1)),
+            (0.7, Color::new(0.6, 0.6, 0.6)),
+        ]));
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(Principled::new(Rc::new(marble)).with_roughness(Rc::new(SolidColor::gray(0.2)))),
+        Rc::new(
+            Principled::new(Rc::new(tiles))
+                .with_roughness(Rc::new(SolidColor::gray(0.3)))
+                .with_clearcoat(Rc::new(SolidColor::gray(1.))),
+        ),
+        Rc::new(
+            Principled::new(Rc::new(SolidColor::new(Color::new(0.95, 0.64, 0.54))))
+                .with_metallic(Rc::new(SolidColor::gray(1.)))
+                .with_roughness(Rc::new(spots)),
+        ),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(Rc::new(Sphere::stationary(center, 1., material)));
+    }
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Delta lights over a checkered floor: a warm point light, a blue
+/// spotlight with a soft edge and a low sun with a disk the size of the
+/// real one, whose shadows blur with distance.
+fn lights() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.2, 0.2),
+        Vec3(0.8, 0.8, 0.8),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7))),
+        Rc::new(
+            Principled::new(Rc::new(SolidColor::new(Color::new(0.8, 0.2, 0.1))))
+                .with_roughness(Rc::new(SolidColor::gray(0.3))),
+        ),
+        Rc::new(Conductor::new(
+            Color::new(0.2, 0.92, 1.1),
+            Color::new(3.9, 2.45, 2.14),
+            0.2,
+        )),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(Rc::new(Sphere::stationary(center, 1., material)));
+    }
+
+    world.add_light(Rc::new(PointLight::new(
+        Vec3(3., 3., 3.),
+        Color::new(12., 8., 4.),
+    )));
+    world.add_light(Rc::new(
+        SpotLight::new(
+            Vec3(2., 6., -2.5),
+            Vec3(0., 0., -2.5),
+            Color::new(10., 20., 50.),
+            25.,
+        )
+        .with_falloff(15.),
+    ));
+    world.add_light(Rc::new(
+        DirectionalLight::new(Vec3(0.6, -0.6, -1.), Color::new(3., 2.8, 2.4))
+            .with_angular_radius(0.27),
+    ));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Equirectangular sky that darkens from a pale horizon to a deep blue
+/// zenith over a gray ground, with a sun 1.5° across and bright enough to
+/// light a surface facing it with the given irradiance.
+fn synthetic_sky(width: u32, height: u32, sun: Vec3, irradiance: Color) -> EnvironmentMap {
+    let sun = sun.unit();
+    let cos_sun = (0.75f64).to_radians().cos();
+    let sun_radiance = irradiance / (PI * (1. - cos_sun * cos_sun));
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for j in 0..height {
+        let theta = PI * (j as f64 + 0.5) / height as f64;
+        for i in 0..width {
+            let phi = 2. * PI * (i as f64 + 0.5) / width as f64 - PI;
+            let dir = Vec3(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            );
+            let color = if dir.dot(&sun) >= cos_sun {
+                sun_radiance
+            } else if dir.y() < 0. {
+                Color::new(0.15, 0.14, 0.12)
+            } else {
+                let a = dir.y().sqrt();
+                (1. - a) * Color::new(0.9, 0.95, 1.) + a * Color::new(0.15, 0.3, 0.8)
+            };
+            pixels.push(color);
+        }
+    }
+    EnvironmentMap::new(width, height, pixels)
+}
+
+/// The spheres of the lights scene lit only by a high dynamic range sky,
+/// whose small sun is found by sampling the sky in proportion to its
+/// brightness and casts sharp shadows.
+fn environment() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.2, 0.2),
+        Vec3(0.8, 0.8, 0.8),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7))),
+        Rc::new(
+            Principled::new(Rc::new(SolidColor::new(Color::new(0.8, 0.2, 0.1))))
+                .with_roughness(Rc::new(SolidColor::gray(0.3))),
+        ),
+        Rc::new(Conductor::new(
+            Color::new(0.2, 0.92, 1.1),
+            Color::new(3.9, 2.45, 2.14),
+            0.2,
+        )),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(Rc::new(Sphere::stationary(center, 1., material)));
+    }
+
+    world.set_environment(Rc::new(synthetic_sky(
+        512,
+        256,
+        Vec3(0.8, 0.7, -1.),
+        Color::new(3., 2.8, 2.4),
+    )));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// The spheres of the lights scene outdoors in the late afternoon, under
+/// a physical sky baked for importance sampling and its sun.
+fn daylight() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker = Rc::new(CheckerTexture::from_colors(
+        0.5,
+        Vec3(0.2, 0.2, 0.2),
+        Vec3(0.8, 0.8, 0.8),
+    ));
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::from_texture(checker));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+
+    let materials: [Rc<dyn Material>; 3] = [
+        Rc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7))),
+        Rc::new(
+            Principled::new(Rc::new(SolidColor::new(Color::new(0.8, 0.2, 0.1))))
+                .with_roughness(Rc::new(SolidColor::gray(0.3))),
+        ),
+        Rc::new(Conductor::new(
+            Color::new(0.2, 0.92, 1.1),
+            Color::new(3.9, 2.45, 2.14),
+            0.2,
+        )),
+    ];
+    for (k, material) in materials.iter().enumerate() {
+        let center = Vec3(0., 1., 2.2 * (1. - k as f64));
+        world.add(Rc::new(Sphere::stationary(center, 1., material)));
+    }
+
+    let sky = PhysicalSky::new(20., 50., 3.)
+        .with_ground_albedo(Color::new(0.4, 0.35, 0.3))
+        .with_scale(0.03);
+    if let Some(sun) = sky.sun_light() {
+        world.add_light(Rc::new(sun));
+    }
+    world.set_environment(Rc::new(sky.bake(512, 256)));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// A night scene lit by four hundred small glowing spheres of random
+/// color and brightness hanging over a field of spheres, each hit
+/// sampling only the lamp a light BVH picks by its likely contribution.
+fn many_lights(rng: &mut StdRng) -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let ground_material: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Vec3(
+                a as f64 + 0.9 * rng.random::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.random::<f64>(),
+            );
+            let material: Rc<dyn Material> = Rc::new(Lambertian::new(random_color(rng, 0.2, 0.9)));
+            world.add(Rc::new(Sphere::stationary(center, 0.2, &material)));
+        }
+    }
+
+    let lamp_radius = 0.05;
+    for _ in 0..400 {
+        let center = Vec3(
+            rng.random_range(-12. ..12.),
+            rng.random_range(0.6..1.5),
+            rng.random_range(-12. ..12.),
+        );
+        // As bright from afar as a point light of this intensity.
+        let intensity = rng.random_range(0.02f64..1.).powi(2) * random_color(rng, 0.1, 1.);
+        let glow: Rc<dyn Material> = Rc::new(DiffuseLight::new(
+            intensity / (PI * lamp_radius * lamp_radius),
+        ));
+        world.add_sphere(Rc::new(Sphere::stationary(center, lamp_radius, &glow)));
+    }
+    world.set_light_selection(LightSelection::Bvh);
+    let night = Color::new(0.005, 0.007, 0.015);
+    world.set_environment(Rc::new(EnvironmentMap::new(1, 1, vec![night])));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Flat strip of quads facing +x, along the curve through points, with u
+/// running along it from 0 to 1 and v across it.
+fn neon_tube(points: &[Vec3], width: f64) -> TriangleMesh {
+    let across = Vec3(0., 0.5 * width, 0.);
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    for (i, &point) in points.iter().enumerate() {
+        let u = i as f64 / (points.len() - 1) as f64;
+        positions.extend([point - across, point + across]);
+        uvs.extend([(u, 0.), (u, 1.)]);
+    }
+    let faces = (0..points.len() - 1)
+        .flat_map(|i| {
+            let (a, b) = (2 * i, 2 * i + 2);
+            [[a, b + 1, b], [a, a + 1, b + 1]]
+        })
+        .collect();
+    TriangleMesh::new(positions, faces).with_uvs(uvs)
+}
+
+fn neon() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let ground_material: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.3, 0.3, 0.35), 0.2));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(0., -1000., 0.),
+        1000.,
+        &ground_material,
+    )));
+    let wall: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.4, 0.4, 0.4)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(-1002., 0., 0.),
+        1000.,
+        &wall,
+    )));
+    let glass: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(2., 0.5, -1.5),
+        0.5,
+        &glass,
+    )));
+    let white: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+    world.add(Rc::new(Sphere::stationary(
+        Vec3(1.5, 0.4, 1.2),
+        0.4,
+        &white,
+    )));
+
+    // A wave fading from magenta to cyan along its length, and an
+    // underline in the color of a 2000 K filament, each a mesh of many
+    // small emissive triangles.
+    let wave: Vec<Vec3> = (0..=240)
+        .map(|i| {
+            let z = -3. + 6. * i as f64 / 240.;
+            Vec3(-1.9, 2. + 0.35 * (2.5 * z).sin(), z)
+        })
+        .collect();
+    let ramp = ColorRamp::new(vec![
+        (0., Color::new(2., 0.1, 1.4)),
+        (1., Color::new(0.1, 1.3, 2.)),
+    ]);
+    let pixels = (0..64).map(|i| ramp.at(i as f64 / 63.)).collect();
+    let glow: Rc<dyn Material> = Rc::new(DiffuseLight::from_texture(Rc::new(ImageTexture::new(
+        64, 1, pixels,
+    ))));
+    world.add_mesh(&Rc::new(neon_tube(&wave, 0.06)), &glow);
+
+    let line = [Vec3(-1.9, 1.3, -2.5), Vec3(-1.9, 1.3, 2.5)];
+    let warm: Rc<dyn Material> = Rc::new(DiffuseLight::new(1.4 * blackbody(2000.)));
+    world.add_mesh(&Rc::new(neon_tube(&line, 0.04)), &warm);
+
+    world.set_light_selection(LightSelection::Bvh);
+    let night = Color::new(0.002, 0.003, 0.006);
+    world.set_environment(Rc::new(EnvironmentMap::new(1, 1, vec![night])));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+fn shapes() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker: Rc<dyn Material> = Rc::new(Lambertian::from_texture(Rc::new(
+        CheckerTexture::from_colors(1., Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Rc::new(Plane::new(
+        Vec3(0., 0., 0.),
+        Vec3(0., 1., 0.),
+        &checker,
+    )));
+
+    let red: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.15, 0.1)));
+    world.add(Rc::new(
+        Cylinder::new(Vec3(0., 0., -2.5), Vec3(0., 1.6, -2.5), 0.6, &red).with_caps(),
+    ));
+    let glass: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+    world.add(Rc::new(
+        Cone::new(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 0.8, &glass).with_cap(),
+    ));
+    let gold: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05));
+    world.add(Rc::new(Torus::new(
+        Vec3(0., 1., 2.6),
+        Vec3(1., 1., 0.),
+        0.8,
+        0.25,
+        &gold,
+    )));
+    let blue: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.6)));
+    world.add(Rc::new(
+        Disk::new(Vec3(2.5, 0.01, 0.5), Vec3(0., 1., 0.), 0.7, &blue).with_inner_radius(0.3),
+    ));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
+
+/// Closed box from a to b for combining with other solids.
+fn solid_box(a: Vec3, b: Vec3, mat: &Rc<dyn Material>) -> Rc<dyn Hittable> {
+    let mut faces = HittableList::new();
+    for triangle in Rc::new(TriangleMesh::cuboid(a, b)).triangles(mat) {
+        faces.add(triangle);
+    }
+    Rc::new(faces)
+}
+
+fn csg() -> (HittableList, Camera) {
+    let mut world = HittableList::new();
+
+    let checker: Rc<dyn Material> = Rc::new(Lambertian::from_texture(Rc::new(
+        CheckerTexture::from_colors(1., Color::new(0.2, 0.3, 0.1), Color::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Rc::new(Plane::new(
+        Vec3(0., 0., 0.),
+        Vec3(0., 1., 0.),
+        &checker,
+    )));
+
+    // A ball drilled through along all three axes, the bores showing the
+    // material of the drills.
+    let red: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.7, 0.15, 0.1)));
+    let white: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8)));
+    let center = Vec3(0., 1.1, -2.2);
+    let mut drilled: Rc<dyn Hittable> = Rc::new(Sphere::stationary(center, 1.1, &red));
+    for axis in [Vec3(1.4, 0., 0.), Vec3(0., 1.4, 0.), Vec3(0., 0., 1.4)] {
+        let drill = Cylinder::new(center - axis, center + axis, 0.45, &white).with_caps();
+        drilled = Rc::new(Csg::difference(drilled, Rc::new(drill)));
+    }
+    world.add(drilled);
+
+    // The rounded cube left where a box and a ball overlap.
+    let gold: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05));
+    let cube = solid_box(Vec3(-0.8, 0., 1.4), Vec3(0.8, 1.6, 3.), &gold);
+    let ball = Rc::new(Sphere::stationary(Vec3(0., 0.8, 2.2), 1.05, &gold));
+    world.add(Rc::new(Csg::intersection(cube, ball)));
+
+    // A glass lens: the overlap of two balls.
+    let glass: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+    let lens = Csg::intersection(
+        Rc::new(Sphere::stationary(Vec3(1.7, 0.9, 0.), 1., &glass)),
+        Rc::new(Sphere::stationary(Vec3(3.3, 0.9, 0.), 1., &glass)),
+    );
+    world.add(Rc::new(lens));
+
+    let camera = Camera::new(
+        16. / 9.,
+        400,
+        100,
+        50,
+        25.,
+        Vec3(13., 3., 0.),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        10.,
+        0.,
+    );
+
+    (world, camera)
+}
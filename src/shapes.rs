@@ -0,0 +1,900 @@
+//! Analytic surfaces besides the sphere: planes, disks, cylinders, cones
+//! and tori. Each is set up in a local frame whose z axis is the shape's
+//! normal or axis of symmetry, and u goes around that axis counterclockwise
+//! from the frame's x axis.
+
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use crate::{
+    bvh::AABB,
+    hittable::{HitRecord, Hittable, next_object_id},
+    material::Material,
+    ray::Ray,
+    roots::{solve_quadratic, solve_quartic},
+    util::Interval,
+    vec3::{Onb, Vec3},
+};
+
+/// Origin and orientation of a shape, with z along its axis.
+#[derive(Clone, Copy)]
+struct Frame {
+    origin: Vec3,
+    onb: Onb,
+}
+
+impl Frame {
+    fn new(origin: Vec3, axis: Vec3) -> Self {
+        Self {
+            origin,
+            onb: Onb::new(axis.unit()),
+        }
+    }
+
+    /// Origin and direction of ray in the frame.
+    fn local_ray(&self, ray: &Ray) -> (Vec3, Vec3) {
+        (
+            self.onb.to_local(ray.origin() - self.origin),
+            self.onb.to_local(ray.dir()),
+        )
+    }
+
+    /// World space vector with coordinates v in the frame.
+    fn world(&self, v: Vec3) -> Vec3 {
+        self.onb.to_world(v)
+    }
+
+    fn axis(&self) -> Vec3 {
+        self.onb.w
+    }
+
+    /// Record of a hit at distance t along ray and local point p, with the
+    /// local outward normal n and local derivatives in u and v.
+    #[allow(clippy::too_many_arguments)]
+    fn hit_record(
+        &self,
+        ray: &Ray,
+        t: f64,
+        p: Vec3,
+        n: Vec3,
+        (u, v): (f64, f64),
+        (dpdu, dpdv): (Vec3, Vec3),
+        (dndu, dndv): (Vec3, Vec3),
+        material: &Rc<dyn Material>,
+    ) -> HitRecord {
+        let point = ray.at(t);
+        let mut rec = HitRecord::new(point, t, ray, self.world(n), Rc::clone(material));
+        let side = if rec.front_face { 1. } else { -1. };
+        (rec.u, rec.v) = (u, v);
+        rec.dpdu = self.world(dpdu);
+        rec.dpdv = self.world(dpdv);
+        rec.dndu = side * self.world(dndu);
+        rec.dndv = side * self.world(dndv);
+        rec.object_point = self.world(p);
+        rec
+    }
+}
+
+/// Box around a circle of radius about center, in the plane normal to
+/// the unit vector axis.
+fn circle_box(center: Vec3, axis: Vec3, radius: f64) -> AABB {
+    let extent = |a: f64| radius * (1. - a * a).max(0.).sqrt();
+    let e = Vec3(extent(axis.x()), extent(axis.y()), extent(axis.z()));
+    AABB::from_points(center - e, center + e)
+}
+
+/// Pads a box so that flat shapes in an axis plane have some depth.
+fn padded(bbox: AABB) -> AABB {
+    AABB::new(
+        bbox.x.expand(1e-4),
+        bbox.y.expand(1e-4),
+        bbox.z.expand(1e-4),
+    )
+}
+
+/// Angle of the local point p around the z axis, in [0, 2π).
+fn azimuth(p: Vec3) -> f64 {
+    let phi = p.y().atan2(p.x());
+    if phi < 0. { phi + 2. * PI } else { phi }
+}
+
+/// Distance along the ray of local origin o and direction d to where it
+/// crosses the plane at height z, and the point there.
+fn cross_plane(o: Vec3, d: Vec3, z: f64) -> Option<(f64, Vec3)> {
+    if d.z() == 0. {
+        return None;
+    }
+    let t = (z - o.z()) / d.z();
+    Some((t, o + t * d))
+}
+
+/// Infinite plane through a point. Texture coordinates are distances
+/// along the frame's x and y axes, so textures repeat across it at their
+/// unit size.
+pub struct Plane {
+    frame: Frame,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: &Rc<dyn Material>) -> Self {
+        let normal = normal.unit();
+        // Planes along the axes are thin in the axis they face; all others
+        // fill space.
+        let span = |n: f64, p: f64| match n.abs() == 1. {
+            true => Interval::new(p, p).expand(1e-4),
+            false => Interval::new(f64::NEG_INFINITY, f64::INFINITY),
+        };
+        let bbox = AABB::new(
+            span(normal.x(), point.x()),
+            span(normal.y(), point.y()),
+            span(normal.z(), point.z()),
+        );
+        Self {
+            frame: Frame::new(point, normal),
+            material: Rc::clone(material),
+            bbox,
+            id: next_object_id(),
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.local_ray(ray);
+        let (t, p) = cross_plane(o, d, 0.)?;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+        let zero = Vec3(0., 0., 0.);
+        let mut rec = self.frame.hit_record(
+            ray,
+            t,
+            p,
+            Vec3(0., 0., 1.),
+            (p.x(), p.y()),
+            (Vec3(1., 0., 0.), Vec3(0., 1., 0.)),
+            (zero, zero),
+            &self.material,
+        );
+        rec.object_id = self.id;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+}
+
+/// Flat disk, or annulus with an inner radius, facing along its normal.
+/// u goes around the center and v from the inner edge to the outer.
+pub struct Disk {
+    frame: Frame,
+    radius: f64,
+    inner_radius: f64,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: &Rc<dyn Material>) -> Self {
+        assert!(radius > 0., "a disk needs a positive radius");
+        let frame = Frame::new(center, normal);
+        Self {
+            frame,
+            radius,
+            inner_radius: 0.,
+            material: Rc::clone(material),
+            bbox: padded(circle_box(center, frame.axis(), radius)),
+            id: next_object_id(),
+        }
+    }
+
+    /// Cuts a hole of inner_radius out of the middle.
+    pub fn with_inner_radius(mut self, inner_radius: f64) -> Self {
+        assert!((0. ..self.radius).contains(&inner_radius));
+        self.inner_radius = inner_radius;
+        self
+    }
+}
+
+/// Record of a hit on a disk or annulus of radii inner and outer in the
+/// plane z = height of frame, or None if p lies off it.
+#[allow(clippy::too_many_arguments)]
+fn disk_hit(
+    frame: &Frame,
+    ray: &Ray,
+    t: f64,
+    p: Vec3,
+    height: f64,
+    (inner, outer): (f64, f64),
+    normal: f64,
+    material: &Rc<dyn Material>,
+) -> Option<HitRecord> {
+    let rho = p.x().hypot(p.y());
+    if rho > outer || rho < inner {
+        return None;
+    }
+    let phi = azimuth(p);
+    let (dpdu, dpdv) = match rho > 0. {
+        true => (
+            2. * PI * Vec3(-p.y(), p.x(), 0.),
+            (outer - inner) * Vec3(p.x(), p.y(), 0.) / rho,
+        ),
+        false => (Vec3(0., 0., 0.), Vec3(0., 0., 0.)),
+    };
+    let zero = Vec3(0., 0., 0.);
+    Some(frame.hit_record(
+        ray,
+        t,
+        Vec3(p.x(), p.y(), height),
+        Vec3(0., 0., normal),
+        (phi / (2. * PI), (rho - inner) / (outer - inner)),
+        (dpdu, dpdv),
+        (zero, zero),
+        material,
+    ))
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (o, d) = self.frame.local_ray(ray);
+        let (t, p) = cross_plane(o, d, 0.)?;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+        let radii = (self.inner_radius, self.radius);
+        let mut rec = disk_hit(&self.frame, ray, t, p, 0., radii, 1., &self.material)?;
+        rec.object_id = self.id;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+}
+
+/// Part of a cylinder or cone a ray hits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Part {
+    Side,
+    Base,
+    Top,
+}
+
+/// Cylinder around the segment from base to top. It is an open tube
+/// unless capped, when disks close both ends to make it a solid. On the
+/// side, u goes around the axis and v from the base to the top.
+pub struct Cylinder {
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f64, material: &Rc<dyn Material>) -> Self {
+        assert!(radius > 0., "a cylinder needs a positive radius");
+        let height = (top - base).length();
+        assert!(height > 0., "a cylinder needs distinct ends");
+        let frame = Frame::new(base, top - base);
+        let bbox = AABB::from_boxes(
+            &circle_box(base, frame.axis(), radius),
+            &circle_box(top, frame.axis(), radius),
+        );
+        Self {
+            frame,
+            height,
+            radius,
+            capped: false,
+            material: Rc::clone(material),
+            bbox: padded(bbox),
+            id: next_object_id(),
+        }
+    }
+
+    /// Closes both ends with disks.
+    pub fn with_caps(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+
+    /// Distances along ray, in increasing order, where it crosses the
+    /// surface, and the part crossed there.
+    fn crossings(&self, ray: &Ray) -> Vec<(f64, Part)> {
+        let (o, d) = self.frame.local_ray(ray);
+        let mut crossings = vec![];
+        let a = d.x() * d.x() + d.y() * d.y();
+        let b = 2. * (o.x() * d.x() + o.y() * d.y());
+        let c = o.x() * o.x() + o.y() * o.y() - self.radius * self.radius;
+        if a > 0.
+            && let Some((t0, t1)) = solve_quadratic(a, b, c)
+        {
+            for t in [t0, t1] {
+                let z = o.z() + t * d.z();
+                if (0. ..=self.height).contains(&z) {
+                    crossings.push((t, Part::Side));
+                }
+            }
+        }
+        if self.capped {
+            for (z, part) in [(0., Part::Base), (self.height, Part::Top)] {
+                if let Some((t, p)) = cross_plane(o, d, z)
+                    && p.x().hypot(p.y()) <= self.radius
+                {
+                    crossings.push((t, part));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+        crossings
+    }
+
+    fn hit_part(&self, ray: &Ray, t: f64, part: Part) -> Option<HitRecord> {
+        let (o, d) = self.frame.local_ray(ray);
+        let p = o + t * d;
+        let radii = (0., self.radius);
+        let mut rec = match part {
+            Part::Side => {
+                let phi = azimuth(p);
+                let around = 2. * PI * Vec3(-p.y(), p.x(), 0.);
+                self.frame.hit_record(
+                    ray,
+                    t,
+                    p,
+                    Vec3(p.x(), p.y(), 0.) / self.radius,
+                    (phi / (2. * PI), p.z() / self.height),
+                    (around, Vec3(0., 0., self.height)),
+                    (around / self.radius, Vec3(0., 0., 0.)),
+                    &self.material,
+                )
+            }
+            Part::Base => disk_hit(&self.frame, ray, t, p, 0., radii, -1., &self.material)?,
+            Part::Top => disk_hit(
+                &self.frame,
+                ray,
+                t,
+                p,
+                self.height,
+                radii,
+                1.,
+                &self.material,
+            )?,
+        };
+        rec.object_id = self.id;
+        Some(rec)
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (t, part) = self
+            .crossings(ray)
+            .into_iter()
+            .find(|&(t, _)| ray_t.surrounds(t))?;
+        self.hit_part(ray, t, part)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn hits(&self, ray: &Ray, ray_t: &Interval) -> Vec<HitRecord> {
+        let mut crossings = self.crossings(ray);
+        crossings.dedup_by(|a, b| a.0 == b.0);
+        crossings
+            .into_iter()
+            .filter(|&(t, _)| ray_t.surrounds(t))
+            .filter_map(|(t, part)| self.hit_part(ray, t, part))
+            .collect()
+    }
+}
+
+/// Cone with a circular base around base, narrowing to a point at apex.
+/// It is open at the base unless capped. On the side, u goes around the
+/// axis and v from the base to the apex.
+pub struct Cone {
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f64, material: &Rc<dyn Material>) -> Self {
+        assert!(radius > 0., "a cone needs a positive radius");
+        let height = (apex - base).length();
+        assert!(height > 0., "a cone needs an apex off its base");
+        let frame = Frame::new(base, apex - base);
+        let bbox = AABB::from_boxes(
+            &circle_box(base, frame.axis(), radius),
+            &AABB::from_points(apex, apex),
+        );
+        Self {
+            frame,
+            height,
+            radius,
+            capped: false,
+            material: Rc::clone(material),
+            bbox: padded(bbox),
+            id: next_object_id(),
+        }
+    }
+
+    /// Closes the base with a disk.
+    pub fn with_cap(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+
+    /// Distances along ray, in increasing order, where it crosses the
+    /// surface, and the part crossed there.
+    fn crossings(&self, ray: &Ray) -> Vec<(f64, Part)> {
+        let (o, d) = self.frame.local_ray(ray);
+        let mut crossings = vec![];
+        // x² + y² = k² (h - z)², with the slope k of the radius.
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.z();
+        let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+        let b = 2. * (o.x() * d.x() + o.y() * d.y() + k2 * h * d.z());
+        let c = o.x() * o.x() + o.y() * o.y() - k2 * h * h;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            let roots = if t0 == t1 { vec![t0] } else { vec![t0, t1] };
+            for t in roots {
+                let z = o.z() + t * d.z();
+                if (0. ..=self.height).contains(&z) {
+                    crossings.push((t, Part::Side));
+                }
+            }
+        }
+        if self.capped
+            && let Some((t, p)) = cross_plane(o, d, 0.)
+            && p.x().hypot(p.y()) <= self.radius
+        {
+            crossings.push((t, Part::Base));
+        }
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+        crossings
+    }
+
+    fn hit_part(&self, ray: &Ray, t: f64, part: Part) -> Option<HitRecord> {
+        let (o, d) = self.frame.local_ray(ray);
+        let p = o + t * d;
+        let mut rec = match part {
+            Part::Side => {
+                let phi = azimuth(p);
+                let (sin, cos) = phi.sin_cos();
+                let k = self.radius / self.height;
+                let scale = 1. / (1. + k * k).sqrt();
+                let around = 2. * PI * Vec3(-p.y(), p.x(), 0.);
+                self.frame.hit_record(
+                    ray,
+                    t,
+                    p,
+                    scale * Vec3(cos, sin, k),
+                    (phi / (2. * PI), p.z() / self.height),
+                    (
+                        around,
+                        Vec3(-self.radius * cos, -self.radius * sin, self.height),
+                    ),
+                    (2. * PI * scale * Vec3(-sin, cos, 0.), Vec3(0., 0., 0.)),
+                    &self.material,
+                )
+            }
+            _ => disk_hit(
+                &self.frame,
+                ray,
+                t,
+                p,
+                0.,
+                (0., self.radius),
+                -1.,
+                &self.material,
+            )?,
+        };
+        rec.object_id = self.id;
+        Some(rec)
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let (t, part) = self
+            .crossings(ray)
+            .into_iter()
+            .find(|&(t, _)| ray_t.surrounds(t))?;
+        self.hit_part(ray, t, part)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn hits(&self, ray: &Ray, ray_t: &Interval) -> Vec<HitRecord> {
+        let mut crossings = self.crossings(ray);
+        crossings.dedup_by(|a, b| a.0 == b.0);
+        crossings
+            .into_iter()
+            .filter(|&(t, _)| ray_t.surrounds(t))
+            .filter_map(|(t, part)| self.hit_part(ray, t, part))
+            .collect()
+    }
+}
+
+/// Torus around center: a tube of minor_radius swept around a circle of
+/// major_radius normal to axis. u goes around the axis and v around the
+/// tube, starting on its outer equator.
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Rc<dyn Material>,
+    bbox: AABB,
+    id: usize,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: &Rc<dyn Material>,
+    ) -> Self {
+        assert!(
+            0. < minor_radius && minor_radius <= major_radius,
+            "a torus needs a tube thinner than its ring"
+        );
+        let frame = Frame::new(center, axis);
+        let a = frame.axis();
+        let extent = |a: f64| {
+            (major_radius + minor_radius) * (1. - a * a).max(0.).sqrt() + minor_radius * a.abs()
+        };
+        let e = Vec3(extent(a.x()), extent(a.y()), extent(a.z()));
+        Self {
+            frame,
+            major_radius,
+            minor_radius,
+            material: Rc::clone(material),
+            bbox: AABB::from_points(center - e, center + e),
+            id: next_object_id(),
+        }
+    }
+
+    /// Distances along ray, in increasing order, where it crosses the
+    /// surface.
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let (o, d) = self.frame.local_ray(ray);
+        let length = d.length();
+        let d = d / length;
+        let (big, small) = (self.major_radius, self.minor_radius);
+
+        // Start from where the ray enters the bounding sphere, so that
+        // distant rays keep the coefficients small.
+        let Some((enter, _)) =
+            solve_quadratic(1., 2. * o.dot(&d), o.dot(&o) - (big + small).powi(2))
+        else {
+            return vec![];
+        };
+        let start = enter.max(0.);
+        let o = o + start * d;
+
+        // (|p|² + R² - r²)² = 4R² (x² + y²) along p = o + t d.
+        let f = o.dot(&d);
+        let e = o.dot(&o) + big * big - small * small;
+        let four_r2 = 4. * big * big;
+        let roots = solve_quartic(
+            4. * f,
+            4. * f * f + 2. * e - four_r2 * (d.x() * d.x() + d.y() * d.y()),
+            4. * f * e - 2. * four_r2 * (o.x() * d.x() + o.y() * d.y()),
+            e * e - four_r2 * (o.x() * o.x() + o.y() * o.y()),
+        );
+        roots.into_iter().map(|t| (t + start) / length).collect()
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64) -> HitRecord {
+        let (o, d) = self.frame.local_ray(ray);
+        let p = o + t * d;
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let phi = azimuth(p);
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let rho = p.x().hypot(p.y());
+        let theta = p.z().atan2(rho - big);
+        let theta = if theta < 0. { theta + 2. * PI } else { theta };
+        let (sin_theta, cos_theta) = theta.sin_cos();
+
+        let ring = big + small * cos_theta;
+        let normal = Vec3(cos_theta * cos_phi, cos_theta * sin_phi, sin_theta);
+        let dpdu = 2. * PI * Vec3(-ring * sin_phi, ring * cos_phi, 0.);
+        let dpdv = 2. * PI * small * Vec3(-sin_theta * cos_phi, -sin_theta * sin_phi, cos_theta);
+        let dndu = 2. * PI * Vec3(-cos_theta * sin_phi, cos_theta * cos_phi, 0.);
+        let mut rec = self.frame.hit_record(
+            ray,
+            t,
+            p,
+            normal,
+            (phi / (2. * PI), theta / (2. * PI)),
+            (dpdu, dpdv),
+            (dndu, dpdv / small),
+            &self.material,
+        );
+        rec.object_id = self.id;
+        rec
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<HitRecord> {
+        let t = self
+            .crossings(ray)
+            .into_iter()
+            .find(|&t| ray_t.surrounds(t))?;
+        Some(self.hit_at(ray, t))
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn hits(&self, ray: &Ray, ray_t: &Interval) -> Vec<HitRecord> {
+        let mut crossings = self.crossings(ray);
+        crossings.dedup();
+        crossings
+            .into_iter()
+            .filter(|&t| ray_t.surrounds(t))
+            .map(|t| self.hit_at(ray, t))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn material() -> Rc<dyn Material> {
+        Rc::new(Lambertian::new(Vec3(0.5, 0.5, 0.5)))
+    }
+
+    fn ray(origin: Vec3, dir: Vec3) -> Ray {
+        Ray::new(origin, dir, 0.)
+    }
+
+    fn all() -> Interval {
+        Interval::new(0.001, f64::INFINITY)
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    /// Checks that rec lies in shape's box, that its derivatives are
+    /// tangent to the surface and that nearby texture coordinates map
+    /// nearby.
+    fn check_hit(shape: &dyn Hittable, rec: &HitRecord) {
+        let b = shape.bounding_box();
+        let p = rec.point;
+        assert!(b.x.contains(p.x()) && b.y.contains(p.y()) && b.z.contains(p.z()));
+        assert!((rec.normal.length() - 1.).abs() < 1e-9);
+        assert!(rec.dpdu.dot(&rec.normal).abs() < 1e-9 * rec.dpdu.length().max(1.));
+        assert!(rec.dpdv.dot(&rec.normal).abs() < 1e-9 * rec.dpdv.length().max(1.));
+
+        // A step along each derivative lands on the surface where the
+        // texture coordinate has moved by that step, up to the seam.
+        let wrap = |d: f64| d - d.round();
+        for (dpd, du, dv) in [(rec.dpdu, 1., 0.), (rec.dpdv, 0., 1.)] {
+            if dpd.length() < 1e-9 {
+                continue;
+            }
+            let h = 1e-4 / dpd.length();
+            let q = p + h * dpd;
+            let near = shape
+                .hit(&ray(q + 1e-2 * rec.normal, -rec.normal), &all())
+                .unwrap();
+            assert!((near.point - q).length() < 1e-6, "{:?}", near.point);
+            assert!((wrap(near.u - rec.u) - h * du).abs() < 1e-2 * h);
+            assert!((wrap(near.v - rec.v) - h * dv).abs() < 1e-2 * h);
+        }
+    }
+
+    #[test]
+    fn planes_work() {
+        let plane = Plane::new(Vec3(0., 1., 0.), Vec3(0., 1., 0.), &material());
+        let rec = plane
+            .hit(&ray(Vec3(2., 3., -1.), Vec3(0., -1., 0.)), &all())
+            .unwrap();
+        check_hit(&plane, &rec);
+        assert!(close(rec.point, Vec3(2., 1., -1.)) && rec.t == 2.);
+        assert!(rec.front_face && close(rec.normal, Vec3(0., 1., 0.)));
+        // Texture coordinates are distances in the plane.
+        let other = plane
+            .hit(&ray(Vec3(3., 3., -1.), Vec3(0., -1., 0.)), &all())
+            .unwrap();
+        assert!(((other.u - rec.u).powi(2) + (other.v - rec.v).powi(2) - 1.).abs() < 1e-9);
+
+        // Seen from below it faces down; parallel and receding rays miss.
+        let rec = plane
+            .hit(&ray(Vec3(0., 0., 0.), Vec3(1., 1., 0.)), &all())
+            .unwrap();
+        assert!(!rec.front_face && close(rec.normal, Vec3(0., -1., 0.)));
+        assert!(
+            plane
+                .hit(&ray(Vec3(0., 1., 0.), Vec3(1., 0., 0.)), &all())
+                .is_none()
+        );
+        assert!(
+            plane
+                .hit(&ray(Vec3(0., 2., 0.), Vec3(0., 1., 0.)), &all())
+                .is_none()
+        );
+
+        // Tilted planes fill space; planes along the axes are thin.
+        let b = plane.bounding_box();
+        assert!(b.y.size() < 1e-3 && b.x.size() == f64::INFINITY);
+        let tilted = Plane::new(Vec3(0., 0., 0.), Vec3(1., 1., 0.), &material());
+        assert_eq!(tilted.bounding_box().y.size(), f64::INFINITY);
+    }
+
+    #[test]
+    fn disks_work() {
+        let disk =
+            Disk::new(Vec3(0., 0., 0.), Vec3(0., 0., 1.), 2., &material()).with_inner_radius(1.);
+        let down = Vec3(0., 0., -1.);
+        let rec = disk.hit(&ray(Vec3(1.5, 0., 1.), down), &all()).unwrap();
+        check_hit(&disk, &rec);
+        assert!(rec.front_face && close(rec.normal, Vec3(0., 0., 1.)));
+        assert!((rec.v - 0.5).abs() < 1e-12);
+        // Quarter of the way around the center.
+        let rec = disk.hit(&ray(Vec3(0., 1.5, 1.), down), &all()).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-12);
+
+        // Holes, the outside and the edge-on plane miss.
+        assert!(disk.hit(&ray(Vec3(0.5, 0., 1.), down), &all()).is_none());
+        assert!(disk.hit(&ray(Vec3(2.5, 0., 1.), down), &all()).is_none());
+        let edge_on = ray(Vec3(-3., 1.5, 0.), Vec3(1., 0., 0.));
+        assert!(disk.hit(&edge_on, &all()).is_none());
+
+        // Its box is flat along its normal, and round in its plane.
+        let tilted = Disk::new(Vec3(1., 1., 1.), Vec3(1., 0., 1.), 1., &material());
+        let b = tilted.bounding_box();
+        assert!((b.y.size() - 2.).abs() < 1e-3);
+        assert!((b.x.size() - 2f64.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cylinders_work() {
+        let tube = Cylinder::new(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 1., &material());
+        let capped = Cylinder::new(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 1., &material()).with_caps();
+        let b = tube.bounding_box();
+        assert!((b.x.size() - 2.).abs() < 1e-3 && (b.y.size() - 2.).abs() < 1e-3);
+
+        // Side hits face out, halfway up.
+        let rec = tube
+            .hit(&ray(Vec3(3., 1., 0.), Vec3(-1., 0., 0.)), &all())
+            .unwrap();
+        check_hit(&tube, &rec);
+        assert!(close(rec.point, Vec3(1., 1., 0.)) && rec.t == 2.);
+        assert!(rec.front_face && close(rec.normal, Vec3(1., 0., 0.)));
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        // From inside, rays hit the side from its back, or a cap.
+        let inside = Vec3(0., 1., 0.);
+        let rec = tube.hit(&ray(inside, Vec3(0., 0., 1.)), &all()).unwrap();
+        check_hit(&tube, &rec);
+        assert!(!rec.front_face && close(rec.normal, Vec3(0., 0., -1.)));
+        assert!(tube.hit(&ray(inside, Vec3(0., 1., 0.)), &all()).is_none());
+        let rec = capped.hit(&ray(inside, Vec3(0., 1., 0.)), &all()).unwrap();
+        check_hit(&capped, &rec);
+        assert!(close(rec.point, Vec3(0., 2., 0.)));
+        assert!(!rec.front_face && close(rec.normal, Vec3(0., -1., 0.)));
+
+        // Caps face out of the ends, and the open tube is seen through.
+        let down = ray(Vec3(0.5, 3., 0.), Vec3(0., -1., 0.));
+        let rec = capped.hit(&down, &all()).unwrap();
+        assert!(rec.front_face && close(rec.normal, Vec3(0., 1., 0.)));
+        assert!(tube.hit(&down, &all()).is_none());
+
+        // A ray grazing the side touches it at one point.
+        let tangent = ray(Vec3(1., 1., -3.), Vec3(0., 0., 1.));
+        let rec = tube.hit(&tangent, &all()).unwrap();
+        assert!((rec.point - Vec3(1., 1., 0.)).length() < 1e-6);
+        assert!(
+            tube.hit(&ray(Vec3(1.001, 1., -3.), Vec3(0., 0., 1.)), &all())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn cones_work() {
+        let cone = Cone::new(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 1., &material()).with_cap();
+        let b = cone.bounding_box();
+        assert!((b.x.size() - 2.).abs() < 1e-3 && (b.y.size() - 2.).abs() < 1e-3);
+
+        // Halfway up the radius is halved, and the normal tilts upward.
+        let rec = cone
+            .hit(&ray(Vec3(3., 1., 0.), Vec3(-1., 0., 0.)), &all())
+            .unwrap();
+        check_hit(&cone, &rec);
+        assert!(close(rec.point, Vec3(0.5, 1., 0.)));
+        assert!(rec.front_face && close(rec.normal, Vec3(2., 1., 0.).unit()));
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        // The base cap faces down; rays from inside hit the side's back.
+        let rec = cone
+            .hit(&ray(Vec3(0.2, -1., 0.), Vec3(0., 1., 0.)), &all())
+            .unwrap();
+        check_hit(&cone, &rec);
+        assert!(rec.front_face && close(rec.normal, Vec3(0., -1., 0.)));
+        let rec = cone
+            .hit(&ray(Vec3(0., 0.5, 0.), Vec3(1., 0., 0.)), &all())
+            .unwrap();
+        assert!(!rec.front_face && close(rec.point, Vec3(0.75, 0.5, 0.)));
+
+        // The mirrored cone above the apex is not part of it, and a ray
+        // along the side first touches it at the rim of the base.
+        assert!(
+            cone.hit(&ray(Vec3(3., 3., 0.), Vec3(-1., 0., 0.)), &all())
+                .is_none()
+        );
+        let along = ray(Vec3(2., -2., 0.), Vec3(-1., 2., 0.));
+        let rec = cone.hit(&along, &all()).unwrap();
+        assert!((rec.point - Vec3(1., 0., 0.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn tori_work() {
+        let torus = Torus::new(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 2., 0.5, &material());
+        let b = torus.bounding_box();
+        assert!((b.x.size() - 5.).abs() < 1e-9 && (b.y.size() - 1.).abs() < 1e-9);
+
+        // Along the equator a ray crosses the tube twice on each side.
+        let across = ray(Vec3(-10., 0., 0.), Vec3(1., 0., 0.));
+        let crossings = torus.crossings(&across);
+        assert_eq!(crossings.len(), 4);
+        for (t, expected) in crossings.iter().zip([7.5, 8.5, 11.5, 12.5]) {
+            assert!((t - expected).abs() < 1e-9, "{:?}", crossings);
+        }
+        let rec = torus.hit(&across, &all()).unwrap();
+        check_hit(&torus, &rec);
+        assert!(rec.front_face && close(rec.normal, Vec3(-1., 0., 0.)));
+        // The top of the tube faces up, a quarter turn around it.
+        let rec = torus
+            .hit(&ray(Vec3(2., 5., 0.), Vec3(0., -1., 0.)), &all())
+            .unwrap();
+        check_hit(&torus, &rec);
+        assert!(close(rec.point, Vec3(2., 0.5, 0.)) && close(rec.normal, Vec3(0., 1., 0.)));
+        assert!((rec.v - 0.25).abs() < 1e-9);
+
+        // Through the hole it misses; from inside the tube it hits its back.
+        assert!(
+            torus
+                .hit(&ray(Vec3(0., 5., 0.), Vec3(0., -1., 0.)), &all())
+                .is_none()
+        );
+        let rec = torus
+            .hit(&ray(Vec3(0., 0., 2.), Vec3(0., 1., 0.)), &all())
+            .unwrap();
+        check_hit(&torus, &rec);
+        assert!(!rec.front_face && close(rec.point, Vec3(0., 0.5, 2.)));
+
+        // Rays grazing the top of the tube touch it at one point, if at all,
+        // and a distant ray keeps its precision.
+        let grazing = ray(Vec3(-10., 0.5, 0.), Vec3(1., 0., 0.));
+        for t in torus.crossings(&grazing) {
+            let p = grazing.at(t);
+            assert!((p.x().abs() - 2.).abs() < 1e-3, "{:?}", p);
+        }
+        let far = ray(Vec3(-1e4, 0., 0.), Vec3(1., 0., 0.));
+        let t = torus.hit(&far, &all()).unwrap().t;
+        assert!((t - (1e4 - 2.5)).abs() < 1e-8);
+    }
+}
@@ -0,0 +1,359 @@
+//! Analytic daylight: the clear sky model of Preetham, Shirley and Smits,
+//! "A Practical Analytic Model for Daylight" (SIGGRAPH 1999), and the sun
+//! seen through the same atmosphere.
+//!
+//! Radiance is given in the model's photometric units scaled by a factor
+//! into the scene's: a color of luminance 1 is 1 kcd/m², and the sun
+//! lights a surface facing it with up to about 100 klux, or 100 units.
+
+use std::f64::consts::PI;
+
+use crate::{
+    color::Color,
+    environment::{Environment, EnvironmentMap},
+    light::{DirectionalLight, Light, LightSample},
+    util::degrees_to_radians,
+    vec3::Vec3,
+};
+
+/// Illuminance from the sun outside the atmosphere, in klux.
+const SOLAR_ILLUMINANCE: f64 = 128.;
+
+/// Angular radius of the sun's disk in degrees.
+const SUN_ANGULAR_RADIUS: f64 = 0.2665;
+
+/// Coefficients A to E of the Perez sky luminance distribution
+/// (1 + A e^(B / cos θ)) (1 + C e^(D γ) + E cos² γ), for a view at θ from
+/// the zenith and γ from the sun.
+#[derive(Clone, Copy, Debug)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    /// Coefficients linear in turbidity, given as (slope, intercept) pairs.
+    fn new(turbidity: f64, fit: [(f64, f64); 5]) -> Self {
+        Self(fit.map(|(slope, intercept)| slope * turbidity + intercept))
+    }
+
+    fn eval(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1. + a * (b / cos_theta.max(0.01)).exp())
+            * (1. + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Clear sky whose color and brightness follow the sun's position and the
+/// haziness of the air, over a diffuse ground lit by both. The sun itself
+/// is not part of the sky; add `sun_light` to the scene for it.
+///
+/// Escaping rays see the sky directly, and surfaces sample it uniformly
+/// over the sphere. Baking it into an environment map samples it in
+/// proportion to its brightness instead.
+pub struct PhysicalSky {
+    sun: Vec3,            // Unit direction toward the sun
+    turbidity: f64,       // Haziness, 2 for a very clear sky to 10 for a hazy one
+    ground_albedo: Color, // Diffuse reflectance of the ground
+    scale: f64,           // Scene radiance units per kcd/m²
+    perez: [Perez; 3],    // Distributions of luminance Y and chromaticities x and y
+    zenith: [f64; 3],     // Y, x and y at the zenith
+    ground: Color,        // Radiance of the ground in kcd/m²
+}
+
+impl PhysicalSky {
+    /// Sky with the sun elevation degrees above the horizon, at azimuth
+    /// degrees around the vertical from +x toward -z, and the given
+    /// turbidity. The ground is a mid gray.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64) -> Self {
+        assert!((-90. ..=90.).contains(&elevation));
+        assert!(
+            (1.7..=10.).contains(&turbidity),
+            "turbidity outside the model's range of 1.7 to 10"
+        );
+        let (elevation, azimuth) = (degrees_to_radians(elevation), degrees_to_radians(azimuth));
+        let sun = Vec3(
+            elevation.cos() * azimuth.cos(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.sin(),
+        );
+
+        let t = turbidity;
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    (0.1787, -1.4630),
+                    (-0.3554, 0.4275),
+                    (-0.0227, 5.3251),
+                    (0.1206, -2.5771),
+                    (-0.0670, 0.3703),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0193, -0.2592),
+                    (-0.0665, 0.0008),
+                    (-0.0004, 0.2125),
+                    (-0.0641, -0.8989),
+                    (-0.0033, 0.0452),
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    (-0.0167, -0.2608),
+                    (-0.0950, 0.0092),
+                    (-0.0079, 0.2102),
+                    (-0.0441, -1.6537),
+                    (-0.0109, 0.0529),
+                ],
+            ),
+        ];
+
+        // Zenith values fitted to the sun's zenith angle, which stops at
+        // the horizon for a sun below it.
+        let theta_s = PI / 2. - elevation.max(0.);
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |k: [f64; 4]| ((k[0] * theta_s + k[1]) * theta_s + k[2]) * theta_s + k[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_chroma_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut sky = Self {
+            sun,
+            turbidity,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            scale: 1.,
+            perez,
+            zenith: [zenith_y.max(0.), zenith_x, zenith_chroma_y],
+            ground: Color::new(0., 0., 0.),
+        };
+        sky.ground = sky.ground_radiance();
+        sky
+    }
+
+    /// Sets the albedo of the diffuse ground below the horizon.
+    pub fn with_ground_albedo(mut self, albedo: Color) -> Self {
+        self.ground_albedo = albedo;
+        self.ground = self.ground_radiance();
+        self
+    }
+
+    /// Sets the factor converting the model's kcd/m² to scene radiance.
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// Unit direction toward the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun
+    }
+
+    /// Sky radiance in kcd/m² from a direction above the horizon.
+    fn sky_radiance(&self, dir: Vec3) -> Color {
+        let cos_theta = dir.y();
+        let gamma = dir.dot(&self.sun).clamp(-1., 1.).acos();
+        let theta_s = self.sun.y().clamp(0., 1.).acos();
+        let [big_y, x, y] = [0, 1, 2].map(|k| {
+            self.zenith[k] * self.perez[k].eval(cos_theta, gamma) / self.perez[k].eval(1., theta_s)
+        });
+        xyy_to_rgb(x, y, big_y)
+    }
+
+    /// Radiance of the diffuse ground lit by the sky and the sun, in
+    /// kcd/m². The sky's illuminance on it is integrated numerically.
+    fn ground_radiance(&self) -> Color {
+        let (n_theta, n_phi) = (64, 128);
+        let (d_theta, d_phi) = (0.5 * PI / n_theta as f64, 2. * PI / n_phi as f64);
+        let mut illuminance = Color::new(0., 0., 0.);
+        for j in 0..n_theta {
+            let theta = (j as f64 + 0.5) * d_theta;
+            for i in 0..n_phi {
+                let phi = (i as f64 + 0.5) * d_phi;
+                let dir = Vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let solid_angle = theta.sin() * d_theta * d_phi;
+                illuminance += theta.cos() * solid_angle * self.sky_radiance(dir);
+            }
+        }
+        illuminance += self.sun.y().max(0.) * self.sun_irradiance();
+        self.ground_albedo * illuminance / PI
+    }
+
+    /// Illuminance in klux from the sun on a surface facing it, per
+    /// channel, after Rayleigh and aerosol scattering along its path
+    /// through the atmosphere. Nothing if it has set.
+    fn sun_irradiance(&self) -> Color {
+        if self.sun.y() <= 0. {
+            return Color::new(0., 0., 0.);
+        }
+        // Relative optical mass of the air, from Kasten's fit.
+        let zenith_degrees = self.sun.y().acos().to_degrees();
+        let mass = 1. / (self.sun.y() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        // Representative wavelengths of the channels, in μm.
+        let transmittance = |lambda: f64| {
+            let rayleigh = -0.008735 * lambda.powf(-4.08) * mass;
+            let aerosol = -beta * lambda.powf(-1.3) * mass;
+            (rayleigh + aerosol).exp()
+        };
+        SOLAR_ILLUMINANCE
+            * Color::new(
+                transmittance(0.65),
+                transmittance(0.57),
+                transmittance(0.475),
+            )
+    }
+
+    /// The sun as a light, a disk of its real size in the direction of the
+    /// sky's sun with the color the atmosphere leaves it. None while it is
+    /// below the horizon.
+    pub fn sun_light(&self) -> Option<DirectionalLight> {
+        (self.sun.y() > 0.).then(|| {
+            DirectionalLight::new(-self.sun, self.scale * self.sun_irradiance())
+                .with_angular_radius(SUN_ANGULAR_RADIUS)
+        })
+    }
+
+    /// Environment map of the sky, width by height pixels, for sampling it
+    /// in proportion to its brightness.
+    pub fn bake(&self, width: u32, height: u32) -> EnvironmentMap {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for j in 0..height {
+            for i in 0..width {
+                let s = (i as f64 + 0.5) / width as f64;
+                let t = (j as f64 + 0.5) / height as f64;
+                pixels.push(self.radiance(EnvironmentMap::direction(s, t)));
+            }
+        }
+        EnvironmentMap::new(width, height, pixels)
+    }
+}
+
+/// Linear sRGB color with luminance Y and chromaticity (x, y).
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Color {
+    if y <= 0. {
+        return Color::new(0., 0., 0.);
+    }
+    let big_x = x * big_y / y;
+    let big_z = (1. - x - y) * big_y / y;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.),
+    )
+}
+
+impl Light for PhysicalSky {
+    fn sample(&self, _: Vec3) -> Option<LightSample> {
+        let wi = Vec3::random_unit_vector();
+        let pdf = 1. / (4. * PI);
+        Some(LightSample {
+            wi,
+            distance: f64::INFINITY,
+            radiance: self.radiance(wi) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn pdf(&self, _: Vec3, _: Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+
+    /// Light from every direction falling on a disk of the scene's size,
+    /// from a coarse bake of the sky.
+    fn power(&self, scene_radius: f64) -> Color {
+        self.bake(64, 32).power(scene_radius)
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn radiance(&self, dir: Vec3) -> Color {
+        let dir = dir.unit();
+        let radiance = match dir.y() > 0. {
+            true => self.sky_radiance(dir),
+            false => self.ground,
+        };
+        self.scale * radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sky_follows_the_sun() {
+        let sky = PhysicalSky::new(30., 90., 3.);
+        assert!((sky.sun_direction() - Vec3(0., 0.5, -0.75f64.sqrt())).length() < 1e-12);
+
+        // The zenith has the fitted luminance, here a few kcd/m², and is
+        // blue.
+        let zenith = sky.radiance(Vec3(0., 1., 0.));
+        assert!((zenith.luminance() - sky.zenith[0]).abs() < 1e-3 * sky.zenith[0]);
+        assert!((2. ..10.).contains(&zenith.luminance()));
+        assert!(zenith.z() > zenith.x());
+
+        // It is brighter around the sun than away from it.
+        let near_sun = (sky.sun_direction() + Vec3(0., 0.1, 0.)).unit();
+        let away = Vec3(0., 0.5, 0.75f64.sqrt());
+        assert!(sky.radiance(near_sun).luminance() > 2. * sky.radiance(away).luminance());
+
+        // Scaling scales everything.
+        let scaled = PhysicalSky::new(30., 90., 3.).with_scale(0.5);
+        assert!((scaled.radiance(away) - 0.5 * sky.radiance(away)).length() < 1e-12);
+    }
+
+    #[test]
+    fn sun_reddens_toward_the_horizon() {
+        let high = PhysicalSky::new(60., 0., 3.).sun_irradiance();
+        let low = PhysicalSky::new(5., 0., 3.).sun_irradiance();
+        let hazy = PhysicalSky::new(60., 0., 8.).sun_irradiance();
+        assert!(high.luminance() < SOLAR_ILLUMINANCE);
+        assert!(low.luminance() < high.luminance());
+        assert!(hazy.luminance() < high.luminance());
+        assert!(low.z() / low.x() < high.z() / high.x());
+
+        let sky = PhysicalSky::new(60., 0., 3.).with_scale(0.1);
+        let sun = sky.sun_light().unwrap().sample(Vec3(0., 0., 0.)).unwrap();
+        assert!(sun.wi.dot(&sky.sun_direction()) > degrees_to_radians(0.27).cos());
+        assert!(PhysicalSky::new(-5., 0., 3.).sun_light().is_none());
+    }
+
+    #[test]
+    fn ground_reflects_sky_and_sun() {
+        let sky = PhysicalSky::new(45., 0., 3.);
+        let dark = sky.radiance(Vec3(0., -1., 0.));
+        let sky = sky.with_ground_albedo(Color::new(0.6, 0.6, 0.6));
+        let light = sky.radiance(Vec3(0.3, -0.5, 0.1));
+        assert!((light - 2. * dark).length() < 1e-9 * light.length());
+        // Mostly lit by the sun, dimmed by albedo and cosine.
+        let sun = (0.5f64.sqrt() * sky.sun_irradiance()).luminance();
+        assert!(light.luminance() > 0.6 * sun / PI);
+        assert!(light.luminance() < 0.6 * 2. * sun / PI);
+    }
+
+    #[test]
+    fn baked_sky_matches_model() {
+        let sky = PhysicalSky::new(20., 45., 4.);
+        let map = sky.bake(64, 32);
+        // At pixel centers.
+        for (i, j) in [(6, 3), (38, 9), (57, 25)] {
+            let dir = EnvironmentMap::direction((i as f64 + 0.5) / 64., (j as f64 + 0.5) / 32.);
+            assert!((map.radiance(dir) - sky.radiance(dir)).length() < 1e-9);
+        }
+        // The baked map samples bright regions more often than the sky's
+        // uniform sampling.
+        let near_sun = (sky.sun_direction() + Vec3(0., 0.05, 0.)).unit();
+        let origin = Vec3(0., 0., 0.);
+        assert!(map.pdf(origin, near_sun) > 2. * sky.pdf(origin, near_sun));
+    }
+}
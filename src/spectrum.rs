@@ -0,0 +1,388 @@
+//! Spectral rendering support: wavelength sampling, upsampling of RGB
+//! colors to spectra and conversion of spectral samples back to RGB.
+//!
+//! Paths in spectral mode carry a hero wavelength and three more spaced
+//! evenly over the visible range (Wilkie et al., "Hero Wavelength Spectral
+//! Sampling", 2014). RGB reflectances and emissions are upsampled to
+//! smooth spectra from a partition of unity of three basis functions, so
+//! that white stays flat and every reflectance stays within [0, 1].
+//! Spectra are converted to XYZ with an analytic fit of the CIE 1931
+//! matching functions and from there to linear sRGB, as are the spectra of
+//! black bodies that give lights the color of their temperature.
+
+use std::sync::OnceLock;
+
+use crate::{color::Color, vec3::Vec3};
+
+/// Shortest wavelength sampled, in nanometres.
+pub const LAMBDA_MIN: f64 = 380.;
+
+/// Longest wavelength sampled, in nanometres.
+pub const LAMBDA_MAX: f64 = 780.;
+
+/// Number of wavelengths carried by a path.
+pub const SAMPLES: usize = 4;
+
+/// Piecewise Gaussian with different widths left and right of its mean.
+fn lobe(x: f64, mu: f64, sigma_left: f64, sigma_right: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma_left } else { sigma_right };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 2° color matching functions at lambda in nanometres, in the
+/// multi-lobe fit of Wyman, Sloan and Shirley, "Simple Analytic
+/// Approximations to the CIE XYZ Color Matching Functions" (2013).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    Vec3(x, y, z)
+}
+
+/// Converts CIE XYZ to linear sRGB with a D65 white point.
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    let Vec3(x, y, z) = xyz;
+    Color::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+/// Lumens per watt of light at 555 nm, where the eye is most sensitive.
+/// Photometric quantities are radiometric ones weighted by luminance
+/// and scaled by it.
+pub const LUMINOUS_EFFICACY: f64 = 683.;
+
+/// Spectral radiance of a black body at temperature kelvin, by Planck's
+/// law, in W/(m² sr nm) at lambda in nanometres.
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    const H: f64 = 6.62607015e-34; // Planck constant, J s
+    const C: f64 = 299792458.; // Speed of light, m/s
+    const K: f64 = 1.380649e-23; // Boltzmann constant, J/K
+    let l = lambda * 1e-9;
+    let radiance = 2. * H * C * C / (l.powi(5) * ((H * C / (l * K * temperature)).exp_m1()));
+    radiance * 1e-9
+}
+
+/// CIE XYZ of the radiance of a black body at temperature kelvin, with
+/// Y in W/(m² sr) weighted by luminous efficiency. The matching functions
+/// vanish outside the 360 to 830 nm summed over.
+fn blackbody_xyz(temperature: f64) -> Vec3 {
+    (360..=830)
+        .map(|lambda| planck(lambda as f64, temperature) * cie_xyz(lambda as f64))
+        .fold(Vec3(0., 0., 0.), |sum, xyz| sum + xyz)
+}
+
+/// Linear sRGB color of a black body at temperature kelvin, with
+/// luminance 1, for lights specified by their color temperature. Below
+/// about 1900 K the color lies outside the sRGB gamut and is clipped.
+pub fn blackbody(temperature: f64) -> Color {
+    assert!(temperature > 0., "black body temperature must be positive");
+    let xyz = blackbody_xyz(temperature);
+    let rgb = xyz_to_linear_srgb(xyz / xyz.y());
+    let rgb = Color::new(rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.));
+    rgb / rgb.luminance()
+}
+
+/// Lumens per watt of the total power a black body at temperature kelvin
+/// radiates, of which most goes to infrared at low temperatures.
+pub fn blackbody_efficacy(temperature: f64) -> f64 {
+    // Stefan–Boltzmann law for the radiance over all wavelengths.
+    const SIGMA: f64 = 5.670374419e-8;
+    let radiance = SIGMA * temperature.powi(4) / std::f64::consts::PI;
+    LUMINOUS_EFFICACY * blackbody_xyz(temperature).y() / radiance
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1. / (1. + (-x).exp())
+}
+
+/// Values of the red, green and blue basis spectra at lambda. They sum
+/// to one everywhere.
+pub fn rgb_basis(lambda: f64) -> Vec3 {
+    let blue_green = sigmoid((lambda - 490.) / 10.);
+    let green_red = sigmoid((lambda - 590.) / 10.);
+    Vec3(green_red, blue_green - green_red, 1. - blue_green)
+}
+
+/// Value at lambda of the spectrum upsampled from an RGB color.
+pub fn upsample(rgb: Color, lambda: f64) -> f64 {
+    rgb.dot(&rgb_basis(lambda))
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn mul(m: &Matrix, v: Vec3) -> Vec3 {
+    let row = |r: &[f64; 3]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+    Vec3(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+fn invert(m: &Matrix) -> Matrix {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum::<f64>();
+    let mut inverse = [[0.; 3]; 3];
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = cofactor(c, r) / det;
+        }
+    }
+    inverse
+}
+
+/// Correction applied after the XYZ to sRGB conversion, so that spectra
+/// upsampled from an RGB color convert back to that color.
+fn basis_correction() -> &'static Matrix {
+    static CORRECTION: OnceLock<Matrix> = OnceLock::new();
+    CORRECTION.get_or_init(|| {
+        // sRGB of each basis spectrum, as the columns of a matrix.
+        let steps = 4000;
+        let mut columns = [Vec3(0., 0., 0.); 3];
+        for k in 0..steps {
+            let lambda = LAMBDA_MIN + (k as f64 + 0.5) / steps as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            let rgb = xyz_to_linear_srgb(cie_xyz(lambda)) / steps as f64;
+            let basis = rgb_basis(lambda);
+            columns[0] += basis.x() * rgb;
+            columns[1] += basis.y() * rgb;
+            columns[2] += basis.z() * rgb;
+        }
+        let m = [
+            [columns[0].x(), columns[1].x(), columns[2].x()],
+            [columns[0].y(), columns[1].y(), columns[2].y()],
+            [columns[0].z(), columns[1].z(), columns[2].z()],
+        ];
+        invert(&m)
+    })
+}
+
+/// Wavelengths carried by one path, in nanometres. The first is the hero
+/// wavelength; the others follow it at equal spacing, wrapping around the
+/// visible range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledWavelengths {
+    lambda: [f64; SAMPLES],
+}
+
+impl SampledWavelengths {
+    pub fn from_hero(hero: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let lambda = std::array::from_fn(|k| {
+            LAMBDA_MIN + (hero - LAMBDA_MIN + k as f64 * range / SAMPLES as f64) % range
+        });
+        Self { lambda }
+    }
+
+    /// Picks a uniformly distributed hero wavelength.
+    pub fn sample_hero() -> f64 {
+        LAMBDA_MIN + rand::random::<f64>() * (LAMBDA_MAX - LAMBDA_MIN)
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self) -> &[f64; SAMPLES] {
+        &self.lambda
+    }
+
+    /// Converts radiance values at the wavelengths to linear sRGB. The
+    /// estimate is unbiased for uniformly distributed hero wavelengths.
+    pub fn to_rgb(&self, values: &[f64; SAMPLES]) -> Color {
+        let mut xyz = Vec3(0., 0., 0.);
+        for (&lambda, &value) in self.lambda.iter().zip(values) {
+            xyz += value * cie_xyz(lambda);
+        }
+        let rgb = xyz_to_linear_srgb(xyz / SAMPLES as f64);
+        mul(basis_correction(), rgb)
+    }
+}
+
+/// Product of the attenuations along a path so far: RGB, or values at
+/// the path's wavelengths in spectral mode.
+#[derive(Clone, Copy, Debug)]
+pub enum PathThroughput {
+    Rgb(Color),
+    Spectral(SampledWavelengths, [f64; SAMPLES]),
+}
+
+impl PathThroughput {
+    /// Unit throughput for a path of the given hero wavelength, or an RGB
+    /// path if there is none.
+    pub fn new(hero: Option<f64>) -> Self {
+        match hero {
+            Some(hero) => Self::Spectral(SampledWavelengths::from_hero(hero), [1.; SAMPLES]),
+            None => Self::Rgb(Color::new(1., 1., 1.)),
+        }
+    }
+
+    /// Multiplies by an RGB attenuation, upsampled in spectral mode.
+    pub fn attenuate(&mut self, attenuation: Color) {
+        match self {
+            Self::Rgb(throughput) => *throughput *= attenuation,
+            Self::Spectral(wavelengths, values) => {
+                for (value, &lambda) in values.iter_mut().zip(wavelengths.lambda()) {
+                    *value *= upsample(attenuation, lambda);
+                }
+            }
+        }
+    }
+
+    /// Drops all but the hero wavelength, after an interaction that sends
+    /// each wavelength a different way, such as dispersive refraction.
+    pub fn terminate_secondary(&mut self) {
+        if let Self::Spectral(_, values) = self {
+            values[0] *= SAMPLES as f64;
+            values[1..].fill(0.);
+        }
+    }
+
+    /// RGB radiance reaching the start of the path from an emitter of the
+    /// given RGB radiance at its end.
+    pub fn radiance(&self, emitted: Color) -> Color {
+        match self {
+            Self::Rgb(throughput) => *throughput * emitted,
+            Self::Spectral(wavelengths, values) => {
+                let mut radiance = *values;
+                for (value, &lambda) in radiance.iter_mut().zip(wavelengths.lambda()) {
+                    *value *= upsample(emitted, lambda);
+                }
+                wavelengths.to_rgb(&radiance)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cie_xyz_works() {
+        // Luminous efficiency peaks at 555 nm; the fit is within a few
+        // percent of the tabulated functions.
+        assert!((cie_xyz(555.).y() - 1.).abs() < 0.01);
+        assert!((cie_xyz(600.).x() - 1.0622).abs() < 0.03);
+        assert!((cie_xyz(450.).z() - 1.7471).abs() < 0.05);
+        assert!(cie_xyz(780.).length() < 1e-3);
+    }
+
+    #[test]
+    fn planck_works() {
+        // Radiance peaks at Wien's displacement b / T and integrates to
+        // the Stefan–Boltzmann law.
+        let temperature = 5000.;
+        let peak = (1..3000)
+            .map(|lambda| (lambda, planck(lambda as f64, temperature)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0;
+        assert!((peak as f64 - 2.897772e6 / temperature).abs() <= 1.);
+        let total = (1..100_000)
+            .map(|lambda| planck(lambda as f64, temperature))
+            .sum::<f64>();
+        let expected = 5.670374419e-8 * temperature.powi(4) / std::f64::consts::PI;
+        assert!((total - expected).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn blackbody_matches_known_values() {
+        // Chromaticities of CIE illuminant A and of the Planckian locus at
+        // 6500 K.
+        let xy = |temperature: f64| {
+            let xyz = blackbody_xyz(temperature);
+            let sum = xyz.x() + xyz.y() + xyz.z();
+            (xyz.x() / sum, xyz.y() / sum)
+        };
+        let (x, y) = xy(2856.);
+        assert!((x - 0.4476).abs() < 3e-3 && (y - 0.4074).abs() < 3e-3);
+        let (x, y) = xy(6500.);
+        assert!((x - 0.3135).abs() < 3e-3 && (y - 0.3236).abs() < 3e-3);
+
+        // Luminous efficacies of ideal black bodies.
+        assert!((blackbody_efficacy(4000.) - 54.7).abs() < 1.);
+        assert!((blackbody_efficacy(7000.) - 95.).abs() < 1.);
+
+        // Colors go from red through white to blue, at luminance 1.
+        for temperature in [1000., 2700., 6500., 12000.] {
+            let color = blackbody(temperature);
+            assert!((color.luminance() - 1.).abs() < 1e-12);
+            assert!(color.x() >= 0. && color.y() >= 0. && color.z() >= 0.);
+        }
+        let warm = blackbody(2700.);
+        assert!(warm.x() > warm.y() && warm.y() > warm.z());
+        assert!((blackbody(6500.) - Color::new(1., 1., 1.)).length() < 0.07);
+        let cold = blackbody(12000.);
+        assert!(cold.z() > cold.y() && cold.y() > cold.x());
+    }
+
+    #[test]
+    fn basis_is_a_partition_of_unity() {
+        for k in 0..=400 {
+            let basis = rgb_basis(LAMBDA_MIN + k as f64);
+            assert!((basis.x() + basis.y() + basis.z() - 1.).abs() < 1e-12);
+            for value in [basis.x(), basis.y(), basis.z()] {
+                assert!((0. ..=1.).contains(&value));
+            }
+        }
+        let gray = Color::new(0.3, 0.3, 0.3);
+        assert!((upsample(gray, 420.) - 0.3).abs() < 1e-12);
+        assert!((upsample(gray, 700.) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn wavelengths_are_spread_evenly() {
+        let wavelengths = SampledWavelengths::from_hero(700.);
+        assert_eq!(wavelengths.hero(), 700.);
+        assert_eq!(wavelengths.lambda(), &[700., 400., 500., 600.]);
+    }
+
+    #[test]
+    fn upsampled_colors_round_trip() {
+        let colors = [
+            Color::new(1., 1., 1.),
+            Color::new(0.8, 0.2, 0.1),
+            Color::new(0.1, 0.6, 0.3),
+            Color::new(0.2, 0.3, 0.9),
+        ];
+        for color in colors {
+            let n = 1000;
+            let mut rgb = Color::new(0., 0., 0.);
+            for k in 0..n {
+                let hero = LAMBDA_MIN + (k as f64 + 0.5) / n as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+                let throughput = PathThroughput::new(Some(hero));
+                rgb += throughput.radiance(color) / n as f64;
+            }
+            assert!((rgb - color).length() < 1e-3, "{:?} {:?}", rgb, color);
+        }
+    }
+
+    #[test]
+    fn rgb_throughput_multiplies() {
+        let mut throughput = PathThroughput::new(None);
+        throughput.attenuate(Color::new(0.5, 0.25, 1.));
+        throughput.terminate_secondary();
+        assert_eq!(
+            throughput.radiance(Color::new(2., 2., 2.)),
+            Color::new(1., 0.5, 2.)
+        );
+    }
+
+    #[test]
+    fn terminating_secondaries_keeps_the_estimate_unbiased() {
+        let color = Color::new(0.8, 0.5, 0.2);
+        let n = 1000;
+        let mut rgb = Color::new(0., 0., 0.);
+        for k in 0..n {
+            let hero = LAMBDA_MIN + (k as f64 + 0.5) / n as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            let mut throughput = PathThroughput::new(Some(hero));
+            throughput.terminate_secondary();
+            rgb += throughput.radiance(color) / n as f64;
+        }
+        assert!((rgb - color).length() < 1e-3, "{:?}", rgb);
+    }
+}
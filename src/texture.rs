@@ -1,9 +1,76 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
 use std::rc::Rc;
 
-use crate::{color::Color, vec3::Vec3};
+use crate::{
+    color::{Color, srgb_to_linear},
+    mipmap::{Filter, MipMap},
+    noise::{Pattern, Perlin},
+    util::PPM,
+    vec3::Vec3,
+};
+
+/// How the lookup point and texture coordinates change from one pixel to
+/// the next in x and in y, which is the area a filtered lookup averages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Footprint {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl Footprint {
+    /// Footprint of a single point, for unfiltered lookups.
+    pub const ZERO: Footprint = Footprint {
+        dpdx: Vec3(0., 0., 0.),
+        dpdy: Vec3(0., 0., 0.),
+        dudx: 0.,
+        dvdx: 0.,
+        dudy: 0.,
+        dvdy: 0.,
+    };
+}
+
+/// Where a texture is looked up: the surface's texture coordinates, the
+/// point in world and in object space, the surface normal and the
+/// footprint around them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TexCoords {
+    pub u: f64,
+    pub v: f64,
+    pub point: Vec3,          // Point read by solid textures
+    pub object_point: Vec3,   // Point relative to the object hit
+    pub normal: Vec3,         // Unit surface normal, zero if unknown
+    pub footprint: Footprint, // Area to filter over, zero for point lookups
+}
+
+impl TexCoords {
+    /// Coordinates with no object frame or normal.
+    pub fn new(u: f64, v: f64, point: Vec3) -> Self {
+        Self {
+            u,
+            v,
+            point,
+            object_point: point,
+            normal: Vec3(0., 0., 0.),
+            footprint: Footprint::ZERO,
+        }
+    }
+}
 
 pub trait Texture {
     fn value(&self, u: f64, v: f64, point: Vec3) -> Color;
+
+    /// Color at full texture coordinates. Textures that map or combine
+    /// coordinates override this; the rest only need `value`.
+    fn value_at(&self, coords: &TexCoords) -> Color {
+        self.value(coords.u, coords.v, coords.point)
+    }
 }
 
 pub struct SolidColor {
@@ -14,6 +81,11 @@ impl SolidColor {
     pub fn new(albedo: Color) -> Self {
         Self { albedo }
     }
+
+    /// Constant texture for scalar parameters, which read the first channel.
+    pub fn gray(value: f64) -> Self {
+        Self::new(Color::new(value, value, value))
+    }
 }
 
 impl Texture for SolidColor {
@@ -26,6 +98,7 @@ pub struct CheckerTexture {
     inv_scale: f64,
     even: Rc<dyn Texture>,
     odd: Rc<dyn Texture>,
+    box_filter: bool, // Average the checks over the lookup's footprint
 }
 
 impl CheckerTexture {
@@ -34,9 +107,18 @@ impl CheckerTexture {
             inv_scale: 1. / scale,
             even,
             odd,
+            box_filter: false,
         }
     }
 
+    /// Averages the checks over a box around the lookup point as wide as
+    /// its footprint, computed exactly, instead of showing the check the
+    /// point falls in. Distant checks blend into gray rather than alias.
+    pub fn with_box_filter(mut self) -> Self {
+        self.box_filter = true;
+        self
+    }
+
     pub fn from_colors(scale: f64, c1: Color, c2: Color) -> Self {
         Self::new(
             scale,
@@ -48,20 +130,591 @@ impl CheckerTexture {
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
+        self.value_at(&TexCoords::new(u, v, point))
+    }
+
+    fn value_at(&self, coords: &TexCoords) -> Color {
+        if self.box_filter {
+            return self.filtered_value(coords);
+        }
+        let point = coords.point;
         let x_integer = (self.inv_scale * point.x()).floor() as i32;
         let y_integer = (self.inv_scale * point.y()).floor() as i32;
         let z_integer = (self.inv_scale * point.z()).floor() as i32;
 
-        let is_even = if (x_integer + y_integer + z_integer) % 2 == 0 {
-            true
-        } else {
-            false
-        };
+        let is_even = (x_integer + y_integer + z_integer) % 2 == 0;
 
         if is_even {
-            self.even.value(u, v, point)
+            self.even.value_at(coords)
         } else {
-            self.odd.value(u, v, point)
+            self.odd.value_at(coords)
+        }
+    }
+}
+
+impl CheckerTexture {
+    /// Checks averaged over the box spanned by the footprint. The check
+    /// parity is a product of square waves along the axes, so its average
+    /// over the box is the product of their averages, which are slopes of
+    /// their integrals, triangle waves.
+    fn filtered_value(&self, coords: &TexCoords) -> Color {
+        let (p, f) = (coords.point, coords.footprint);
+        let axes = [
+            (p.x(), f.dpdx.x(), f.dpdy.x()),
+            (p.y(), f.dpdx.y(), f.dpdy.y()),
+            (p.z(), f.dpdx.z(), f.dpdy.z()),
+        ];
+        let square_wave = |x: f64| 1. - 2. * (x.floor().rem_euclid(2.));
+        let triangle_wave = |x: f64| 1. - (x.rem_euclid(2.) - 1.).abs();
+        let mean: f64 = axes
+            .into_iter()
+            .map(|(x, dx, dy)| {
+                let x = self.inv_scale * x;
+                let half_width = self.inv_scale * dx.abs().max(dy.abs());
+                if half_width == 0. {
+                    square_wave(x)
+                } else {
+                    (triangle_wave(x + half_width) - triangle_wave(x - half_width))
+                        / (2. * half_width)
+                }
+            })
+            .product();
+
+        let even = 0.5 * (1. + mean);
+        match even {
+            1. => self.even.value_at(coords),
+            0. => self.odd.value_at(coords),
+            _ => even * self.even.value_at(coords) + (1. - even) * self.odd.value_at(coords),
+        }
+    }
+}
+
+/// Image wrapped around the surface by its texture coordinates, with u
+/// running left to right and v bottom to top. Lookups repeat the image
+/// outside [0, 1] and filter it over their footprint, trilinearly unless
+/// set otherwise.
+pub struct ImageTexture {
+    mipmap: MipMap, // Linear values, top row first
+    filter: Filter,
+}
+
+impl ImageTexture {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        Self {
+            mipmap: MipMap::new(width, height, pixels),
+            filter: Filter::Trilinear,
+        }
+    }
+
+    /// Sets how lookups are filtered.
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Loads a color image from a PPM file, decoding its sRGB values.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(path, srgb_to_linear)
+    }
+
+    /// Loads a PPM file of linear data, such as a normal map.
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(path, |x| x)
+    }
+
+    fn read<P: AsRef<Path>>(path: P, decode: fn(f64) -> f64) -> io::Result<Self> {
+        let ppm = PPM::read(BufReader::new(File::open(path)?))?;
+        let pixels = ppm
+            .pixels()
+            .iter()
+            .map(|c| Color::new(decode(c.x()), decode(c.y()), decode(c.z())))
+            .collect();
+        Ok(Self::new(ppm.width(), ppm.height(), pixels))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
+        self.value_at(&TexCoords::new(u, v, point))
+    }
+
+    fn value_at(&self, coords: &TexCoords) -> Color {
+        // Images run top to bottom, against v.
+        let f = coords.footprint;
+        self.mipmap.lookup(
+            self.filter,
+            coords.u,
+            1. - coords.v,
+            (f.dudx, -f.dvdx),
+            (f.dudy, -f.dvdy),
+        )
+    }
+}
+
+/// Gradient map from scalars to colors: stops of a value and a color,
+/// blended linearly between them and held beyond the first and last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    /// Ramp through the given stops, in increasing order of value.
+    pub fn new(stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs a stop");
+        assert!(
+            stops.windows(2).all(|pair| pair[0].0 <= pair[1].0),
+            "color ramp stops must be in increasing order"
+        );
+        Self { stops }
+    }
+
+    /// Black at 0 to white at 1.
+    pub fn gray() -> Self {
+        Self::new(vec![
+            (0., Color::new(0., 0., 0.)),
+            (1., Color::new(1., 1., 1.)),
+        ])
+    }
+
+    pub fn at(&self, value: f64) -> Color {
+        let next = self.stops.partition_point(|&(stop, _)| stop <= value);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let ((v0, c0), (v1, c1)) = (self.stops[next - 1], self.stops[next]);
+        let t = (value - v0) / (v1 - v0);
+        (1. - t) * c0 + t * c1
+    }
+}
+
+/// Solid texture of a noise pattern over space, colored by a ramp. Plain
+/// Perlin noise in gray by default, for solid textures and bump maps.
+pub struct NoiseTexture {
+    pattern: Rc<dyn Pattern>,
+    scale: f64, // Lattice cells per unit distance
+    ramp: ColorRamp,
+}
+
+impl NoiseTexture {
+    pub fn new(noise: Perlin, scale: f64) -> Self {
+        Self::from_pattern(Rc::new(noise), scale)
+    }
+
+    pub fn from_pattern(pattern: Rc<dyn Pattern>, scale: f64) -> Self {
+        Self {
+            pattern,
+            scale,
+            ramp: ColorRamp::gray(),
+        }
+    }
+
+    /// Colors the pattern's values with the ramp.
+    pub fn with_ramp(mut self, ramp: ColorRamp) -> Self {
+        self.ramp = ramp;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _: f64, _: f64, point: Vec3) -> Color {
+        self.ramp.at(self.pattern.value(self.scale * point))
+    }
+}
+
+/// Way of deriving the coordinates a texture is looked up at from those
+/// of a hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    /// Scales the texture coordinates, rotates them counterclockwise by
+    /// an angle in radians, then offsets them.
+    Uv {
+        scale: (f64, f64),
+        rotation: f64,
+        offset: (f64, f64),
+    },
+    /// Projects the point onto the plane spanned by s and t: u and v are
+    /// its coordinates along them.
+    Planar { s: Vec3, t: Vec3 },
+    /// Wraps u around the y axis, starting from -x, with v the height.
+    Cylindrical,
+    /// Wraps u around the y axis as the longitude and v from the bottom
+    /// pole to the top, like the texture coordinates of a sphere.
+    Spherical,
+    /// Looks the texture up in the planes facing the x, y and z axes and
+    /// blends the three by how much the normal faces each. Higher
+    /// sharpness narrows the blends.
+    Triplanar { sharpness: f64 },
+    /// Looks solid textures up at the point in object space, so that they
+    /// move with the object.
+    Object,
+}
+
+impl Mapping {
+    /// Texture coordinates scaled by (su, sv).
+    pub fn scale(su: f64, sv: f64) -> Self {
+        Mapping::Uv {
+            scale: (su, sv),
+            rotation: 0.,
+            offset: (0., 0.),
+        }
+    }
+
+    /// Mapped coordinates, with the footprint carried over by mapping the
+    /// coordinates it reaches.
+    fn map(&self, coords: &TexCoords) -> TexCoords {
+        let mapped = self.map_point(coords);
+        let f = coords.footprint;
+        if f == Footprint::ZERO {
+            return mapped;
+        }
+        let change = |dp: Vec3, du: f64, dv: f64| {
+            let next = self.map_point(&TexCoords {
+                u: coords.u + du,
+                v: coords.v + dv,
+                point: coords.point + dp,
+                object_point: coords.object_point + dp,
+                ..*coords
+            });
+            let mut du = next.u - mapped.u;
+            // Take the short way around the seam of wrapping mappings.
+            if matches!(self, Mapping::Cylindrical | Mapping::Spherical) {
+                du -= du.round();
+            }
+            (next.point - mapped.point, du, next.v - mapped.v)
+        };
+        let (dpdx, dudx, dvdx) = change(f.dpdx, f.dudx, f.dvdx);
+        let (dpdy, dudy, dvdy) = change(f.dpdy, f.dudy, f.dvdy);
+        TexCoords {
+            footprint: Footprint {
+                dpdx,
+                dpdy,
+                dudx,
+                dvdx,
+                dudy,
+                dvdy,
+            },
+            ..mapped
+        }
+    }
+
+    fn map_point(&self, coords: &TexCoords) -> TexCoords {
+        let p = coords.point;
+        let around_y = || ((-p.z()).atan2(p.x()) + PI) / (2. * PI);
+        let (u, v) = match *self {
+            Mapping::Uv {
+                scale,
+                rotation,
+                offset,
+            } => {
+                let (u, v) = (coords.u * scale.0, coords.v * scale.1);
+                let (sin, cos) = rotation.sin_cos();
+                (cos * u - sin * v + offset.0, sin * u + cos * v + offset.1)
+            }
+            Mapping::Planar { s, t } => (p.dot(&s), p.dot(&t)),
+            Mapping::Cylindrical => (around_y(), p.y()),
+            Mapping::Spherical => {
+                let cos_theta = -p.y() / p.length().max(1e-12);
+                (around_y(), cos_theta.clamp(-1., 1.).acos() / PI)
+            }
+            Mapping::Object => {
+                return TexCoords {
+                    point: coords.object_point,
+                    ..*coords
+                };
+            }
+            Mapping::Triplanar { .. } => (coords.u, coords.v),
+        };
+        TexCoords { u, v, ..*coords }
+    }
+}
+
+/// Texture looked up at coordinates transformed by a mapping. Mapped
+/// textures nest, the outer mapping applying first: a spherical mapping
+/// inside an object mapping wraps around the object wherever it moves.
+pub struct MappedTexture {
+    texture: Rc<dyn Texture>,
+    mapping: Mapping,
+}
+
+impl MappedTexture {
+    pub fn new(texture: Rc<dyn Texture>, mapping: Mapping) -> Self {
+        Self { texture, mapping }
+    }
+}
+
+impl Texture for MappedTexture {
+    fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
+        self.value_at(&TexCoords::new(u, v, point))
+    }
+
+    fn value_at(&self, coords: &TexCoords) -> Color {
+        let Mapping::Triplanar { sharpness } = self.mapping else {
+            return self.texture.value_at(&self.mapping.map(coords));
+        };
+
+        let n = coords.normal;
+        let weights = [n.x(), n.y(), n.z()].map(|w| w.abs().powf(sharpness));
+        let total: f64 = weights.iter().sum();
+        if total == 0. {
+            return self.texture.value_at(coords);
+        }
+        // Axes of u and v in each plane.
+        let planes = [(2, 1), (0, 2), (0, 1)];
+        let axis = |p: Vec3, i: usize| [p.x(), p.y(), p.z()][i];
+        let (p, f) = (coords.point, coords.footprint);
+        let mut color = Color::new(0., 0., 0.);
+        for (weight, (i, j)) in weights.into_iter().zip(planes) {
+            if weight > 0. {
+                let plane = TexCoords {
+                    u: axis(p, i),
+                    v: axis(p, j),
+                    footprint: Footprint {
+                        dudx: axis(f.dpdx, i),
+                        dvdx: axis(f.dpdx, j),
+                        dudy: axis(f.dpdy, i),
+                        dvdy: axis(f.dpdy, j),
+                        ..f
+                    },
+                    ..*coords
+                };
+                color += weight / total * self.texture.value_at(&plane);
+            }
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_texture_works() {
+        let black = Color::new(0., 0., 0.);
+        let white = Color::new(1., 1., 1.);
+        let image = ImageTexture::new(2, 1, vec![black, white]);
+        let p = Vec3(0., 0., 0.);
+
+        assert_eq!(image.value(0.25, 0.5, p), black);
+        assert_eq!(image.value(0.75, 0.5, p), white);
+        assert_eq!(image.value(0.5, 0.5, p), 0.5 * white);
+        // Lookups wrap around at the edges.
+        assert_eq!(image.value(0., 0.5, p), 0.5 * white);
+        assert_eq!(image.value(1.75, -3.5, p), white);
+    }
+
+    #[test]
+    fn image_texture_loads_ppm() {
+        let path = std::env::temp_dir().join("tracerust_image_texture_loads_ppm.ppm");
+        std::fs::write(&path, "P3\n1 2\n255\n255 0 0\n188 188 188\n").unwrap();
+        let color = ImageTexture::load(&path).unwrap();
+        let data = ImageTexture::load_linear(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The top row is at v = 1.
+        let p = Vec3(0., 0., 0.);
+        assert_eq!(color.value(0.5, 0.75, p), Color::new(1., 0., 0.));
+        assert!((color.value(0.5, 0.25, p).y() - 0.5).abs() < 0.01);
+        assert_eq!(data.value(0.5, 0.25, p).y(), 188. / 255.);
+    }
+
+    #[test]
+    fn color_ramp_works() {
+        let red = Color::new(1., 0., 0.);
+        let blue = Color::new(0., 0., 1.);
+        let white = Color::new(1., 1., 1.);
+        let ramp = ColorRamp::new(vec![(0.2, red), (0.6, blue), (0.6, white), (1., white)]);
+        assert_eq!(ramp.at(-1.), red);
+        assert_eq!(ramp.at(0.2), red);
+        assert!((ramp.at(0.3) - Color::new(0.75, 0., 0.25)).length() < 1e-12);
+        // Stops at the same value make a hard edge.
+        assert_eq!(ramp.at(0.6), white);
+        assert!((ramp.at(0.6 - 1e-9) - blue).length() < 1e-6);
+        assert_eq!(ramp.at(3.), white);
+
+        // Noise textures color their pattern's values.
+        struct Height;
+        impl Pattern for Height {
+            fn value(&self, p: Vec3) -> f64 {
+                p.y()
+            }
+        }
+        let expected = ramp.at(0.3);
+        let texture = NoiseTexture::from_pattern(Rc::new(Height), 0.1).with_ramp(ramp);
+        assert!((texture.value(0., 0., Vec3(5., 3., 0.)) - expected).length() < 1e-12);
+    }
+
+    /// Texture showing its coordinates: u, v and the point's x.
+    struct Coordinates;
+
+    impl Texture for Coordinates {
+        fn value(&self, u: f64, v: f64, point: Vec3) -> Color {
+            Color::new(u, v, point.x())
+        }
+    }
+
+    fn mapped(mapping: Mapping, coords: &TexCoords) -> Color {
+        MappedTexture::new(Rc::new(Coordinates), mapping).value_at(coords)
+    }
+
+    #[test]
+    fn mappings_work() {
+        let coords = TexCoords {
+            u: 0.5,
+            v: 0.25,
+            point: Vec3(0., 2., -1.),
+            object_point: Vec3(3., 0., 0.),
+            normal: Vec3(0., 1., 0.),
+            footprint: Footprint::ZERO,
+        };
+        let uv = Mapping::Uv {
+            scale: (2., 4.),
+            rotation: PI / 2.,
+            offset: (1., 0.),
+        };
+        assert!((mapped(uv, &coords) - Color::new(0., 1., 0.)).length() < 1e-12);
+        assert_eq!(
+            mapped(Mapping::scale(2., 2.), &coords),
+            Color::new(1., 0.5, 0.)
+        );
+
+        let planar = Mapping::Planar {
+            s: Vec3(0., 0., -1.),
+            t: Vec3(0., 0.5, 0.),
+        };
+        assert_eq!(mapped(planar, &coords), Color::new(1., 1., 0.));
+        // -z is three quarter turns around y from -x, and the point is
+        // level with the center.
+        assert_eq!(
+            mapped(Mapping::Cylindrical, &coords),
+            Color::new(0.75, 2., 0.)
+        );
+        let equator = TexCoords::new(0., 0., Vec3(0., 0., -1.));
+        assert_eq!(
+            mapped(Mapping::Spherical, &equator),
+            Color::new(0.75, 0.5, 0.)
+        );
+        assert_eq!(mapped(Mapping::Object, &coords), Color::new(0.5, 0.25, 3.));
+
+        // Nested mappings apply from the outside in.
+        let inner = Rc::new(MappedTexture::new(Rc::new(Coordinates), Mapping::Spherical));
+        let outer = MappedTexture::new(inner, Mapping::Object);
+        assert_eq!(outer.value_at(&coords), Color::new(0.5, 0.5, 3.));
+    }
+
+    /// Texture showing the footprint: the changes in u and v in x, and in
+    /// u in y.
+    struct Changes;
+
+    impl Texture for Changes {
+        fn value(&self, _: f64, _: f64, _: Vec3) -> Color {
+            Color::new(0., 0., 0.)
+        }
+
+        fn value_at(&self, coords: &TexCoords) -> Color {
+            let f = coords.footprint;
+            Color::new(f.dudx, f.dvdx, f.dudy)
         }
     }
+
+    #[test]
+    fn mappings_carry_footprints() {
+        let mut coords = TexCoords::new(0.5, 0.5, Vec3(-1., 0., 0.));
+        coords.normal = Vec3(0., 1., 0.);
+        coords.footprint = Footprint {
+            dpdx: Vec3(0.1, 0., 0.01),
+            dpdy: Vec3(0., 0., -0.01),
+            dudx: 0.1,
+            dvdx: 0.,
+            dudy: 0.,
+            dvdy: 0.2,
+        };
+        let footprint = |mapping| MappedTexture::new(Rc::new(Changes), mapping).value_at(&coords);
+
+        let uv = Mapping::Uv {
+            scale: (2., 4.),
+            rotation: PI / 2.,
+            offset: (1., 0.),
+        };
+        assert!((footprint(uv) - Color::new(0., 0.2, -0.8)).length() < 1e-12);
+        let planar = Mapping::Planar {
+            s: Vec3(1., 0., 0.),
+            t: Vec3(0., 0., 2.),
+        };
+        assert!((footprint(planar) - Color::new(0.1, 0.02, 0.)).length() < 1e-12);
+        let triplanar = Mapping::Triplanar { sharpness: 1. };
+        assert!((footprint(triplanar) - Color::new(0.1, 0.01, 0.)).length() < 1e-12);
+
+        // Steps across the seam at -x take the short way around.
+        let spherical = footprint(Mapping::Spherical);
+        assert!((spherical.x() - 0.01f64.atan2(0.9) / (2. * PI)).abs() < 1e-12);
+        assert!((spherical.z() + 0.01f64.atan() / (2. * PI)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn checker_box_filter_works() {
+        let white = Color::new(1., 1., 1.);
+        let black = Color::new(0., 0., 0.);
+        let checker = CheckerTexture::from_colors(0.5, white, black);
+        let filtered = CheckerTexture::from_colors(0.5, white, black).with_box_filter();
+
+        let mut coords = TexCoords::new(0., 0., Vec3(0.2, 0.1, -0.7));
+        assert_eq!(filtered.value_at(&coords), checker.value_at(&coords));
+
+        // The filtered value is the mean over the box, here found by the
+        // midpoint rule.
+        coords.footprint.dpdx = Vec3(0.4, 0., 0.);
+        coords.footprint.dpdy = Vec3(0., 0.1, -0.3);
+        let steps = 200;
+        let mut mean = black;
+        for i in 0..steps {
+            for j in 0..steps {
+                for k in 0..steps / 4 {
+                    let offset = |k: usize, n: usize, half_width: f64| {
+                        half_width * (2. * (k as f64 + 0.5) / n as f64 - 1.)
+                    };
+                    let point = coords.point
+                        + Vec3(
+                            offset(i, steps, 0.4),
+                            offset(k, steps / 4, 0.1),
+                            offset(j, steps, 0.3),
+                        );
+                    mean += checker.value(0., 0., point);
+                }
+            }
+        }
+        mean /= (steps * steps * (steps / 4)) as f64;
+        assert!((filtered.value_at(&coords) - mean).length() < 1e-3);
+
+        // Wide footprints average to an even blend.
+        coords.footprint.dpdx = Vec3(100., 0., 0.);
+        let gray = filtered.value_at(&coords);
+        assert!((gray - 0.5 * white).length() < 0.01);
+    }
+
+    #[test]
+    fn triplanar_mapping_blends_by_normal() {
+        let mut coords = TexCoords::new(0., 0., Vec3(1., 2., 3.));
+        let triplanar = Mapping::Triplanar { sharpness: 1. };
+        // Facing y, the texture lies in the xz plane.
+        coords.normal = Vec3(0., -1., 0.);
+        assert_eq!(mapped(triplanar, &coords), Color::new(1., 3., 1.));
+
+        // Halfway between the x and z planes it is an even blend.
+        coords.normal = Vec3(1., 0., 1.).unit();
+        let blend = mapped(triplanar, &coords);
+        assert!((blend - Color::new(2., 2., 1.)).length() < 1e-12);
+        let sharp = mapped(Mapping::Triplanar { sharpness: 8. }, &coords);
+        assert!((sharp - blend).length() < 1e-12);
+        // Sharper blends favor the plane the normal faces most.
+        coords.normal = Vec3(0.6, 0., 0.8);
+        let target = Color::new(1., 2., 1.);
+        let soft = mapped(triplanar, &coords);
+        let sharp = mapped(Mapping::Triplanar { sharpness: 8. }, &coords);
+        assert!((sharp - target).length() < 0.25 * (soft - target).length());
+    }
 }
@@ -1,6 +1,6 @@
-use crate::color::Color;
+use crate::color::{Color, DisplayTransform, Quantizer};
 use rand;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 
 pub fn random_f64(min: f64, max: f64) -> f64 {
     min + (max - min) * rand::random::<f64>()
@@ -94,6 +94,8 @@ pub struct PPM {
     width: u32,
     height: u32,
     pixels: Vec<Color>,
+    display: DisplayTransform,
+    quantizer: Quantizer,
 }
 
 impl PPM {
@@ -102,28 +104,267 @@ impl PPM {
             width,
             height,
             pixels: Vec::new(),
+            display: DisplayTransform::default(),
+            quantizer: Quantizer::default(),
         }
     }
 
+    /// Sets the transform from the pushed linear colors to display values.
+    pub fn with_display(mut self, display: DisplayTransform) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Sets how display values are rounded and dithered to 8 bits.
+    pub fn with_quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+
     pub fn push(&mut self, color: Color) {
         self.pixels.push(color);
     }
 
+    /// Reads a binary (P6) or plain (P3) PPM image with pixel values
+    /// scaled to [0, 1], as they are stored, without decoding them.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // Header tokens, skipping whitespace and comments.
+        let mut pos = 0;
+        let mut token = || {
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => {
+                        while data.get(pos).is_some_and(|&c| c != b'\n') {
+                            pos += 1;
+                        }
+                    }
+                    Some(c) if c.is_ascii_whitespace() => pos += 1,
+                    _ => break,
+                }
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            (start, pos)
+        };
+        let header =
+            |token: (usize, usize)| String::from_utf8_lossy(&data[token.0..token.1]).to_string();
+
+        let magic = header(token());
+        let mut number = |name: &str| {
+            header(token())
+                .parse::<u32>()
+                .map_err(|_| invalid(&format!("invalid PPM {}", name)))
+        };
+        let width = number("width")?;
+        let height = number("height")?;
+        let max = number("maximum value")?;
+        if max == 0 || max > 65535 {
+            return Err(invalid("invalid PPM maximum value"));
+        }
+        let count = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or_else(|| invalid("invalid PPM size"))?;
+
+        let values: Vec<u32> = match magic.as_str() {
+            "P6" => {
+                // A single whitespace character separates header and data.
+                let start = pos + 1;
+                let bytes = if max < 256 { 1 } else { 2 };
+                let body = count
+                    .checked_mul(bytes)
+                    .and_then(|len| data.get(start..start.checked_add(len)?))
+                    .ok_or_else(|| invalid("truncated PPM data"))?;
+                match bytes {
+                    1 => body.iter().map(|&b| b as u32).collect(),
+                    _ => body
+                        .chunks(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                        .collect(),
+                }
+            }
+            "P3" => (0..count)
+                .map(|_| number("sample"))
+                .collect::<io::Result<_>>()?,
+            _ => return Err(invalid("not a PPM image")),
+        };
+
+        let scale = 1. / max as f64;
+        let pixels = values
+            .chunks(3)
+            .map(|c| {
+                Color::new(
+                    c[0] as f64 * scale,
+                    c[1] as f64 * scale,
+                    c[2] as f64 * scale,
+                )
+            })
+            .collect();
+        Ok(PPM {
+            pixels,
+            ..PPM::new(width, height)
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixel values, top row first.
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
     pub fn write_to_buffer<W: Write>(&self, writer: &mut BufWriter<W>) {
         let w = self.width;
         let h = self.height;
         writer
-            .write(format!("P3\n{} {}\n255\n", w, h).as_bytes())
+            .write_all(format!("P3\n{} {}\n255\n", w, h).as_bytes())
             .unwrap();
 
         for j in 0..h {
             for i in 0..w {
-                self.pixels[(j * w + i) as usize].write_io(writer);
+                self.pixels[(j * w + i) as usize].write_io(
+                    writer,
+                    &self.display,
+                    &self.quantizer,
+                    (i, j),
+                );
             }
         }
     }
 }
 
+/// Portable float map: an uncompressed RGB image with 32-bit float
+/// channels, for output that must not be clamped or quantized.
+pub struct PFM {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl PFM {
+    pub fn new(width: u32, height: u32) -> Self {
+        PFM {
+            width,
+            height,
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, color: Color) {
+        self.pixels.push(color);
+    }
+
+    /// Reads a color (PF) or grayscale (Pf) float map, in either byte
+    /// order, with rows stored top first like pushed ones.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // Three header lines of magic, size and scale.
+        let mut lines = data.splitn(4, |&c| c == b'\n');
+        let mut line = || {
+            lines
+                .next()
+                .map(|l| String::from_utf8_lossy(l).trim().to_string())
+                .ok_or_else(|| invalid("truncated PFM header"))
+        };
+        let channels = match line()?.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("not a PFM image")),
+        };
+        let size = line()?;
+        let mut size = size.split_whitespace().map(|n| n.parse::<u32>());
+        let (Some(Ok(width)), Some(Ok(height)), None) = (size.next(), size.next(), size.next())
+        else {
+            return Err(invalid("invalid PFM size"));
+        };
+        let scale: f64 = line()?.parse().map_err(|_| invalid("invalid PFM scale"))?;
+        let body = lines.next().unwrap_or_default();
+
+        // Empty images have no rows to read, and would only reach code
+        // that expects pixels.
+        let pixel_count = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|&n| n > 0)
+            .ok_or_else(|| invalid("invalid PFM size"))?;
+        let body = pixel_count
+            .checked_mul(4 * channels)
+            .and_then(|len| body.get(..len))
+            .ok_or_else(|| invalid("truncated PFM data"))?;
+        let values: Vec<f64> = body
+            .chunks(4)
+            .map(|b| {
+                let bytes = [b[0], b[1], b[2], b[3]];
+                match scale < 0. {
+                    true => f32::from_le_bytes(bytes),
+                    false => f32::from_be_bytes(bytes),
+                }
+            })
+            .map(|v| v as f64)
+            .collect();
+
+        let mut pixels = Vec::with_capacity(pixel_count);
+        for row in values.chunks(channels * width as usize).rev() {
+            pixels.extend(row.chunks(channels).map(|c| match channels {
+                3 => Color::new(c[0], c[1], c[2]),
+                _ => Color::new(c[0], c[0], c[0]),
+            }));
+        }
+        Ok(PFM {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixel values, top row first.
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Writes the image, whose pixels were pushed top row first. PFM
+    /// stores rows bottom to top; a negative scale marks little-endian data.
+    pub fn write_to_buffer<W: Write>(&self, writer: &mut BufWriter<W>) -> io::Result<()> {
+        let w = self.width;
+        let h = self.height;
+        writer.write_all(format!("PF\n{} {}\n-1.0\n", w, h).as_bytes())?;
+
+        for j in (0..h).rev() {
+            for i in 0..w {
+                let c = self.pixels[(j * w + i) as usize];
+                for channel in [c.x(), c.y(), c.z()] {
+                    writer.write_all(&(channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
 pub fn image_test() {
     let image_width = 256;
     let image_height = 256;
@@ -160,21 +401,94 @@ mod tests {
                 Color::new(0.0, 0.0, 0.0),
                 Color::new(0.0, 0.0, 0.0),
             ],
+            display: DisplayTransform::default(),
+            quantizer: Quantizer::default(),
         };
 
         let actual: Vec<u8> = vec![];
         let mut writer = BufWriter::new(actual);
         ppm.write_to_buffer(&mut writer);
 
-        let expected = "P3\n2 2\n255\n0 255 0\n0 181 0\n0 0 0\n0 0 0\n".as_bytes();
+        let expected = "P3\n2 2\n255\n0 255 0\n0 188 0\n0 0 0\n0 0 0\n".as_bytes();
         assert_eq!(writer.buffer(), expected)
     }
 
+    #[test]
+    fn ppm_reader_works() {
+        let plain = PPM::read("P3\n# comment\n2 1\n255\n0 255 0\n51 0 255\n".as_bytes()).unwrap();
+        assert_eq!((plain.width(), plain.height()), (2, 1));
+        assert_eq!(
+            plain.pixels(),
+            &[Color::new(0., 1., 0.), Color::new(0.2, 0., 1.)]
+        );
+
+        let mut binary = b"P6 1 2 65535\n".to_vec();
+        binary.extend_from_slice(&[0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let binary = PPM::read(binary.as_slice()).unwrap();
+        assert_eq!(
+            binary.pixels(),
+            &[Color::new(0., 1., 0.), Color::new(0., 0., 0.)]
+        );
+
+        assert!(PPM::read("P6 2 2 255\n\0\0\0".as_bytes()).is_err());
+        assert!(PPM::read("PF 1 1 -1.0\n".as_bytes()).is_err());
+        // Sizes whose sample count overflows are rejected, not wrapped.
+        assert!(PPM::read("P6 4294967295 4294967295 65535\n".as_bytes()).is_err());
+        assert!(PPM::read("P3 65536 65536 255\n0 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn pfm_writer_works() {
+        let mut pfm = PFM::new(1, 2);
+        pfm.push(Color::new(1.0, 0.0, 0.0));
+        pfm.push(Color::new(0.0, 0.0, 2.0));
+
+        let mut writer = BufWriter::new(Vec::new());
+        pfm.write_to_buffer(&mut writer).unwrap();
+
+        let mut expected = b"PF\n1 2\n-1.0\n".to_vec();
+        for channel in [0.0f32, 0.0, 2.0, 1.0, 0.0, 0.0] {
+            expected.extend_from_slice(&channel.to_le_bytes());
+        }
+        assert_eq!(writer.get_ref(), &expected)
+    }
+
+    #[test]
+    fn pfm_reader_works() {
+        let mut pfm = PFM::new(2, 2);
+        for c in [(1., 2., 3.), (4., 5., 6.), (0.5, 0., -1.), (1e4, 0., 0.)] {
+            pfm.push(Color::new(c.0, c.1, c.2));
+        }
+        let mut writer = BufWriter::new(Vec::new());
+        pfm.write_to_buffer(&mut writer).unwrap();
+        let read = PFM::read(writer.get_ref().as_slice()).unwrap();
+        assert_eq!((read.width(), read.height()), (2, 2));
+        assert_eq!(read.pixels(), pfm.pixels());
+
+        // Big-endian grayscale, bottom row first.
+        let mut gray = b"Pf\n1 2\n1.0\n".to_vec();
+        for value in [0.25f32, 8.] {
+            gray.extend_from_slice(&value.to_be_bytes());
+        }
+        let gray = PFM::read(gray.as_slice()).unwrap();
+        assert_eq!(
+            gray.pixels(),
+            &[Color::new(8., 8., 8.), Color::new(0.25, 0.25, 0.25)]
+        );
+
+        assert!(PFM::read(&b"PF\n2 2\n-1.0\n\0\0\0\0"[..]).is_err());
+        assert!(PFM::read(&b"P6\n1 1\n255\n\0\0\0"[..]).is_err());
+        // Empty and overflowing sizes are rejected.
+        assert!(PFM::read(&b"PF\n0 2\n-1.0\n"[..]).is_err());
+        assert!(PFM::read(&b"Pf\n2 0\n-1.0\n"[..]).is_err());
+        assert!(PFM::read(&b"PF\n4294967295 4294967295\n-1.0\n"[..]).is_err());
+    }
+
     #[test]
     fn random_f64_works() {
         for _ in 0..100 {
             let random = random_f64(2., 5.);
-            assert!(2. <= random && random < 5.)
+            assert!((2.0..5.0).contains(&random))
         }
     }
 
@@ -59,9 +59,9 @@ impl Vec3 {
 
     pub fn random_unit_vector() -> Vec3 {
         loop {
-            let p = Self::random();
+            let p = Self::random_mm(-1., 1.);
             let lensq = p.length_squared();
-            if 1e-160 <= lensq && lensq <= 1. {
+            if (1e-160..=1.).contains(&lensq) {
                 return p / lensq.sqrt();
             }
         }
@@ -86,7 +86,7 @@ impl Vec3 {
 
     pub fn near_zero(&self) -> bool {
         const EPS: f64 = 1e-8;
-        return self.0.abs() < EPS && self.1.abs() < EPS && self.2.abs() < EPS;
+        self.0.abs() < EPS && self.1.abs() < EPS && self.2.abs() < EPS
     }
 
     // return the reflection of self across normal
@@ -98,7 +98,7 @@ impl Vec3 {
     // whose normal is n and etai_over_etat is the ratio of the refactive
     // indices. self and n should be a unit vectors.
     pub fn refract(&self, n: &Self, etai_over_etat: f64) -> Self {
-        let cos_theta = -self.dot(&n);
+        let cos_theta = -self.dot(n);
         let r_out_perp = etai_over_etat * (*self + cos_theta * *n);
         let r_out_parallel = -(1. - r_out_perp.length_squared()).abs().sqrt() * *n;
         r_out_perp + r_out_parallel
@@ -191,6 +191,50 @@ impl Neg for Vec3 {
     }
 }
 
+/// Orthonormal basis with w along a given direction, for moving
+/// directions into and out of a local shading frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Builds a basis around the unit vector n (Duff et al. 2017).
+    pub fn new(n: Vec3) -> Self {
+        let sign = 1_f64.copysign(n.2);
+        let a = -1. / (sign + n.2);
+        let b = n.0 * n.1 * a;
+        Self {
+            u: Vec3(1. + sign * n.0 * n.0 * a, sign * b, -sign * n.0),
+            v: Vec3(b, sign + n.1 * n.1 * a, -n.1),
+            w: n,
+        }
+    }
+
+    /// Builds a basis around the unit vector n with u along the part of
+    /// tangent perpendicular to n, or any basis if tangent is parallel to n.
+    pub fn with_tangent(n: Vec3, tangent: Vec3) -> Self {
+        let u = tangent - tangent.dot(&n) * n;
+        if u.length_squared() <= 1e-12 * tangent.length_squared() {
+            return Self::new(n);
+        }
+        let u = u.unit();
+        Self { u, v: n.cross(&u), w: n }
+    }
+
+    /// Coordinates of a world space vector in the basis.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+
+    /// World space vector with coordinates a in the basis.
+    pub fn to_world(&self, a: Vec3) -> Vec3 {
+        a.0 * self.u + a.1 * self.v + a.2 * self.w
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -313,4 +357,24 @@ mod tests {
         let w = Vec3(1.0 / 2.0_f64.sqrt(), -1.0 / 2.0_f64.sqrt(), 0.0);
         assert_eq!(v.unit(), w)
     }
+
+    #[test]
+    fn onb_works() {
+        for n in [Vec3(0., 0., 1.), Vec3(0., 0., -1.), Vec3(1., 2., -3.).unit()] {
+            let onb = Onb::new(n);
+            assert!((onb.u.length() - 1.).abs() < 1e-12);
+            assert!((onb.v.length() - 1.).abs() < 1e-12);
+            assert!(onb.u.dot(&onb.v).abs() < 1e-12);
+            assert!(onb.u.dot(&n).abs() < 1e-12);
+            assert!((onb.u.cross(&onb.v) - n).length() < 1e-12);
+
+            let a = Vec3(0.3, -0.2, 0.9);
+            assert!((onb.to_world(onb.to_local(a)) - a).length() < 1e-12);
+
+            let onb = Onb::with_tangent(n, Vec3(1., 1., 1.));
+            assert!((onb.u.cross(&onb.v) - n).length() < 1e-12);
+            assert!(onb.u.dot(&Vec3(1., 1., 1.)) > 0.);
+            assert!(onb.u.dot(&n).abs() < 1e-12);
+        }
+    }
 }